@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use iso_currency::Currency;
+use rust_decimal::Decimal;
+
+use betty::market::Market;
+use betty::price::{CurrencyAmount, Frame, Points, Price, Resolution};
+use betty::trade::Direction;
+
+// Trading operations of a broker account
+//
+// Methods taking `&mut self` change the session, the rest need an open session.
+// A dealing request which reached the broker returns a `Confirmation`, even if the deal
+// was rejected - errors are reserved for failed requests.
+pub trait Broker {
+    fn login(&mut self) -> Result<Session, BrokerError>;
+    fn logout(&mut self) -> Result<(), BrokerError>;
+
+    fn accounts(&self) -> Result<Vec<AccountSummary>, BrokerError>;
+    fn market(&self, epic: &str) -> Result<MarketDetails, BrokerError>;
+    fn prices(
+        &self,
+        epic: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Frame>, BrokerError>;
+
+    fn positions(&self) -> Result<Vec<Position>, BrokerError>;
+    fn open_position(&self, request: &PositionRequest) -> Result<Confirmation, BrokerError>;
+    fn close_position(&self, position: &Position) -> Result<Confirmation, BrokerError>;
+    fn amend_stop(&self, deal_id: &str, stop: Points) -> Result<Confirmation, BrokerError>;
+
+    fn working_orders(&self) -> Result<Vec<WorkingOrder>, BrokerError>;
+    fn create_working_order(
+        &self,
+        request: &WorkingOrderRequest,
+    ) -> Result<Confirmation, BrokerError>;
+    fn delete_working_order(&self, deal_id: &str) -> Result<Confirmation, BrokerError>;
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Credentials {
+    pub api_key: String,
+    pub identifier: String,
+    pub password: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Session {
+    pub account_id: String, // account the session trades on
+    pub currency: Currency,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AccountSummary {
+    pub id: String,
+    pub name: String,
+    pub preferred: bool,
+    pub balance: CurrencyAmount,
+    pub available: CurrencyAmount, // funds available to open new positions
+    pub profit_loss: CurrencyAmount,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MarketDetails {
+    pub epic: String,
+    pub name: String,
+    pub market: Market,
+    pub price: Price,
+    pub tradeable: bool,
+}
+
+// Market order for a new position with a stop-loss
+#[derive(Debug, PartialEq, Clone)]
+pub struct PositionRequest {
+    pub epic: String,
+    pub direction: Direction,
+    pub size: CurrencyAmount,  // per point
+    pub stop: Points,          // stop-loss level
+    pub guaranteed_stop: bool, // filled exactly at the stop, for a premium
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Position {
+    pub deal_id: String,
+    pub epic: String,
+    pub direction: Direction,
+    pub size: CurrencyAmount,
+    pub level: Points, // entry price
+    pub stop: Option<Points>,
+    pub guaranteed_stop: bool,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WorkingOrderType {
+    Limit, // opens at the level or better
+    Stop,  // opens once the price moves through the level
+}
+
+// Order to open a position when the price reaches a level, with a stop at a distance
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkingOrderRequest {
+    pub epic: String,
+    pub direction: Direction,
+    pub order_type: WorkingOrderType,
+    pub size: CurrencyAmount,
+    pub level: Points,
+    pub stop_distance: Points,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkingOrder {
+    pub deal_id: String,
+    pub epic: String,
+    pub direction: Direction,
+    pub order_type: WorkingOrderType,
+    pub size: CurrencyAmount,
+    pub level: Points,
+    pub stop_distance: Option<Points>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DealStatus {
+    Accepted,
+    Rejected,
+}
+
+// Outcome of a dealing request
+#[derive(Debug, PartialEq, Clone)]
+pub struct Confirmation {
+    pub deal_reference: String,
+    pub deal_id: String,
+    pub status: DealStatus,
+    pub reason: String, // broker's reason code, e.g. SUCCESS or MINIMUM_ORDER_SIZE_ERROR
+    pub epic: String,
+    pub direction: Direction,
+    pub size: Option<Decimal>,
+    pub level: Option<Points>, // price the deal was done at
+    pub stop: Option<Points>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BrokerError {
+    NotLoggedIn,
+    Api { status: u16, code: String }, // error response with the broker's error code
+    Transport(String),
+    InvalidResponse(String),
+    UnsupportedResolution(Resolution),
+}
+
+impl Error for BrokerError {}
+
+impl Display for BrokerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BrokerError::NotLoggedIn => write!(f, "Not logged in"),
+            BrokerError::Api { status, code } => write!(f, "Broker error {}: {}", status, code),
+            BrokerError::Transport(e) => write!(f, "Couldn't reach the broker: {}", e),
+            BrokerError::InvalidResponse(e) => write!(f, "Invalid response from the broker: {}", e),
+            BrokerError::UnsupportedResolution(r) => write!(f, "Unsupported resolution {:?}", r),
+        }
+    }
+}
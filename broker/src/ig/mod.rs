@@ -0,0 +1,529 @@
+mod model;
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use betty::price::{Frame, Points, Resolution};
+
+use crate::broker::{
+    AccountSummary, Broker, BrokerError, Confirmation, Credentials, MarketDetails, Position,
+    PositionRequest, Session, WorkingOrder, WorkingOrderRequest,
+};
+use model::*;
+
+pub const LIVE_URL: &str = "https://api.ig.com/gateway/deal";
+pub const DEMO_URL: &str = "https://demo-api.ig.com/gateway/deal";
+
+// Client of the IG REST trading API
+//
+// Logging in opens a session, its tokens are sent with every following request.
+// See https://labs.ig.com/rest-trading-api-reference
+pub struct IgClient {
+    pub base_url: String,
+    credentials: Credentials,
+    agent: ureq::Agent,
+    tokens: Option<Tokens>,
+}
+
+// Session tokens, CST and X-SECURITY-TOKEN
+#[derive(Debug, Clone)]
+struct Tokens {
+    client: String,
+    account: String,
+}
+
+impl IgClient {
+    pub fn new(base_url: &str, credentials: Credentials) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            credentials,
+            agent: ureq::Agent::new(),
+            tokens: None,
+        }
+    }
+
+    pub fn demo(credentials: Credentials) -> Self {
+        Self::new(DEMO_URL, credentials)
+    }
+
+    pub fn live(credentials: Credentials) -> Self {
+        Self::new(LIVE_URL, credentials)
+    }
+
+    fn request(&self, method: &str, path: &str, version: u8) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}{}", self.base_url, path))
+            .set("X-IG-API-KEY", &self.credentials.api_key)
+            .set("Version", &version.to_string())
+            .set("Accept", "application/json; charset=UTF-8")
+            .set("Content-Type", "application/json; charset=UTF-8");
+
+        match &self.tokens {
+            Some(tokens) => request
+                .set("CST", &tokens.client)
+                .set("X-SECURITY-TOKEN", &tokens.account),
+            None => request,
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str, version: u8) -> Result<T, BrokerError> {
+        self.logged_in()?;
+
+        parse(send(self.request("GET", path, version).call())?)
+    }
+
+    fn send<B: Serialize, T: DeserializeOwned>(
+        &self,
+        request: ureq::Request,
+        body: &B,
+    ) -> Result<T, BrokerError> {
+        self.logged_in()?;
+
+        parse(send(request.send_json(body))?)
+    }
+
+    // Dealing requests are only acknowledged with a reference, the outcome needs confirming
+    fn confirm(&self, reference: DealReferenceResponse) -> Result<Confirmation, BrokerError> {
+        self.get::<ConfirmResponse>(&format!("/confirms/{}", reference.deal_reference), 1)?
+            .confirmation()
+    }
+
+    fn logged_in(&self) -> Result<(), BrokerError> {
+        match self.tokens {
+            Some(_) => Ok(()),
+            None => Err(BrokerError::NotLoggedIn),
+        }
+    }
+}
+
+impl Broker for IgClient {
+    fn login(&mut self) -> Result<Session, BrokerError> {
+        let body = LoginRequest {
+            identifier: &self.credentials.identifier,
+            password: &self.credentials.password,
+        };
+        let response = send(self.request("POST", "/session", 2).send_json(&body))?;
+
+        let header = |name| {
+            response
+                .header(name)
+                .map(|v| v.to_string())
+                .ok_or_else(|| BrokerError::InvalidResponse(format!("missing {} header", name)))
+        };
+        let tokens = Tokens {
+            client: header("CST")?,
+            account: header("X-SECURITY-TOKEN")?,
+        };
+
+        let session = parse::<SessionResponse>(response)?.session()?;
+        self.tokens = Some(tokens);
+
+        Ok(session)
+    }
+
+    fn logout(&mut self) -> Result<(), BrokerError> {
+        self.logged_in()?;
+
+        send(self.request("DELETE", "/session", 1).call())?;
+        self.tokens = None;
+
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<AccountSummary>, BrokerError> {
+        self.get::<AccountsResponse>("/accounts", 1)?.accounts()
+    }
+
+    fn market(&self, epic: &str) -> Result<MarketDetails, BrokerError> {
+        self.get::<MarketResponse>(&format!("/markets/{}", epic), 3)?
+            .details()
+    }
+
+    fn prices(
+        &self,
+        epic: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Frame>, BrokerError> {
+        let path = format!(
+            "/prices/{}?resolution={}&from={}&to={}&pageSize=0",
+            epic,
+            resolution_code(resolution)?,
+            time_code(from),
+            time_code(to)
+        );
+
+        self.get::<PricesResponse>(&path, 3)?.frames(resolution)
+    }
+
+    fn positions(&self) -> Result<Vec<Position>, BrokerError> {
+        self.get::<PositionsResponse>("/positions", 2)?.positions()
+    }
+
+    fn open_position(&self, request: &PositionRequest) -> Result<Confirmation, BrokerError> {
+        let reference = self.send(
+            self.request("POST", "/positions/otc", 2),
+            &OpenPositionRequest::from(request),
+        )?;
+
+        self.confirm(reference)
+    }
+
+    // IG doesn't accept a body with DELETE, the method is overridden with a header instead
+    fn close_position(&self, position: &Position) -> Result<Confirmation, BrokerError> {
+        let reference = self.send(
+            self.request("POST", "/positions/otc", 1)
+                .set("_method", "DELETE"),
+            &ClosePositionRequest::from(position),
+        )?;
+
+        self.confirm(reference)
+    }
+
+    fn amend_stop(&self, deal_id: &str, stop: Points) -> Result<Confirmation, BrokerError> {
+        let reference = self.send(
+            self.request("PUT", &format!("/positions/otc/{}", deal_id), 2),
+            &AmendPositionRequest {
+                stop_level: stop,
+                limit_level: None,
+                trailing_stop: false,
+            },
+        )?;
+
+        self.confirm(reference)
+    }
+
+    fn working_orders(&self) -> Result<Vec<WorkingOrder>, BrokerError> {
+        self.get::<WorkingOrdersResponse>("/workingorders", 2)?
+            .working_orders()
+    }
+
+    fn create_working_order(
+        &self,
+        request: &WorkingOrderRequest,
+    ) -> Result<Confirmation, BrokerError> {
+        let reference = self.send(
+            self.request("POST", "/workingorders/otc", 2),
+            &CreateWorkingOrderRequest::from(request),
+        )?;
+
+        self.confirm(reference)
+    }
+
+    fn delete_working_order(&self, deal_id: &str) -> Result<Confirmation, BrokerError> {
+        self.logged_in()?;
+
+        let response = send(
+            self.request("DELETE", &format!("/workingorders/otc/{}", deal_id), 2)
+                .call(),
+        )?;
+
+        self.confirm(parse(response)?)
+    }
+}
+
+// Error responses carry IG's error code
+fn send(result: Result<ureq::Response, ureq::Error>) -> Result<ureq::Response, BrokerError> {
+    match result {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => {
+            let code = response
+                .into_json::<ErrorResponse>()
+                .map_or_else(|_| "unknown".to_string(), |e| e.error_code);
+
+            Err(BrokerError::Api { status, code })
+        }
+        Err(e) => Err(BrokerError::Transport(e.to_string())),
+    }
+}
+
+fn parse<T: DeserializeOwned>(response: ureq::Response) -> Result<T, BrokerError> {
+    response
+        .into_json()
+        .map_err(|e| BrokerError::InvalidResponse(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use betty::price::{CurrencyAmount, Price};
+    use betty::trade::Direction;
+
+    use crate::broker::{DealStatus, WorkingOrderType};
+    use crate::mock::{MockMarket, MockServer, MockState};
+
+    #[test]
+    fn logs_in_and_out() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+
+        assert_eq!(client.accounts(), Err(BrokerError::NotLoggedIn));
+
+        let session = client.login().unwrap();
+        assert_eq!(
+            session,
+            Session {
+                account_id: "Z1234".to_string(),
+                currency: Currency::GBP
+            }
+        );
+
+        client.logout().unwrap();
+        assert_eq!(client.accounts(), Err(BrokerError::NotLoggedIn));
+    }
+
+    #[test]
+    fn reports_api_errors() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(
+            &server.url,
+            Credentials {
+                password: "wrong".to_string(),
+                ..credentials()
+            },
+        );
+
+        assert_eq!(
+            client.login(),
+            Err(BrokerError::Api {
+                status: 401,
+                code: "error.security.invalid-details".to_string()
+            })
+        );
+
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        assert_eq!(
+            client.market("CS.D.NOPE.TODAY.IP"),
+            Err(BrokerError::Api {
+                status: 404,
+                code: "error.service.marketdata.instrument.epic.unavailable".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn reports_transport_errors() {
+        let mut client = IgClient::new("http://127.0.0.1:1", credentials());
+
+        assert!(matches!(client.login(), Err(BrokerError::Transport(_))));
+    }
+
+    #[test]
+    fn lists_accounts() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        assert_eq!(
+            client.accounts().unwrap(),
+            vec![AccountSummary {
+                id: "Z1234".to_string(),
+                name: "Spread bet".to_string(),
+                preferred: true,
+                balance: gbp(dec!(20000)),
+                available: gbp(dec!(20000)),
+                profit_loss: gbp(dec!(0)),
+            }]
+        );
+    }
+
+    #[test]
+    fn gets_market_details() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        let actual = client.market(EPIC).unwrap();
+
+        assert_eq!(actual, market().details);
+    }
+
+    #[test]
+    fn gets_historical_prices() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let actual = client
+            .prices(EPIC, Resolution::Day, start, start + Duration::days(3))
+            .unwrap();
+
+        assert_eq!(actual, market().prices[..4].to_vec());
+        assert_eq!(
+            client.prices(EPIC, Resolution::Minute(7), start, start),
+            Err(BrokerError::UnsupportedResolution(Resolution::Minute(7)))
+        );
+    }
+
+    #[test]
+    fn opens_amends_and_closes_a_position() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        let opened = client
+            .open_position(&PositionRequest {
+                epic: EPIC.to_string(),
+                direction: Direction::Buy,
+                size: gbp(dec!(2)),
+                stop: dec!(13150),
+                guaranteed_stop: false,
+            })
+            .unwrap();
+
+        assert_eq!(opened.status, DealStatus::Accepted);
+        assert_eq!(opened.level, Some(dec!(13247)));
+        assert_eq!(opened.stop, Some(dec!(13150)));
+
+        let positions = client.positions().unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].deal_id, opened.deal_id);
+        assert_eq!(positions[0].size, gbp(dec!(2)));
+        assert_eq!(positions[0].stop, Some(dec!(13150)));
+
+        let amended = client.amend_stop(&opened.deal_id, dec!(13200)).unwrap();
+        assert_eq!(amended.status, DealStatus::Accepted);
+        assert_eq!(client.positions().unwrap()[0].stop, Some(dec!(13200)));
+
+        let closed = client.close_position(&positions[0]).unwrap();
+        assert_eq!(closed.status, DealStatus::Accepted);
+        assert_eq!(closed.direction, Direction::Sell);
+        assert_eq!(closed.level, Some(dec!(13245.5)));
+        assert_eq!(client.positions().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn confirms_rejected_deals() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        let too_small = client
+            .open_position(&PositionRequest {
+                epic: EPIC.to_string(),
+                direction: Direction::Buy,
+                size: gbp(dec!(0.1)),
+                stop: dec!(13150),
+                guaranteed_stop: false,
+            })
+            .unwrap();
+        let stop_too_close = client
+            .open_position(&PositionRequest {
+                epic: EPIC.to_string(),
+                direction: Direction::Sell,
+                size: gbp(dec!(1)),
+                stop: dec!(13250),
+                guaranteed_stop: false,
+            })
+            .unwrap();
+
+        assert_eq!(too_small.status, DealStatus::Rejected);
+        assert_eq!(too_small.reason, "MINIMUM_ORDER_SIZE_ERROR");
+        assert_eq!(stop_too_close.status, DealStatus::Rejected);
+        assert_eq!(stop_too_close.reason, "ATTACHED_ORDER_LEVEL_ERROR");
+        assert_eq!(client.positions().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn creates_and_deletes_working_orders() {
+        let server = MockServer::start(state());
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        let created = client
+            .create_working_order(&WorkingOrderRequest {
+                epic: EPIC.to_string(),
+                direction: Direction::Sell,
+                order_type: WorkingOrderType::Stop,
+                size: gbp(dec!(1)),
+                level: dec!(13000),
+                stop_distance: dec!(60),
+            })
+            .unwrap();
+        assert_eq!(created.status, DealStatus::Accepted);
+
+        let orders = client.working_orders().unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].deal_id, created.deal_id);
+        assert_eq!(orders[0].order_type, WorkingOrderType::Stop);
+        assert_eq!(orders[0].level, dec!(13000));
+        assert_eq!(orders[0].stop_distance, Some(dec!(60)));
+
+        let deleted = client.delete_working_order(&created.deal_id).unwrap();
+        assert_eq!(deleted.status, DealStatus::Accepted);
+        assert_eq!(client.working_orders().unwrap(), vec![]);
+    }
+
+    // Fixtures
+
+    const EPIC: &str = "IX.D.DAX.DAILY.IP";
+
+    fn gbp(amount: rust_decimal::Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            api_key: "key".to_string(),
+            identifier: "betty".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    fn state() -> MockState {
+        let mut state = MockState::new(credentials(), "Z1234", gbp(dec!(20000)));
+        state.markets.insert(EPIC.to_string(), market());
+
+        state
+    }
+
+    fn market() -> MockMarket {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let prices = (1..=5)
+            .map(|i| {
+                let level = dec!(13200) + rust_decimal::Decimal::from(i * 10);
+
+                Frame {
+                    open: Price::new_mid(level, dec!(2)),
+                    close: Price::new_mid(level + dec!(5), dec!(2)),
+                    low: Price::new_mid(level - dec!(20), dec!(2)),
+                    high: Price::new_mid(level + dec!(20), dec!(2)),
+                    close_time: start + Duration::days(i),
+                }
+            })
+            .collect();
+
+        MockMarket {
+            details: MarketDetails {
+                epic: EPIC.to_string(),
+                name: "Germany 40".to_string(),
+                market: betty::market::Market {
+                    code: EPIC.to_string(),
+                    margin_factor: dec!(0.05),
+                    min_deal_size: gbp(dec!(0.5)),
+                    min_stop_distance: dec!(12),
+                    guaranteed_stop: None,
+                    funding: None,
+                },
+                price: Price {
+                    ask: dec!(13247),
+                    bid: dec!(13245.5),
+                },
+                tradeable: true,
+            },
+            resolution: Resolution::Day,
+            prices,
+        }
+    }
+}
@@ -0,0 +1,659 @@
+// Request and response bodies of the IG REST API
+use chrono::{DateTime, TimeZone, Utc};
+use iso_currency::Currency;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize, Serializer};
+
+use betty::market::Market;
+use betty::price::{CurrencyAmount, Frame, Price, Resolution};
+use betty::trade::Direction;
+
+use crate::broker::{
+    AccountSummary, BrokerError, Confirmation, DealStatus, MarketDetails, Position,
+    PositionRequest, Session, WorkingOrder, WorkingOrderRequest, WorkingOrderType,
+};
+
+// Spread betting positions don't expire
+const EXPIRY: &str = "DFB";
+
+// IG sends times without a time zone
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+// Requests
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginRequest<'a> {
+    pub identifier: &'a str,
+    pub password: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPositionRequest<'a> {
+    epic: &'a str,
+    expiry: &'a str,
+    direction: &'a str,
+    #[serde(serialize_with = "number")]
+    size: Decimal,
+    order_type: &'a str,
+    currency_code: &'a str,
+    force_open: bool,
+    guaranteed_stop: bool,
+    #[serde(serialize_with = "number")]
+    stop_level: Decimal,
+}
+
+impl<'a> From<&'a PositionRequest> for OpenPositionRequest<'a> {
+    fn from(request: &'a PositionRequest) -> Self {
+        Self {
+            epic: &request.epic,
+            expiry: EXPIRY,
+            direction: direction_code(request.direction),
+            size: request.size.amount,
+            order_type: "MARKET",
+            currency_code: request.size.currency.code(),
+            force_open: true,
+            guaranteed_stop: request.guaranteed_stop,
+            stop_level: request.stop,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClosePositionRequest<'a> {
+    deal_id: &'a str,
+    direction: &'a str,
+    #[serde(serialize_with = "number")]
+    size: Decimal,
+    order_type: &'a str,
+}
+
+impl<'a> From<&'a Position> for ClosePositionRequest<'a> {
+    fn from(position: &'a Position) -> Self {
+        let direction = match position.direction {
+            Direction::Buy => Direction::Sell,
+            Direction::Sell => Direction::Buy,
+        };
+
+        Self {
+            deal_id: &position.deal_id,
+            direction: direction_code(direction),
+            size: position.size.amount,
+            order_type: "MARKET",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AmendPositionRequest {
+    #[serde(serialize_with = "number")]
+    pub stop_level: Decimal,
+    pub limit_level: Option<Decimal>,
+    pub trailing_stop: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWorkingOrderRequest<'a> {
+    epic: &'a str,
+    expiry: &'a str,
+    direction: &'a str,
+    #[serde(serialize_with = "number")]
+    size: Decimal,
+    #[serde(serialize_with = "number")]
+    level: Decimal,
+    #[serde(rename = "type")]
+    order_type: &'a str,
+    currency_code: &'a str,
+    time_in_force: &'a str,
+    force_open: bool,
+    guaranteed_stop: bool,
+    #[serde(serialize_with = "number")]
+    stop_distance: Decimal,
+}
+
+impl<'a> From<&'a WorkingOrderRequest> for CreateWorkingOrderRequest<'a> {
+    fn from(request: &'a WorkingOrderRequest) -> Self {
+        Self {
+            epic: &request.epic,
+            expiry: EXPIRY,
+            direction: direction_code(request.direction),
+            size: request.size.amount,
+            level: request.level,
+            order_type: order_type_code(request.order_type),
+            currency_code: request.size.currency.code(),
+            time_in_force: "GOOD_TILL_CANCELLED",
+            force_open: true,
+            guaranteed_stop: false,
+            stop_distance: request.stop_distance,
+        }
+    }
+}
+
+// IG expects numbers, not the strings decimals serialise to
+fn number<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(value.to_f64().unwrap_or_default())
+}
+
+// Responses
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub error_code: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    current_account_id: String,
+    currency_iso_code: String,
+}
+
+impl SessionResponse {
+    pub fn session(self) -> Result<Session, BrokerError> {
+        Ok(Session {
+            account_id: self.current_account_id,
+            currency: currency(&self.currency_iso_code)?,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountsResponse {
+    accounts: Vec<AccountDto>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AccountDto {
+    account_id: String,
+    account_name: String,
+    preferred: bool,
+    currency: String,
+    balance: BalanceDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BalanceDto {
+    balance: Decimal,
+    available: Decimal,
+    profit_loss: Decimal,
+}
+
+impl AccountsResponse {
+    pub fn accounts(self) -> Result<Vec<AccountSummary>, BrokerError> {
+        self.accounts
+            .into_iter()
+            .map(|a| {
+                let currency = currency(&a.currency)?;
+
+                Ok(AccountSummary {
+                    id: a.account_id,
+                    name: a.account_name,
+                    preferred: a.preferred,
+                    balance: CurrencyAmount::new(a.balance.balance, currency),
+                    available: CurrencyAmount::new(a.balance.available, currency),
+                    profit_loss: CurrencyAmount::new(a.balance.profit_loss, currency),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketResponse {
+    instrument: InstrumentDto,
+    dealing_rules: DealingRulesDto,
+    snapshot: SnapshotDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct InstrumentDto {
+    epic: String,
+    name: String,
+    currencies: Vec<CurrencyDto>,
+    margin_factor: Decimal,
+    margin_factor_unit: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CurrencyDto {
+    code: String,
+    is_default: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DealingRulesDto {
+    min_deal_size: UnitValueDto,
+    min_normal_stop_or_limit_distance: UnitValueDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UnitValueDto {
+    unit: String,
+    value: Decimal,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotDto {
+    market_status: String,
+    bid: Option<Decimal>,
+    offer: Option<Decimal>,
+}
+
+impl MarketResponse {
+    pub fn details(self) -> Result<MarketDetails, BrokerError> {
+        let instrument = self.instrument;
+        let rules = self.dealing_rules;
+
+        let code = instrument
+            .currencies
+            .iter()
+            .find(|c| c.is_default)
+            .or_else(|| instrument.currencies.first())
+            .map(|c| c.code.as_str())
+            .ok_or_else(|| invalid("market without a currency"))?;
+        let currency = currency(code)?;
+
+        let margin_factor = match instrument.margin_factor_unit.as_str() {
+            "PERCENTAGE" => instrument.margin_factor / dec!(100),
+            _ => instrument.margin_factor,
+        };
+
+        let stop_distance = &rules.min_normal_stop_or_limit_distance;
+        if stop_distance.unit != "POINTS" {
+            return Err(invalid(&format!(
+                "minimum stop distance in {}",
+                stop_distance.unit
+            )));
+        }
+
+        let price = match (self.snapshot.bid, self.snapshot.offer) {
+            (Some(bid), Some(ask)) => Price { ask, bid },
+            _ => return Err(invalid("market without a price")),
+        };
+
+        Ok(MarketDetails {
+            epic: instrument.epic.clone(),
+            name: instrument.name,
+            market: Market {
+                code: instrument.epic,
+                margin_factor,
+                min_deal_size: CurrencyAmount::new(rules.min_deal_size.value, currency),
+                min_stop_distance: stop_distance.value,
+                guaranteed_stop: None,
+                funding: None,
+            },
+            price,
+            tradeable: self.snapshot.market_status == "TRADEABLE",
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionsResponse {
+    positions: Vec<PositionItemDto>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PositionItemDto {
+    position: PositionDto,
+    market: PositionMarketDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PositionDto {
+    deal_id: String,
+    direction: String,
+    size: Decimal,
+    level: Decimal,
+    stop_level: Option<Decimal>,
+    #[serde(default)]
+    controlled_risk: bool, // guaranteed stop
+    #[serde(rename = "createdDateUTC")]
+    created_date_utc: String,
+    currency: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PositionMarketDto {
+    epic: String,
+}
+
+impl PositionsResponse {
+    pub fn positions(self) -> Result<Vec<Position>, BrokerError> {
+        self.positions
+            .into_iter()
+            .map(|item| {
+                let position = item.position;
+
+                Ok(Position {
+                    deal_id: position.deal_id,
+                    epic: item.market.epic,
+                    direction: direction(&position.direction)?,
+                    size: CurrencyAmount::new(position.size, currency(&position.currency)?),
+                    level: position.level,
+                    stop: position.stop_level,
+                    guaranteed_stop: position.controlled_risk,
+                    created: time(&position.created_date_utc)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DealReferenceResponse {
+    pub deal_reference: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmResponse {
+    deal_reference: String,
+    deal_id: String,
+    deal_status: String,
+    reason: String,
+    epic: String,
+    direction: String,
+    size: Option<Decimal>,
+    level: Option<Decimal>,
+    stop_level: Option<Decimal>,
+    date: String,
+}
+
+impl ConfirmResponse {
+    pub fn confirmation(self) -> Result<Confirmation, BrokerError> {
+        let status = match self.deal_status.as_str() {
+            "ACCEPTED" => DealStatus::Accepted,
+            "REJECTED" => DealStatus::Rejected,
+            other => return Err(invalid(&format!("deal status {}", other))),
+        };
+
+        Ok(Confirmation {
+            deal_reference: self.deal_reference,
+            deal_id: self.deal_id,
+            status,
+            reason: self.reason,
+            epic: self.epic,
+            direction: direction(&self.direction)?,
+            size: self.size,
+            level: self.level,
+            stop: self.stop_level,
+            time: time(&self.date)?,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkingOrdersResponse {
+    working_orders: Vec<WorkingOrderItemDto>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WorkingOrderItemDto {
+    working_order_data: WorkingOrderDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WorkingOrderDto {
+    deal_id: String,
+    epic: String,
+    direction: String,
+    order_type: String,
+    order_size: Decimal,
+    order_level: Decimal,
+    stop_distance: Option<Decimal>,
+    #[serde(rename = "createdDateUTC")]
+    created_date_utc: String,
+    currency_code: String,
+}
+
+impl WorkingOrdersResponse {
+    pub fn working_orders(self) -> Result<Vec<WorkingOrder>, BrokerError> {
+        self.working_orders
+            .into_iter()
+            .map(|item| {
+                let order = item.working_order_data;
+                let order_type = match order.order_type.as_str() {
+                    "LIMIT" => WorkingOrderType::Limit,
+                    "STOP" => WorkingOrderType::Stop,
+                    other => return Err(invalid(&format!("order type {}", other))),
+                };
+
+                Ok(WorkingOrder {
+                    deal_id: order.deal_id,
+                    epic: order.epic,
+                    direction: direction(&order.direction)?,
+                    order_type,
+                    size: CurrencyAmount::new(order.order_size, currency(&order.currency_code)?),
+                    level: order.order_level,
+                    stop_distance: order.stop_distance,
+                    created: time(&order.created_date_utc)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PricesResponse {
+    prices: Vec<PriceDto>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PriceDto {
+    #[serde(rename = "snapshotTimeUTC")]
+    snapshot_time_utc: String,
+    open_price: BidAskDto,
+    close_price: BidAskDto,
+    high_price: BidAskDto,
+    low_price: BidAskDto,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BidAskDto {
+    bid: Decimal,
+    ask: Decimal,
+}
+
+impl BidAskDto {
+    fn price(&self) -> Price {
+        Price {
+            ask: self.ask,
+            bid: self.bid,
+        }
+    }
+}
+
+impl PricesResponse {
+    // IG timestamps a price at the start of its period, frames at the end
+    pub fn frames(self, resolution: Resolution) -> Result<Vec<Frame>, BrokerError> {
+        self.prices
+            .into_iter()
+            .map(|p| {
+                Ok(Frame {
+                    open: p.open_price.price(),
+                    close: p.close_price.price(),
+                    low: p.low_price.price(),
+                    high: p.high_price.price(),
+                    close_time: time(&p.snapshot_time_utc)? + resolution,
+                })
+            })
+            .collect()
+    }
+}
+
+// Codes
+
+pub fn direction_code(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Buy => "BUY",
+        Direction::Sell => "SELL",
+    }
+}
+
+pub fn order_type_code(order_type: WorkingOrderType) -> &'static str {
+    match order_type {
+        WorkingOrderType::Limit => "LIMIT",
+        WorkingOrderType::Stop => "STOP",
+    }
+}
+
+pub fn resolution_code(resolution: Resolution) -> Result<&'static str, BrokerError> {
+    match resolution {
+        Resolution::Second => Ok("SECOND"),
+        Resolution::Minute(1) => Ok("MINUTE"),
+        Resolution::Minute(2) => Ok("MINUTE_2"),
+        Resolution::Minute(3) => Ok("MINUTE_3"),
+        Resolution::Minute(5) => Ok("MINUTE_5"),
+        Resolution::Minute(10) => Ok("MINUTE_10"),
+        Resolution::Minute(15) => Ok("MINUTE_15"),
+        Resolution::Minute(30) => Ok("MINUTE_30"),
+        Resolution::Hour(1) => Ok("HOUR"),
+        Resolution::Hour(2) => Ok("HOUR_2"),
+        Resolution::Hour(3) => Ok("HOUR_3"),
+        Resolution::Hour(4) => Ok("HOUR_4"),
+        Resolution::Day => Ok("DAY"),
+        Resolution::Week => Ok("WEEK"),
+        Resolution::Month => Ok("MONTH"),
+        _ => Err(BrokerError::UnsupportedResolution(resolution)),
+    }
+}
+
+pub fn time_code(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+fn direction(code: &str) -> Result<Direction, BrokerError> {
+    match code {
+        "BUY" => Ok(Direction::Buy),
+        "SELL" => Ok(Direction::Sell),
+        other => Err(invalid(&format!("direction {}", other))),
+    }
+}
+
+fn currency(code: &str) -> Result<Currency, BrokerError> {
+    Currency::from_code(code).ok_or_else(|| invalid(&format!("currency {}", code)))
+}
+
+fn time(value: &str) -> Result<DateTime<Utc>, BrokerError> {
+    Utc.datetime_from_str(value, DATE_FORMAT)
+        .map_err(|e| invalid(&format!("time {}: {}", value, e)))
+}
+
+fn invalid(message: &str) -> BrokerError {
+    BrokerError::InvalidResponse(message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn converts_market_details() {
+        let response: MarketResponse = serde_json::from_str(
+            r#"{
+                "instrument": {
+                    "epic": "IX.D.DAX.DAILY.IP",
+                    "name": "Germany 40",
+                    "currencies": [{"code": "EUR", "isDefault": false}, {"code": "GBP", "isDefault": true}],
+                    "marginFactor": 5,
+                    "marginFactorUnit": "PERCENTAGE"
+                },
+                "dealingRules": {
+                    "minDealSize": {"unit": "POINTS", "value": 0.5},
+                    "minNormalStopOrLimitDistance": {"unit": "POINTS", "value": 12}
+                },
+                "snapshot": {"marketStatus": "TRADEABLE", "bid": 13245.6, "offer": 13247.0}
+            }"#,
+        )
+        .unwrap();
+
+        let actual = response.details().unwrap();
+
+        assert_eq!(actual.market.margin_factor, dec!(0.05));
+        assert_eq!(
+            actual.market.min_deal_size,
+            CurrencyAmount::new(dec!(0.5), Currency::GBP)
+        );
+        assert_eq!(actual.market.min_stop_distance, dec!(12));
+        assert_eq!(
+            actual.price,
+            Price {
+                ask: dec!(13247.0),
+                bid: dec!(13245.6)
+            }
+        );
+        assert!(actual.tradeable);
+    }
+
+    #[test]
+    fn timestamps_frames_at_the_end_of_the_period() {
+        let response: PricesResponse = serde_json::from_str(
+            r#"{"prices": [{
+                "snapshotTimeUTC": "2021-01-04T00:00:00",
+                "openPrice": {"bid": 13700, "ask": 13702},
+                "closePrice": {"bid": 13725.5, "ask": 13727.5},
+                "highPrice": {"bid": 13800, "ask": 13802},
+                "lowPrice": {"bid": 13600, "ask": 13602}
+            }]}"#,
+        )
+        .unwrap();
+
+        let actual = response.frames(Resolution::Hour(4)).unwrap();
+
+        assert_eq!(actual[0].close_time, Utc.ymd(2021, 1, 4).and_hms(4, 0, 0));
+        assert_eq!(actual[0].close.bid, dec!(13725.5));
+    }
+
+    #[test]
+    fn sends_decimals_as_numbers() {
+        let request = AmendPositionRequest {
+            stop_level: dec!(13150.5),
+            limit_level: None,
+            trailing_stop: false,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"stopLevel":13150.5,"limitLevel":null,"trailingStop":false}"#
+        );
+    }
+
+    #[test]
+    fn only_supports_ig_resolutions() {
+        assert_eq!(resolution_code(Resolution::Minute(15)), Ok("MINUTE_15"));
+        assert_eq!(
+            resolution_code(Resolution::Minute(7)),
+            Err(BrokerError::UnsupportedResolution(Resolution::Minute(7)))
+        );
+    }
+}
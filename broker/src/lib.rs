@@ -0,0 +1,7 @@
+mod broker;
+pub mod ig;
+pub mod live;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+pub use crate::broker::*;
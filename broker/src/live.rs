@@ -0,0 +1,324 @@
+use betty::broker::{OpenPosition, OrderBroker, OrderError};
+use betty::price::CurrencyAmount;
+use betty::trade::{Amendment, Entry, Exit, StopType};
+
+use crate::broker::{Broker, BrokerError, Confirmation, DealStatus, Position, PositionRequest};
+
+// Places the orders of an account trading one market through a broker API,
+// so it can be traded live by a `betty::live::LiveRunner`
+//
+// Positions are identified by the broker's deal ids.
+pub struct LiveBroker<B: Broker> {
+    pub client: B,
+    pub epic: String,
+}
+
+impl<B: Broker> LiveBroker<B> {
+    pub fn new(client: B, epic: &str) -> Self {
+        Self {
+            client,
+            epic: epic.to_string(),
+        }
+    }
+
+    fn position(&self, deal_id: &str) -> Result<Option<Position>, OrderError> {
+        Ok(self
+            .client
+            .positions()?
+            .into_iter()
+            .find(|p| p.deal_id == deal_id))
+    }
+
+    fn close_position(&self, exit: &Exit) -> Result<Exit, OrderError> {
+        let position = self.position(&exit.position_id)?.ok_or_else(|| {
+            OrderError::Rejected(format!("No open position {}", exit.position_id))
+        })?;
+        let confirmation = accepted(self.client.close_position(&position)?)?;
+
+        Ok(Exit {
+            price: confirmation.level.unwrap_or(exit.price),
+            time: confirmation.time,
+            ..exit.clone()
+        })
+    }
+}
+
+impl<B: Broker> OrderBroker for LiveBroker<B> {
+    fn open(&mut self, entry: &Entry) -> Result<Entry, OrderError> {
+        let confirmation = accepted(self.client.open_position(&PositionRequest {
+            epic: self.epic.clone(),
+            direction: entry.direction,
+            size: entry.size,
+            stop: entry.stop,
+            guaranteed_stop: matches!(entry.stop_type, StopType::Guaranteed { .. }),
+        })?)?;
+
+        Ok(Entry {
+            position_id: confirmation.deal_id,
+            price: confirmation.level.unwrap_or(entry.price),
+            stop: confirmation.stop.unwrap_or(entry.stop),
+            time: confirmation.time,
+            ..entry.clone()
+        })
+    }
+
+    fn close(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+        self.close_position(exit)
+    }
+
+    // The broker closes the position when the stop is hit, if it hasn't yet
+    // (e.g. the account saw a price the broker didn't), it's closed at the market
+    fn stop(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+        match self.position(&exit.position_id)? {
+            Some(_) => self.close_position(exit),
+            None => Ok(exit.clone()),
+        }
+    }
+
+    fn amend_stop(&mut self, amendment: &Amendment) -> Result<Amendment, OrderError> {
+        let confirmation = accepted(
+            self.client
+                .amend_stop(&amendment.position_id, amendment.stop)?,
+        )?;
+
+        Ok(Amendment {
+            stop: confirmation.stop.unwrap_or(amendment.stop),
+            ..amendment.clone()
+        })
+    }
+
+    fn positions(&mut self) -> Result<Vec<OpenPosition>, OrderError> {
+        Ok(self
+            .client
+            .positions()?
+            .into_iter()
+            .filter(|p| p.epic == self.epic)
+            .map(|p| OpenPosition {
+                position_id: p.deal_id,
+                direction: p.direction,
+                size: p.size,
+                level: p.level,
+                stop: p.stop,
+                time: p.created,
+            })
+            .collect())
+    }
+
+    // Balance of the preferred account
+    fn balance(&mut self) -> Result<CurrencyAmount, OrderError> {
+        self.client
+            .accounts()?
+            .into_iter()
+            .find(|a| a.preferred)
+            .map(|a| a.balance)
+            .ok_or_else(|| OrderError::Failed("No preferred account".to_string()))
+    }
+}
+
+impl From<BrokerError> for OrderError {
+    fn from(error: BrokerError) -> Self {
+        OrderError::Failed(error.to_string())
+    }
+}
+
+fn accepted(confirmation: Confirmation) -> Result<Confirmation, OrderError> {
+    match confirmation.status {
+        DealStatus::Accepted => Ok(confirmation),
+        DealStatus::Rejected => Err(OrderError::Rejected(confirmation.reason)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use iso_currency::Currency;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use betty::market::Market;
+    use betty::price::{CurrencyAmount, Price, Resolution};
+    use betty::trade::Direction;
+
+    use crate::broker::{Credentials, MarketDetails};
+    use crate::ig::IgClient;
+    use crate::mock::{MockMarket, MockServer, MockState};
+
+    #[test]
+    fn opens_positions_at_the_broker() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+
+        let actual = broker.open(&entry(dec!(1))).unwrap();
+
+        let position = server.state().positions[0].clone();
+        assert_eq!(actual.position_id, position.deal_id);
+        assert_eq!(actual.price, dec!(13247)); // filled at the broker's offer
+        assert_eq!(actual.stop, dec!(13150));
+        assert_eq!(actual.size, gbp(dec!(1)));
+        assert!(!position.guaranteed_stop);
+
+        let guaranteed = broker
+            .open(&Entry {
+                stop_type: StopType::Guaranteed { premium: dec!(2) },
+                ..entry(dec!(1))
+            })
+            .unwrap();
+
+        let position = &broker.client.positions().unwrap()[1];
+        assert_eq!(guaranteed.position_id, position.deal_id);
+        assert_eq!(
+            guaranteed.stop_type,
+            StopType::Guaranteed { premium: dec!(2) }
+        );
+        assert!(position.guaranteed_stop);
+    }
+
+    #[test]
+    fn reports_rejected_orders() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+
+        assert_eq!(
+            broker.open(&entry(dec!(0.1))),
+            Err(OrderError::Rejected("MINIMUM_ORDER_SIZE_ERROR".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_failed_orders() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+        broker.client.logout().unwrap();
+
+        assert_eq!(
+            broker.open(&entry(dec!(1))),
+            Err(OrderError::Failed("Not logged in".to_string()))
+        );
+    }
+
+    #[test]
+    fn closes_and_amends_positions() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+        let entry = broker.open(&entry(dec!(1))).unwrap();
+
+        let amended = broker
+            .amend_stop(&entry.amend(dec!(13200), date()))
+            .unwrap();
+        assert_eq!(amended.stop, dec!(13200));
+        assert_eq!(server.state().positions[0].stop, Some(dec!(13200)));
+
+        let exit = broker
+            .close(&entry.exit(Price::new_mid(dec!(13300), dec!(2)), date()))
+            .unwrap();
+        assert_eq!(exit.position_id, entry.position_id);
+        assert_eq!(exit.price, dec!(13245.5)); // filled at the broker's bid
+        assert_eq!(server.state().positions, vec![]);
+    }
+
+    #[test]
+    fn confirms_positions_stopped_out_by_the_broker() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+
+        // Stopped out at the broker
+        let first = broker.open(&entry(dec!(1))).unwrap();
+        server.state().positions.clear();
+        let exit = first.exit(Price::new_mid(dec!(13100), dec!(2)), date());
+        assert_eq!(broker.stop(&exit), Ok(exit));
+
+        // Still open at the broker
+        let second = broker.open(&entry(dec!(1))).unwrap();
+        let exit = second.exit(Price::new_mid(dec!(13100), dec!(2)), date());
+        assert_eq!(broker.stop(&exit).unwrap().price, dec!(13245.5));
+        assert_eq!(server.state().positions, vec![]);
+    }
+
+    #[test]
+    fn reports_positions_in_the_market_and_balance() {
+        let server = MockServer::start(state());
+        let mut broker = LiveBroker::new(client(&server), EPIC);
+        let entry = broker.open(&entry(dec!(1))).unwrap();
+
+        let positions = broker.positions().unwrap();
+
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].position_id, entry.position_id);
+        assert_eq!(positions[0].stop, Some(dec!(13150)));
+        assert_eq!(broker.balance(), Ok(gbp(dec!(20000))));
+
+        broker.epic = "CS.D.GBPUSD.TODAY.IP".to_string();
+        assert_eq!(broker.positions(), Ok(vec![]));
+    }
+
+    // Fixtures
+
+    const EPIC: &str = "IX.D.DAX.DAILY.IP";
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)
+    }
+
+    fn entry(size: Decimal) -> Entry {
+        Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(13247),
+            stop: dec!(13150),
+            stop_type: StopType::Normal,
+            size: gbp(size),
+            time: date(),
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            api_key: "key".to_string(),
+            identifier: "betty".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    fn client(server: &MockServer) -> IgClient {
+        let mut client = IgClient::new(&server.url, credentials());
+        client.login().unwrap();
+
+        client
+    }
+
+    fn state() -> MockState {
+        let mut state = MockState::new(credentials(), "Z1234", gbp(dec!(20000)));
+        state.markets.insert(
+            EPIC.to_string(),
+            MockMarket {
+                details: MarketDetails {
+                    epic: EPIC.to_string(),
+                    name: "Germany 40".to_string(),
+                    market: Market {
+                        code: EPIC.to_string(),
+                        margin_factor: dec!(0.05),
+                        min_deal_size: gbp(dec!(0.5)),
+                        min_stop_distance: dec!(12),
+                        guaranteed_stop: None,
+                        funding: None,
+                    },
+                    price: Price {
+                        ask: dec!(13247),
+                        bid: dec!(13245.5),
+                    },
+                    tradeable: true,
+                },
+                resolution: Resolution::Day,
+                prices: vec![],
+            },
+        );
+
+        state
+    }
+}
@@ -0,0 +1,715 @@
+// Local HTTP server imitating the IG REST API, for testing without a network or an account
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use betty::price::{CurrencyAmount, Frame, Points, Price, Resolution};
+use betty::trade::Direction;
+
+use crate::broker::{
+    Confirmation, Credentials, DealStatus, MarketDetails, Position, WorkingOrder, WorkingOrderType,
+};
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+// A market and its price history at one resolution
+#[derive(Debug, PartialEq, Clone)]
+pub struct MockMarket {
+    pub details: MarketDetails,
+    pub resolution: Resolution,
+    pub prices: Vec<Frame>,
+}
+
+// Everything the mock broker knows, tests can change it while the server runs,
+// e.g. to close a position as if it was stopped out
+#[derive(Debug, Clone)]
+pub struct MockState {
+    pub credentials: Credentials,
+    pub account_id: String,
+    pub balance: CurrencyAmount,
+    pub markets: HashMap<String, MockMarket>,
+    pub positions: Vec<Position>,
+    pub working_orders: Vec<WorkingOrder>,
+    pub confirms: HashMap<String, Confirmation>,
+    tokens: Option<(String, String)>,
+    deals: usize,
+}
+
+impl MockState {
+    pub fn new(credentials: Credentials, account_id: &str, balance: CurrencyAmount) -> Self {
+        Self {
+            credentials,
+            account_id: account_id.to_string(),
+            balance,
+            markets: HashMap::new(),
+            positions: vec![],
+            working_orders: vec![],
+            confirms: HashMap::new(),
+            tokens: None,
+            deals: 0,
+        }
+    }
+}
+
+// Serves the mock API on a random local port until dropped
+pub struct MockServer {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start(state: MockState) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").expect("Couldn't start mock server"));
+        let url = format!(
+            "http://{}",
+            server
+                .server_addr()
+                .to_ip()
+                .expect("Mock server isn't on IP")
+        );
+        let state = Arc::new(Mutex::new(state));
+
+        let thread = {
+            let server = server.clone();
+            let state = state.clone();
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let mut state = match state.lock() {
+                        Ok(state) => state,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+
+                    respond(&mut state, request);
+                }
+            })
+        };
+
+        Self {
+            url,
+            state,
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Status, extra headers and body of a response
+type Reply = (u16, Vec<(&'static str, String)>, Value);
+
+fn respond(state: &mut MockState, mut request: Request) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let body: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+
+    let header = |name| header(&request, name);
+
+    let method = match (request.method(), header("_method")) {
+        (Method::Post, Some(overridden)) => overridden,
+        (method, _) => method.as_str().to_string(),
+    };
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let (status, headers, value) =
+        if header("X-IG-API-KEY") != Some(state.credentials.api_key.clone()) {
+            error(403, "error.security.invalid-api-key")
+        } else if method == "POST" && segments == ["session"] {
+            login(state, &body)
+        } else if state.tokens.clone().map(|(c, s)| (Some(c), Some(s)))
+            != Some((header("CST"), header("X-SECURITY-TOKEN")))
+        {
+            error(401, "error.security.client-token-invalid")
+        } else {
+            route(state, &method, &segments, query, &body)
+        };
+
+    let mut response = Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(header_from(
+            "Content-Type",
+            "application/json; charset=UTF-8",
+        ));
+    for (name, value) in headers {
+        response.add_header(header_from(name, &value));
+    }
+
+    let _ = request.respond(response);
+}
+
+fn route(
+    state: &mut MockState,
+    method: &str,
+    segments: &[&str],
+    query: &str,
+    body: &Value,
+) -> Reply {
+    match (method, segments) {
+        ("DELETE", ["session"]) => {
+            state.tokens = None;
+            (204, vec![], Value::Null)
+        }
+        ("GET", ["accounts"]) => ok(accounts(state)),
+        ("GET", ["markets", epic]) => match state.markets.get(*epic) {
+            Some(market) => ok(market_details(&market.details)),
+            None => unknown_epic(),
+        },
+        ("GET", ["prices", epic]) => prices(state, epic, query),
+        ("GET", ["positions"]) => ok(json!({
+            "positions": state.positions.iter().map(|p| position(state, p)).collect::<Vec<_>>()
+        })),
+        ("POST", ["positions", "otc"]) => open_position(state, body),
+        ("DELETE", ["positions", "otc"]) => close_position(state, body),
+        ("PUT", ["positions", "otc", deal_id]) => amend_position(state, deal_id, body),
+        ("GET", ["workingorders"]) => ok(json!({
+            "workingOrders": state.working_orders.iter().map(working_order).collect::<Vec<_>>()
+        })),
+        ("POST", ["workingorders", "otc"]) => create_working_order(state, body),
+        ("DELETE", ["workingorders", "otc", deal_id]) => delete_working_order(state, deal_id),
+        ("GET", ["confirms", reference]) => match state.confirms.get(*reference) {
+            Some(confirmation) => ok(confirm(confirmation)),
+            None => error(404, "error.confirms.deal-not-found"),
+        },
+        _ => error(404, "error.public-api.endpoint-not-found"),
+    }
+}
+
+// Endpoints
+
+fn login(state: &mut MockState, body: &Value) -> Reply {
+    if body["identifier"] != state.credentials.identifier.as_str()
+        || body["password"] != state.credentials.password.as_str()
+    {
+        return error(401, "error.security.invalid-details");
+    }
+
+    state.deals += 1;
+    let tokens = (
+        format!("cst-{}", state.deals),
+        format!("security-{}", state.deals),
+    );
+    state.tokens = Some(tokens.clone());
+
+    (
+        200,
+        vec![("CST", tokens.0), ("X-SECURITY-TOKEN", tokens.1)],
+        json!({
+            "accountType": "SPREADBET",
+            "currencyIsoCode": state.balance.currency.code(),
+            "currentAccountId": state.account_id,
+            "accounts": accounts(state)["accounts"],
+        }),
+    )
+}
+
+fn accounts(state: &MockState) -> Value {
+    let profit_loss: Decimal = state.positions.iter().map(|p| open_profit(state, p)).sum();
+
+    json!({
+        "accounts": [{
+            "accountId": state.account_id,
+            "accountName": "Spread bet",
+            "accountType": "SPREADBET",
+            "preferred": true,
+            "currency": state.balance.currency.code(),
+            "balance": {
+                "balance": number(state.balance.amount),
+                "deposit": 0,
+                "profitLoss": number(profit_loss),
+                "available": number(state.balance.amount + profit_loss),
+            },
+        }]
+    })
+}
+
+fn market_details(details: &MarketDetails) -> Value {
+    let market = &details.market;
+
+    json!({
+        "instrument": {
+            "epic": details.epic,
+            "name": details.name,
+            "currencies": [{"code": market.min_deal_size.currency.code(), "isDefault": true}],
+            "marginFactor": number(market.margin_factor * Decimal::from(100)),
+            "marginFactorUnit": "PERCENTAGE",
+        },
+        "dealingRules": {
+            "minDealSize": {"unit": "POINTS", "value": number(market.min_deal_size.amount)},
+            "minNormalStopOrLimitDistance": {"unit": "POINTS", "value": number(market.min_stop_distance)},
+        },
+        "snapshot": {
+            "marketStatus": if details.tradeable { "TRADEABLE" } else { "CLOSED" },
+            "bid": number(details.price.bid),
+            "offer": number(details.price.ask),
+        },
+    })
+}
+
+// Frames are timestamped at the start of their period, like IG does
+fn prices(state: &MockState, epic: &str, query: &str) -> Reply {
+    let market = match state.markets.get(epic) {
+        Some(market) => market,
+        None => return unknown_epic(),
+    };
+
+    let parameters: HashMap<&str, &str> =
+        query.split('&').filter_map(|p| p.split_once('=')).collect();
+    let time = |name| {
+        parameters
+            .get(name)
+            .and_then(|t| Utc.datetime_from_str(t, DATE_FORMAT).ok())
+    };
+
+    if parameters.get("resolution").cloned() != Some(resolution_code(market.resolution).as_str()) {
+        return error(400, "error.unsupported.resolution");
+    }
+    let (from, to) = match (time("from"), time("to")) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return error(400, "error.malformed.date"),
+    };
+
+    let period = market.resolution.nominal_duration();
+    let prices: Vec<Value> = market
+        .prices
+        .iter()
+        .filter(|f| f.close_time - period >= from && f.close_time - period <= to)
+        .map(|f| {
+            json!({
+                "snapshotTimeUTC": format_time(f.close_time - period),
+                "openPrice": bid_ask(f.open),
+                "closePrice": bid_ask(f.close),
+                "highPrice": bid_ask(f.high),
+                "lowPrice": bid_ask(f.low),
+                "lastTradedVolume": 0,
+            })
+        })
+        .collect();
+
+    ok(json!({ "prices": prices }))
+}
+
+fn open_position(state: &mut MockState, body: &Value) -> Reply {
+    let (epic, direction, size, stop) = match (
+        body["epic"].as_str(),
+        parse_direction(&body["direction"]),
+        decimal(&body["size"]),
+        decimal(&body["stopLevel"]),
+    ) {
+        (Some(epic), Some(direction), Some(size), Some(stop)) => (epic, direction, size, stop),
+        _ => return error(400, "validation.null-not-allowed.request"),
+    };
+    let details = match state.markets.get(epic) {
+        Some(market) => market.details.clone(),
+        None => return unknown_epic(),
+    };
+
+    let guaranteed_stop = body["guaranteedStop"].as_bool().unwrap_or(false);
+
+    let level = match direction {
+        Direction::Buy => details.price.ask,
+        Direction::Sell => details.price.bid,
+    };
+    let min_stop_distance = match details.market.guaranteed_stop {
+        Some(guaranteed) if guaranteed_stop => guaranteed.min_distance,
+        _ => details.market.min_stop_distance,
+    };
+    let reason = if !details.tradeable {
+        "MARKET_CLOSED_WITH_EDITS"
+    } else if size < details.market.min_deal_size.amount {
+        "MINIMUM_ORDER_SIZE_ERROR"
+    } else if (level - stop).abs() < min_stop_distance
+        || (direction == Direction::Buy && stop >= level)
+        || (direction == Direction::Sell && stop <= level)
+    {
+        "ATTACHED_ORDER_LEVEL_ERROR"
+    } else {
+        "SUCCESS"
+    };
+
+    let deal_id = next_deal(state);
+    if reason == "SUCCESS" {
+        state.positions.push(Position {
+            deal_id: deal_id.clone(),
+            epic: epic.to_string(),
+            direction,
+            size: CurrencyAmount::new(size, details.market.min_deal_size.currency),
+            level,
+            stop: Some(stop),
+            guaranteed_stop,
+            created: Utc::now(),
+        });
+    }
+
+    confirmed(
+        state,
+        deal_id,
+        reason,
+        epic,
+        direction,
+        Some(size),
+        Some(level),
+        Some(stop),
+    )
+}
+
+fn close_position(state: &mut MockState, body: &Value) -> Reply {
+    let index = match body["dealId"]
+        .as_str()
+        .and_then(|id| state.positions.iter().position(|p| p.deal_id == id))
+    {
+        Some(index) => index,
+        None => return error(404, "error.service.otc.position.not-found"),
+    };
+
+    let position = &state.positions[index];
+    let price = state.markets[&position.epic].details.price;
+    let (direction, level) = match position.direction {
+        Direction::Buy => (Direction::Sell, price.bid),
+        Direction::Sell => (Direction::Buy, price.ask),
+    };
+
+    let profit = CurrencyAmount::new(profit(position, level), position.size.currency);
+    state.balance = match state.balance + profit {
+        Ok(balance) => balance,
+        Err(_) => return error(400, "error.service.otc.currency.mismatch"),
+    };
+    let position = state.positions.remove(index);
+
+    confirmed(
+        state,
+        position.deal_id,
+        "SUCCESS",
+        &position.epic,
+        direction,
+        Some(position.size.amount),
+        Some(level),
+        None,
+    )
+}
+
+fn amend_position(state: &mut MockState, deal_id: &str, body: &Value) -> Reply {
+    let stop = decimal(&body["stopLevel"]);
+    let position = match state.positions.iter_mut().find(|p| p.deal_id == deal_id) {
+        Some(position) => position,
+        None => return error(404, "error.service.otc.position.not-found"),
+    };
+
+    position.stop = stop;
+    let position = position.clone();
+
+    confirmed(
+        state,
+        position.deal_id,
+        "SUCCESS",
+        &position.epic,
+        position.direction,
+        Some(position.size.amount),
+        Some(position.level),
+        stop,
+    )
+}
+
+fn create_working_order(state: &mut MockState, body: &Value) -> Reply {
+    let order_type = match body["type"].as_str() {
+        Some("LIMIT") => Some(WorkingOrderType::Limit),
+        Some("STOP") => Some(WorkingOrderType::Stop),
+        _ => None,
+    };
+    let (epic, direction, order_type, size, level) = match (
+        body["epic"].as_str(),
+        parse_direction(&body["direction"]),
+        order_type,
+        decimal(&body["size"]),
+        decimal(&body["level"]),
+    ) {
+        (Some(epic), Some(direction), Some(order_type), Some(size), Some(level)) => {
+            (epic, direction, order_type, size, level)
+        }
+        _ => return error(400, "validation.null-not-allowed.request"),
+    };
+    let details = match state.markets.get(epic) {
+        Some(market) => market.details.clone(),
+        None => return unknown_epic(),
+    };
+
+    let reason = if size < details.market.min_deal_size.amount {
+        "MINIMUM_ORDER_SIZE_ERROR"
+    } else {
+        "SUCCESS"
+    };
+
+    let deal_id = next_deal(state);
+    if reason == "SUCCESS" {
+        state.working_orders.push(WorkingOrder {
+            deal_id: deal_id.clone(),
+            epic: epic.to_string(),
+            direction,
+            order_type,
+            size: CurrencyAmount::new(size, details.market.min_deal_size.currency),
+            level,
+            stop_distance: decimal(&body["stopDistance"]),
+            created: Utc::now(),
+        });
+    }
+
+    confirmed(
+        state,
+        deal_id,
+        reason,
+        epic,
+        direction,
+        Some(size),
+        Some(level),
+        None,
+    )
+}
+
+fn delete_working_order(state: &mut MockState, deal_id: &str) -> Reply {
+    let index = match state
+        .working_orders
+        .iter()
+        .position(|o| o.deal_id == deal_id)
+    {
+        Some(index) => index,
+        None => return error(404, "error.service.otc.workingorder.not-found"),
+    };
+
+    let order = state.working_orders.remove(index);
+
+    confirmed(
+        state,
+        order.deal_id,
+        "SUCCESS",
+        &order.epic,
+        order.direction,
+        Some(order.size.amount),
+        Some(order.level),
+        None,
+    )
+}
+
+// Responses
+
+fn ok(value: Value) -> Reply {
+    (200, vec![], value)
+}
+
+fn error(status: u16, code: &str) -> Reply {
+    (status, vec![], json!({ "errorCode": code }))
+}
+
+fn unknown_epic() -> Reply {
+    error(404, "error.service.marketdata.instrument.epic.unavailable")
+}
+
+// Stores the outcome of a deal to confirm, replies with its reference
+#[allow(clippy::too_many_arguments)]
+fn confirmed(
+    state: &mut MockState,
+    deal_id: String,
+    reason: &str,
+    epic: &str,
+    direction: Direction,
+    size: Option<Decimal>,
+    level: Option<Points>,
+    stop: Option<Points>,
+) -> Reply {
+    let reference = format!("REF-{}", next_deal(state));
+    let status = if reason == "SUCCESS" {
+        DealStatus::Accepted
+    } else {
+        DealStatus::Rejected
+    };
+
+    state.confirms.insert(
+        reference.clone(),
+        Confirmation {
+            deal_reference: reference.clone(),
+            deal_id,
+            status,
+            reason: reason.to_string(),
+            epic: epic.to_string(),
+            direction,
+            size,
+            level,
+            stop,
+            time: Utc::now(),
+        },
+    );
+
+    ok(json!({ "dealReference": reference }))
+}
+
+fn confirm(confirmation: &Confirmation) -> Value {
+    json!({
+        "dealReference": confirmation.deal_reference,
+        "dealId": confirmation.deal_id,
+        "dealStatus": match confirmation.status {
+            DealStatus::Accepted => "ACCEPTED",
+            DealStatus::Rejected => "REJECTED",
+        },
+        "reason": confirmation.reason,
+        "epic": confirmation.epic,
+        "expiry": "DFB",
+        "direction": direction_code(confirmation.direction),
+        "size": confirmation.size.map(number),
+        "level": confirmation.level.map(number),
+        "stopLevel": confirmation.stop.map(number),
+        "date": format_time(confirmation.time),
+    })
+}
+
+fn position(state: &MockState, position: &Position) -> Value {
+    let price = state.markets[&position.epic].details.price;
+
+    json!({
+        "position": {
+            "dealId": position.deal_id,
+            "direction": direction_code(position.direction),
+            "size": number(position.size.amount),
+            "level": number(position.level),
+            "stopLevel": position.stop.map(number),
+            "limitLevel": null,
+            "controlledRisk": position.guaranteed_stop,
+            "createdDateUTC": format_time(position.created),
+            "currency": position.size.currency.code(),
+        },
+        "market": {
+            "epic": position.epic,
+            "bid": number(price.bid),
+            "offer": number(price.ask),
+        },
+    })
+}
+
+fn working_order(order: &WorkingOrder) -> Value {
+    json!({
+        "workingOrderData": {
+            "dealId": order.deal_id,
+            "epic": order.epic,
+            "direction": direction_code(order.direction),
+            "orderType": match order.order_type {
+                WorkingOrderType::Limit => "LIMIT",
+                WorkingOrderType::Stop => "STOP",
+            },
+            "orderSize": number(order.size.amount),
+            "orderLevel": number(order.level),
+            "stopDistance": order.stop_distance.map(number),
+            "timeInForce": "GOOD_TILL_CANCELLED",
+            "createdDateUTC": format_time(order.created),
+            "currencyCode": order.size.currency.code(),
+        },
+    })
+}
+
+fn bid_ask(price: Price) -> Value {
+    json!({ "bid": number(price.bid), "ask": number(price.ask), "lastTraded": null })
+}
+
+// Helpers
+
+fn next_deal(state: &mut MockState) -> String {
+    state.deals += 1;
+
+    format!("DEAL{:06}", state.deals)
+}
+
+fn open_profit(state: &MockState, position: &Position) -> Decimal {
+    let price = state.markets[&position.epic].details.price;
+
+    match position.direction {
+        Direction::Buy => profit(position, price.bid),
+        Direction::Sell => profit(position, price.ask),
+    }
+}
+
+fn profit(position: &Position, level: Points) -> Decimal {
+    match position.direction {
+        Direction::Buy => position.size.amount * (level - position.level),
+        Direction::Sell => position.size.amount * (position.level - level),
+    }
+}
+
+fn number(value: Decimal) -> Value {
+    value
+        .to_f64()
+        .and_then(serde_json::Number::from_f64)
+        .map_or(Value::Null, Value::Number)
+}
+
+fn decimal(value: &Value) -> Option<Decimal> {
+    value
+        .as_f64()
+        .and_then(|n| Decimal::from_str(&n.to_string()).ok())
+}
+
+fn parse_direction(value: &Value) -> Option<Direction> {
+    match value.as_str() {
+        Some("BUY") => Some(Direction::Buy),
+        Some("SELL") => Some(Direction::Sell),
+        _ => None,
+    }
+}
+
+fn direction_code(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Buy => "BUY",
+        Direction::Sell => "SELL",
+    }
+}
+
+fn resolution_code(resolution: Resolution) -> String {
+    match resolution {
+        Resolution::Second => "SECOND".to_string(),
+        Resolution::Minute(1) => "MINUTE".to_string(),
+        Resolution::Minute(n) => format!("MINUTE_{}", n),
+        Resolution::Hour(1) => "HOUR".to_string(),
+        Resolution::Hour(n) => format!("HOUR_{}", n),
+        Resolution::Day => "DAY".to_string(),
+        Resolution::Week => "WEEK".to_string(),
+        Resolution::Month => "MONTH".to_string(),
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format(DATE_FORMAT).to_string()
+}
+
+fn header(request: &Request, name: &str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+fn header_from(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
@@ -1,49 +1,254 @@
+mod monte_carlo;
+mod optimise;
+mod paper;
+mod portfolio;
 mod print;
 mod read;
+mod state;
+mod write;
 
-use std::io;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, io, process};
 
 use betty::backtest::Backtest;
+use chrono::Weekday;
 use iso_currency::Currency;
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 
-use betty::account::Account;
-use betty::market::Market;
-use betty::price::{CurrencyAmount, Resolution};
+use betty::account::{Account, MarginPolicy, Pyramiding};
+use betty::fill::{Execution, FillModel, Slippage};
+use betty::fx::RateSource;
+use betty::market::{Funding, GuaranteedStop, Market};
+use betty::price::{CurrencyAmount, Frame, Resolution};
 use betty::strategies::{Donchian, MACD};
 
-use crate::print::format_trade_log;
-use crate::read::read_prices_csv;
+use crate::optimise::parse;
+use crate::print::{format_margin_call, format_report, format_trade_log};
+use crate::read::{parse_currency, read_prices_csv, read_rates_csv};
+
+const RISK_PER_TRADE: Decimal = dec!(0.03);
 
 fn main() {
-    let prices = read_prices_csv(io::stdin());
-    let latest_price = prices.last().unwrap().close;
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    let market = Market {
-        code: "GDAXI".to_string(),
-        margin_factor: dec!(0.05),
-        min_deal_size: CurrencyAmount::new(dec!(0.50), Currency::GBP),
-        min_stop_distance: dec!(12),
+    let result = match args.first().map(|a| a.as_str()) {
+        None => backtest(&[]),
+        Some("backtest") => backtest(&args[1..]),
+        Some("optimise") => optimise::run(&args[1..]),
+        Some("walk-forward") => optimise::walk_forward(&args[1..]),
+        Some("monte-carlo") => monte_carlo::run(&args[1..]),
+        Some("paper") => paper::run(&args[1..]),
+        Some("portfolio") => portfolio::run(&args[1..]),
+        Some(command) => Err(format!("Unknown command {}", command)),
     };
 
-    let ts = MACD {
-        short: 12,
-        long: 42,
-        signal: 10,
-        entry_lim: dec!(40),
-        exit_lim: dec!(40),
-    };
-    let rs = Donchian { channel_length: 20 };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        eprintln!(
+            "Usage: cli [backtest [options] | optimise [options] | walk-forward [options] | monte-carlo [options] | paper [options] | portfolio [options]] < prices.csv"
+        );
+        process::exit(1);
+    }
+}
+
+struct BacktestOptions {
+    pyramiding: Pyramiding,
+    currency: Option<Currency>,
+    rates: Option<PathBuf>,
+    slippage: Slippage,
+    execution: Execution,
+    guaranteed_stop: Option<GuaranteedStop>,
+    funding: Option<Funding>,
+    margin_policy: MarginPolicy,
+}
 
-    let opening_balance = CurrencyAmount::new(dec!(20000.00), Currency::GBP);
+// Backtest of the strategy on prices from stdin
+//
+// Options:
+//   --units <n>          open positions at a time, adding to a winning one
+//   --step <R>           move in favour of the latest unit before adding the next
+//   --max-risk <value>   total risk of the open positions, as a fraction of the balance
+//   --currency <code>    of the market, when it's not the currency of the balance
+//   --rates <path>       CSV file of exchange rates into the currency of the balance
+//   --slippage <model>   points lost on every fill: <points>, spread:<fraction> of the
+//                        spread or range:<fraction> of the range of the day
+//   --execution <timing> of orders: same-close, the close the strategy decided on, or
+//                        next-open, next-close or next-mid of the following day
+//   --guaranteed <premium>:<distance>
+//                        place guaranteed stops, for a premium in points, at least the
+//                        distance away from the entry
+//   --funding <benchmark>:<fee>
+//                        charge positions held overnight the annual benchmark rate plus
+//                        the admin fee, or pay short ones the rate less the fee
+//   --margin <warning>:<liquidation>
+//                        margin levels, the value of the account as a fraction of the
+//                        margin held, to call for margin and to close positions at
+fn backtest(args: &[String]) -> Result<(), String> {
+    let options = parse_backtest_options(args)?;
 
-    let account = Account::new(market, ts, rs, dec!(0.03), opening_balance, Resolution::Day);
+    let prices = read_prices_csv(io::stdin());
+    let latest_price = prices.last().ok_or("No prices")?.close;
 
+    let mut account = account(opening_balance());
+    account.pyramiding = options.pyramiding;
+    if let Some(currency) = options.currency {
+        account.market.min_deal_size.currency = currency;
+    }
+    if let Some(path) = &options.rates {
+        account.rates = read_rates(path)?;
+    }
+    if let Some(guaranteed_stop) = options.guaranteed_stop {
+        account.market.guaranteed_stop = Some(guaranteed_stop);
+        account.guaranteed_stops = true;
+    }
+    account.market.funding = options.funding;
     let mut backtest = Backtest::new(account);
+    backtest.fill_model = FillModel::new(options.slippage, options.execution);
+    backtest.margin_policy = options.margin_policy;
     backtest.run(&prices);
 
     let trade_log = backtest.account.trade_log(latest_price);
 
-    let log = format_trade_log(&trade_log, opening_balance, latest_price);
+    let log = format_trade_log(&trade_log, backtest.opening_balance, latest_price);
     println!("{}", log);
+
+    for call in &backtest.margin_calls {
+        println!("{}", format_margin_call(call));
+    }
+
+    let report = format_report(&backtest.report(latest_price));
+    println!("{}", report);
+
+    Ok(())
+}
+
+fn parse_backtest_options(args: &[String]) -> Result<BacktestOptions, String> {
+    let mut options = BacktestOptions {
+        pyramiding: Pyramiding::none(),
+        currency: None,
+        rates: None,
+        slippage: Slippage::None,
+        execution: Execution::SameClose,
+        guaranteed_stop: None,
+        funding: None,
+        margin_policy: MarginPolicy::ig(),
+    };
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--units" => options.pyramiding.max_units = parse(value)?,
+            "--step" => options.pyramiding.step = parse(value)?,
+            "--max-risk" => options.pyramiding.max_risk = parse(value)?,
+            "--currency" => options.currency = Some(parse_currency(value)?),
+            "--rates" => options.rates = Some(PathBuf::from(value)),
+            "--slippage" => options.slippage = parse_slippage(value)?,
+            "--execution" => options.execution = parse_execution(value)?,
+            "--guaranteed" => options.guaranteed_stop = Some(parse_guaranteed_stop(value)?),
+            "--funding" => options.funding = Some(parse_funding(value)?),
+            "--margin" => options.margin_policy = parse_margin_policy(value)?,
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_slippage(value: &str) -> Result<Slippage, String> {
+    match value.split_once(':') {
+        Some(("spread", fraction)) => Ok(Slippage::Spread(parse(fraction)?)),
+        Some(("range", fraction)) => Ok(Slippage::Volatility(parse(fraction)?)),
+        Some(_) => Err(format!("Unknown slippage {}", value)),
+        None => Ok(Slippage::Points(parse(value)?)),
+    }
+}
+
+fn parse_execution(value: &str) -> Result<Execution, String> {
+    match value {
+        "same-close" => Ok(Execution::SameClose),
+        "next-open" => Ok(Execution::NextOpen),
+        "next-close" => Ok(Execution::NextClose),
+        "next-mid" => Ok(Execution::NextMid),
+        _ => Err(format!("Unknown execution {}", value)),
+    }
+}
+
+fn parse_guaranteed_stop(value: &str) -> Result<GuaranteedStop, String> {
+    let (premium, distance) = value.split_once(':').ok_or_else(|| {
+        format!(
+            "Invalid guaranteed stop {}, expected premium:distance",
+            value
+        )
+    })?;
+
+    Ok(GuaranteedStop::new(parse(premium)?, parse(distance)?))
+}
+
+// Funding of an index, the weekend is charged on Fridays
+fn parse_funding(value: &str) -> Result<Funding, String> {
+    let (benchmark, fee) = value
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid funding {}, expected benchmark:fee", value))?;
+
+    Ok(Funding::new(parse(benchmark)?, parse(fee)?, Weekday::Fri))
+}
+
+fn parse_margin_policy(value: &str) -> Result<MarginPolicy, String> {
+    let (warning, liquidation) = value.split_once(':').ok_or_else(|| {
+        format!(
+            "Invalid margin levels {}, expected warning:liquidation",
+            value
+        )
+    })?;
+
+    Ok(MarginPolicy::new(parse(warning)?, parse(liquidation)?))
+}
+
+fn read_rates(path: &Path) -> Result<Arc<dyn RateSource>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+
+    Ok(Arc::new(read_rates_csv(file)?))
+}
+
+fn run_backtest(prices: &[Frame]) -> Backtest<MACD, Donchian> {
+    let mut backtest = Backtest::new(account(opening_balance()));
+    backtest.run(prices);
+
+    backtest
+}
+
+fn account(opening_balance: CurrencyAmount) -> Account<MACD, Donchian> {
+    let ts = MACD::new(12, 42, 10, dec!(40), dec!(40));
+    let rs = Donchian::new(20);
+
+    Account::new(
+        market(),
+        ts,
+        rs,
+        RISK_PER_TRADE,
+        opening_balance,
+        Resolution::Day,
+    )
+}
+
+fn market() -> Market {
+    Market {
+        code: "GDAXI".to_string(),
+        margin_factor: dec!(0.05),
+        min_deal_size: CurrencyAmount::new(dec!(0.50), Currency::GBP),
+        min_stop_distance: dec!(12),
+        guaranteed_stop: None,
+        funding: None,
+    }
+}
+
+fn opening_balance() -> CurrencyAmount {
+    CurrencyAmount::new(dec!(20000.00), Currency::GBP)
 }
@@ -0,0 +1,85 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use betty::optimise::MonteCarlo;
+
+use crate::optimise::{parse, parse_list};
+use crate::print::format_monte_carlo;
+use crate::read::read_prices_csv;
+use crate::run_backtest;
+
+struct Options {
+    monte_carlo: MonteCarlo,
+    risk_per_trade: Vec<Decimal>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            monte_carlo: MonteCarlo::new(10000, 0),
+            risk_per_trade: vec![dec!(0.01), dec!(0.02), dec!(0.03), dec!(0.05), dec!(0.1)],
+        }
+    }
+}
+
+// Monte Carlo simulation of the trades of a backtest on prices from stdin
+//
+// Options:
+//   --runs <n>
+//   --seed <n>
+//   --sampling <shuffle|bootstrap>
+//   --spread <points>     extra cost of every trade
+//   --slippage <points>   maximum random slippage on entry and on exit
+//   --ruin <fraction>     loss of the opening balance considered a ruin
+//   --risk <value,value,...>
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+
+    let prices = read_prices_csv(io::stdin());
+    let latest_price = prices.last().ok_or("No prices")?.close;
+
+    let backtest = run_backtest(&prices);
+    let trades = backtest.account.trade_log(latest_price);
+
+    let results: Vec<_> = options
+        .risk_per_trade
+        .iter()
+        .filter_map(|risk| {
+            options
+                .monte_carlo
+                .run(&trades, backtest.opening_balance, *risk)
+        })
+        .collect();
+
+    println!("{}", format_monte_carlo(&results, &options.monte_carlo));
+
+    Ok(())
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--runs" => options.monte_carlo.runs = parse(value)?,
+            "--seed" => options.monte_carlo.seed = parse(value)?,
+            "--sampling" => {
+                options.monte_carlo.sampling = value.parse().map_err(|e| format!("{}", e))?
+            }
+            "--spread" => options.monte_carlo.spread = parse(value)?,
+            "--slippage" => options.monte_carlo.slippage = parse(value)?,
+            "--ruin" => options.monte_carlo.ruin = parse(value)?,
+            "--risk" => options.risk_per_trade = parse_list(value)?,
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use betty::optimise::{
+    available_threads, steps, Annealing, Genetic, GridSearch, Objective, Optimiser, ParameterSpace,
+    Simulation, WalkForward,
+};
+use betty::price::Resolution;
+
+use crate::print::{format_convergence, format_evaluations, format_report, format_walk_forward};
+use crate::read::read_prices_csv;
+use crate::write::write_evaluations_csv;
+use crate::{market, opening_balance, RISK_PER_TRADE};
+
+struct Options {
+    method: String,
+    seed: u64,
+    population: usize,
+    generations: usize,
+    iterations: usize,
+    objective: Objective,
+    top: usize,
+    output: String,
+    threads: usize,
+    space: ParameterSpace,
+    walk_forward: WalkForward,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            method: "grid".to_string(),
+            seed: 0,
+            population: 30,
+            generations: 20,
+            iterations: 300,
+            objective: Objective::Sharpe,
+            top: 10,
+            output: "optimisation.csv".to_string(),
+            threads: available_threads(),
+            space: ParameterSpace {
+                short: steps(8, 16, 2),
+                long: steps(30, 50, 4),
+                signal: steps(6, 12, 2),
+                entry_lim: vec![dec!(40)],
+                exit_lim: vec![dec!(40)],
+                channel_length: steps(10, 30, 5),
+                risk_per_trade: vec![RISK_PER_TRADE],
+            },
+            walk_forward: WalkForward {
+                in_sample: 500,
+                out_of_sample: 125,
+            },
+        }
+    }
+}
+
+impl Options {
+    fn optimiser(&self) -> Result<Box<dyn Optimiser>, String> {
+        match self.method.as_str() {
+            "grid" => Ok(Box::new(GridSearch {
+                threads: self.threads,
+            })),
+            "genetic" => Ok(Box::new(Genetic::new(
+                self.population,
+                self.generations,
+                self.seed,
+            ))),
+            "annealing" => Ok(Box::new(Annealing::new(self.iterations, self.seed))),
+            _ => Err(format!("Unknown optimisation method {}", self.method)),
+        }
+    }
+}
+
+// Optimise the strategy parameters on prices from stdin
+//
+// Options:
+//   --method <grid|genetic|annealing>
+//   --seed <n>          random seed of the genetic and annealing methods
+//   --population <n>    genetic population size
+//   --generations <n>   genetic generations
+//   --iterations <n>    annealing iterations
+//   --objective <return|cagr|sharpe|sortino|profit-factor|expectancy|drawdown>
+//   --top <n>           number of best results to print
+//   --output <path>     CSV file to write all the results to
+//   --threads <n>
+//   --short, --long, --signal, --channel <start:end:step | value>
+//   --entry, --exit, --risk <value,value,...>
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+
+    let optimiser = options.optimiser()?;
+    let prices = read_prices_csv(io::stdin());
+
+    let optimisation =
+        optimiser.optimise(&simulation(), &options.space, &prices, options.objective);
+    let evaluations = &optimisation.evaluations;
+
+    if options.method != "grid" {
+        println!("{}", format_convergence(&optimisation.convergence));
+    }
+
+    let top = &evaluations[..options.top.min(evaluations.len())];
+    println!("{}", format_evaluations(top, options.objective));
+
+    let file = File::create(&options.output)
+        .map_err(|e| format!("Couldn't create {}: {}", options.output, e))?;
+    write_evaluations_csv(file, evaluations)
+        .map_err(|e| format!("Couldn't write {}: {}", options.output, e))?;
+
+    println!(
+        "{} results written to {}",
+        evaluations.len(),
+        options.output
+    );
+
+    Ok(())
+}
+
+// Walk-forward analysis on prices from stdin, optimising in each in-sample window
+//
+// Takes the same options as optimise (apart from --top and --output), plus
+//   --in-sample <n>       frames to optimise on
+//   --out-of-sample <n>   frames to test the best parameters on
+pub fn walk_forward(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+
+    let optimiser = options.optimiser()?;
+    let prices = read_prices_csv(io::stdin());
+
+    let result = options.walk_forward.run(
+        &simulation(),
+        optimiser.as_ref(),
+        &options.space,
+        &prices,
+        options.objective,
+    );
+
+    if result.windows.is_empty() {
+        return Err(format!(
+            "Not enough prices for a single window, need {}",
+            options.walk_forward.in_sample + options.walk_forward.out_of_sample
+        ));
+    }
+
+    println!("{}", format_walk_forward(&result, options.objective));
+    println!("{}", format_report(&result.report));
+
+    Ok(())
+}
+
+fn simulation() -> Simulation {
+    Simulation {
+        market: market(),
+        opening_balance: opening_balance(),
+        resolution: Resolution::Day,
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--method" => options.method = value.clone(),
+            "--seed" => options.seed = parse(value)?,
+            "--population" => options.population = parse(value)?,
+            "--generations" => options.generations = parse(value)?,
+            "--iterations" => options.iterations = parse(value)?,
+            "--objective" => options.objective = value.parse().map_err(|e| format!("{}", e))?,
+            "--top" => options.top = parse(value)?,
+            "--output" => options.output = value.clone(),
+            "--threads" => options.threads = parse(value)?,
+            "--short" => options.space.short = parse_steps(value)?,
+            "--long" => options.space.long = parse_steps(value)?,
+            "--signal" => options.space.signal = parse_steps(value)?,
+            "--channel" => options.space.channel_length = parse_steps(value)?,
+            "--entry" => options.space.entry_lim = parse_list(value)?,
+            "--exit" => options.space.exit_lim = parse_list(value)?,
+            "--risk" => options.space.risk_per_trade = parse_list(value)?,
+            "--in-sample" => options.walk_forward.in_sample = parse(value)?,
+            "--out-of-sample" => options.walk_forward.out_of_sample = parse(value)?,
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid value {}", value))
+}
+
+// Either a single value, or start:end:step
+fn parse_steps(value: &str) -> Result<Vec<usize>, String> {
+    let parts = value
+        .split(':')
+        .map(parse)
+        .collect::<Result<Vec<usize>, String>>()?;
+
+    match parts[..] {
+        [single] => Ok(vec![single]),
+        [start, end, step] => Ok(steps(start, end, step)),
+        _ => Err(format!("Invalid range {}, expected start:end:step", value)),
+    }
+}
+
+pub fn parse_list(value: &str) -> Result<Vec<Decimal>, String> {
+    value.split(',').map(parse).collect()
+}
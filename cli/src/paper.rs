@@ -0,0 +1,248 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use betty::account::Account;
+use betty::broker::SimulatedBroker;
+use betty::live::LiveRunner;
+use betty::price::{CurrencyAmount, Frame, PriceHistory};
+use betty::strategies::{Donchian, MACD};
+
+use crate::optimise::parse;
+use crate::print::{format_event, format_trade_log};
+use crate::read::{read_prices, PricesFile};
+use crate::state::PaperState;
+use crate::{account, opening_balance};
+
+struct Options {
+    state: PathBuf,
+    prices: Option<PathBuf>,
+    poll: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            state: PathBuf::from("paper.json"),
+            prices: None,
+            poll: None,
+        }
+    }
+}
+
+// Paper trading a strategy on prices as they arrive, with a simulated broker
+//
+// The account is saved whenever it trades and after each batch of prices, and picked up
+// again on the next run. Prices the account has already seen are skipped, so the same
+// file can be fed in again after more prices are appended to it.
+//
+// Options:
+//   --state <path>       where the account is saved, paper.json by default
+//   --prices <path>      CSV file to read the prices from instead of stdin
+//   --poll <seconds>     keep watching the prices file for new prices
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let mut paper = Paper::load(&options.state)?;
+
+    match &options.prices {
+        Some(path) => {
+            let mut prices = PricesFile::new(path);
+
+            loop {
+                paper.update(prices.read()?.into_iter(), &options.state)?;
+
+                match options.poll {
+                    Some(seconds) => thread::sleep(Duration::from_secs(seconds)),
+                    None => break,
+                }
+            }
+        }
+        None => paper.update(read_prices(io::stdin()), &options.state)?,
+    }
+
+    if let Some(latest) = paper.runner.account.price_history.latest() {
+        let trade_log = paper.runner.account.trade_log(latest.close);
+        println!(
+            "{}",
+            format_trade_log(&trade_log, paper.opening_balance, latest.close)
+        );
+    }
+
+    Ok(())
+}
+
+struct Paper {
+    runner: LiveRunner<MACD, Donchian, SimulatedBroker>,
+    opening_balance: CurrencyAmount,
+}
+
+impl Paper {
+    // Rebuilds the account from a saved state, or starts a new one
+    fn load(path: &Path) -> Result<Self, String> {
+        let (opening_balance, runner) = match PaperState::load(path)? {
+            Some(state) => {
+                let mut account = paper_account(state.opening_balance);
+                account
+                    .restore(state.account)
+                    .map_err(|e| format!("Cannot restore {}: {}", path.display(), e))?;
+
+                let mut broker = SimulatedBroker::new(account.market.clone(), account.balance);
+                broker.positions = account
+                    .live_trades()
+                    .iter()
+                    .map(|p| p.entry.clone())
+                    .collect();
+                broker.next_id = state.next_id;
+
+                (state.opening_balance, LiveRunner::new(account, broker))
+            }
+            None => {
+                let account = paper_account(opening_balance());
+                let broker = SimulatedBroker::new(account.market.clone(), account.balance);
+
+                (account.balance, LiveRunner::new(account, broker))
+            }
+        };
+
+        Ok(Self {
+            runner,
+            opening_balance,
+        })
+    }
+
+    // Trades on the prices newer than the latest one seen
+    fn update<I>(&mut self, frames: I, path: &Path) -> Result<(), String>
+    where
+        I: Iterator<Item = Frame>,
+    {
+        let mut unsaved = false;
+
+        for frame in frames {
+            if let Some(latest) = self.runner.account.price_history.latest() {
+                if frame.close_time <= latest.close_time {
+                    continue;
+                }
+            }
+
+            let events = self.runner.update_price(frame);
+            for event in &events {
+                println!("{}", format_event(event));
+            }
+
+            // Trades are saved straight away, other prices with the rest of the batch
+            unsaved = events.is_empty();
+            if !unsaved {
+                self.state().save(path)?;
+            }
+        }
+
+        if unsaved {
+            self.state().save(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn state(&self) -> PaperState {
+        PaperState {
+            opening_balance: self.opening_balance,
+            next_id: self.runner.broker.next_id,
+            account: self.runner.account.snapshot(),
+        }
+    }
+}
+
+// Account keeping all of the prices, for the strategies to warm up on exactly when restored
+fn paper_account(opening_balance: CurrencyAmount) -> Account<MACD, Donchian> {
+    let mut account = account(opening_balance);
+    account.price_history = PriceHistory::new(account.price_history.resolution);
+
+    account
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--state" => options.state = PathBuf::from(value),
+            "--prices" => options.prices = Some(PathBuf::from(value)),
+            "--poll" => options.poll = Some(parse(value)?),
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use betty::trade::Trade;
+
+    use super::*;
+    use crate::read::read_prices_csv;
+
+    #[test]
+    fn carries_on_after_a_restart_as_if_it_never_stopped() -> Result<(), String> {
+        let prices = read_prices_csv(&include_bytes!("../../dax-2018-2021-daily.csv")[..]);
+        let (restarted, uninterrupted) = (state_path("restarted"), state_path("uninterrupted"));
+
+        let mut paper = Paper::load(&restarted)?;
+        paper.update(prices[..450].iter().copied(), &restarted)?;
+        let open = paper.runner.account.live_trades().len();
+        let mut paper = Paper::load(&restarted)?;
+        paper.update(prices[450..].iter().copied(), &restarted)?;
+
+        let mut expected = Paper::load(&uninterrupted)?;
+        expected.update(prices.iter().copied(), &uninterrupted)?;
+
+        fs::remove_file(&restarted)
+            .and_then(|_| fs::remove_file(&uninterrupted))
+            .unwrap();
+
+        assert_eq!(open, 1);
+        assert!(expected.runner.account.closed_trades().len() > 10);
+        let (state, expected) = (paper.state(), expected.state());
+        assert_eq!(state.next_id, expected.next_id);
+        assert_eq!(state.account.balance, expected.account.balance);
+        assert_eq!(state.account.price_history, expected.account.price_history);
+        assert_eq!(state.account.live_trades, expected.account.live_trades);
+        assert_eq!(
+            ratios_rounded(state.account.closed_trades),
+            ratios_rounded(expected.account.closed_trades)
+        );
+
+        Ok(())
+    }
+
+    // Fixtures
+
+    // Built with the rest of the workspace decimals may be saved as floats, the risk-reward
+    // ratios are the only numbers with more digits than those keep
+    fn ratios_rounded(trades: Vec<Trade>) -> Vec<Trade> {
+        trades
+            .into_iter()
+            .map(|t| Trade {
+                risk_reward: t.risk_reward.round_dp(10),
+                ..t
+            })
+            .collect()
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("paper-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+}
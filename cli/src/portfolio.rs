@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use betty::fill::{Execution, FillModel, Slippage};
+use betty::market::{Funding, GuaranteedStop, Market};
+use betty::portfolio::{Portfolio, RiskLimits};
+use iso_currency::Currency;
+
+use crate::optimise::parse;
+use crate::print::{format_report, format_trade_log};
+use crate::read::{parse_currency, read_prices_csv};
+use crate::{
+    account, market, opening_balance, parse_execution, parse_funding, parse_guaranteed_stop,
+    parse_slippage, read_rates,
+};
+
+struct Options {
+    markets: Vec<(String, Option<Currency>, PathBuf)>,
+    limits: RiskLimits,
+    rates: Option<PathBuf>,
+    slippage: Slippage,
+    execution: Execution,
+    guaranteed_stop: Option<GuaranteedStop>,
+    funding: Option<Funding>,
+}
+
+// Backtest of the strategy on several markets at once, from a shared balance
+//
+// The markets trade by the same rules as the default one, under their own codes and
+// optionally in their own currencies.
+//
+// Options:
+//   --market <code>[:<currency>]=<path>
+//                            market and the CSV file of its prices, repeated for each market
+//   --per-market <value>     open risk in any one market, as a fraction of the balance
+//   --total <value>          open risk across all the markets
+//   --correlated <value>     open risk in markets moving together, in the same direction
+//   --correlation <value>    of returns, above which markets are taken to move together
+//   --lookback <n>           returns the correlation is measured over
+//   --rates <path>           CSV file of exchange rates into the currency of the balance
+//   --slippage <model>       points lost on every fill, as in the backtest
+//   --execution <timing>     of orders, as in the backtest
+//   --guaranteed <premium>:<distance>
+//                            guaranteed stops in every market, as in the backtest
+//   --funding <benchmark>:<fee>
+//                            overnight funding in every market, as in the backtest
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    if options.markets.is_empty() {
+        return Err("No markets".to_string());
+    }
+
+    let rates = match &options.rates {
+        Some(path) => Some(read_rates(path)?),
+        None => None,
+    };
+
+    let mut accounts = vec![];
+    let mut prices = vec![];
+    for (code, currency, path) in &options.markets {
+        let file =
+            File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+
+        let mut account = account(opening_balance());
+        account.market = Market {
+            code: code.clone(),
+            guaranteed_stop: options.guaranteed_stop,
+            funding: options.funding,
+            ..market()
+        };
+        account.guaranteed_stops = options.guaranteed_stop.is_some();
+        if let Some(currency) = currency {
+            account.market.min_deal_size.currency = *currency;
+        }
+        if let Some(rates) = &rates {
+            account.rates = rates.clone();
+        }
+        accounts.push(account);
+        prices.push(read_prices_csv(file));
+    }
+
+    let mut portfolio = Portfolio::new(accounts, opening_balance(), options.limits);
+    for backtest in &mut portfolio.markets {
+        backtest.fill_model = FillModel::new(options.slippage, options.execution);
+    }
+    portfolio.run(&prices);
+
+    for backtest in &portfolio.markets {
+        if let Some(latest) = backtest.account.price_history.latest() {
+            let trade_log = backtest.account.trade_log(latest.close);
+
+            println!("{}", backtest.account.market.code);
+            println!(
+                "{}",
+                format_trade_log(&trade_log, portfolio.opening_balance, latest.close)
+            );
+        }
+    }
+
+    let report = format_report(&portfolio.report());
+    println!("{}", report);
+
+    Ok(())
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        markets: vec![],
+        limits: RiskLimits::none(),
+        rates: None,
+        slippage: Slippage::None,
+        execution: Execution::SameClose,
+        guaranteed_stop: None,
+        funding: None,
+    };
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+
+        match flag.as_str() {
+            "--market" => {
+                let (market, path) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid market {}, expected code=path", value))?;
+                let (code, currency) = match market.split_once(':') {
+                    Some((code, currency)) => (code, Some(parse_currency(currency)?)),
+                    None => (market, None),
+                };
+                options
+                    .markets
+                    .push((code.to_string(), currency, PathBuf::from(path)));
+            }
+            "--per-market" => options.limits.per_market = parse(value)?,
+            "--total" => options.limits.total = parse(value)?,
+            "--correlated" => options.limits.correlated = parse(value)?,
+            "--correlation" => options.limits.correlation = parse(value)?,
+            "--lookback" => options.limits.lookback = parse(value)?,
+            "--rates" => options.rates = Some(PathBuf::from(value)),
+            "--slippage" => options.slippage = parse_slippage(value)?,
+            "--execution" => options.execution = parse_execution(value)?,
+            "--guaranteed" => options.guaranteed_stop = Some(parse_guaranteed_stop(value)?),
+            "--funding" => options.funding = Some(parse_funding(value)?),
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
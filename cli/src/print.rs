@@ -1,10 +1,15 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use term_table::{row::Row, table_cell::TableCell, Table, TableStyle};
 use termion::{color, style};
 
+use betty::account::MarginCall;
+use betty::optimise::{Evaluation, MonteCarlo, MonteCarloResult, Objective, WalkForwardResult};
 use betty::price::{CurrencyAmount, Price};
-use betty::trade::{Direction, Trade, TradeOutcome};
+use betty::report::Report;
+use betty::trade::{Direction, Order, Trade, TradeOutcome};
 
 pub fn format_trade_log(
     trade_log: &Vec<Trade>,
@@ -18,7 +23,7 @@ pub fn format_trade_log(
     table.add_row(Row::new(
         vec![
             "ID", "Status", "Entry", "Price", "Dir", "Exit", "Price", "Stop", "Change", "£ PP",
-            "Risk", "Outcome", "Profit", "RR", "Balance",
+            "Risk", "Outcome", "Funding", "Profit", "RR", "Balance",
         ]
         .into_iter()
         .map(|it| TableCell::new(format!("{}{}{}", style::Bold, it, style::Reset))),
@@ -26,8 +31,9 @@ pub fn format_trade_log(
 
     let mut balance = opening_balance;
 
+    // The trade log is in the currency of the balance
     for trade in trade_log {
-        balance += trade.profit;
+        balance.amount += trade.profit.amount;
 
         table.add_row(Row::new(
             vec![
@@ -46,8 +52,8 @@ pub fn format_trade_log(
                     .unwrap_or("-".to_string()),
                 format!(
                     "{}{}{}",
-                    stop_colour(&trade, latest_price),
-                    trade.stop,
+                    stop_colour(trade, latest_price),
+                    format_stop(trade),
                     color::Fg(color::Reset)
                 ),
                 trade.price_diff.to_string(),
@@ -59,6 +65,7 @@ pub fn format_trade_log(
                     trade.outcome,
                     color::Fg(color::Reset)
                 ),
+                trade.funding.to_string(),
                 format!(
                     "{}{}{}",
                     outcome_color(trade.outcome),
@@ -74,11 +81,325 @@ pub fn format_trade_log(
                 balance.to_string(),
             ]
             .into_iter()
-            .map(|it| TableCell::new(it)),
+            .map(TableCell::new),
         ));
     }
 
-    format!("{}", table.render())
+    table.render()
+}
+
+pub fn format_report(report: &Report) -> String {
+    // Pretty print a performance summary
+    let mut table = Table::new();
+    table.style = TableStyle::simple();
+
+    let percent = |d: Decimal| format!("{}%", (d * dec!(100)).round_dp(2));
+    let ratio = |d: Option<Decimal>| d.map_or("-".to_string(), |d| d.round_dp(2).to_string());
+
+    let rows = vec![
+        (
+            "Period",
+            format!(
+                "{} - {}",
+                report.start.format("%e-%b-%Y"),
+                report.end.format("%e-%b-%Y")
+            ),
+        ),
+        ("Opening balance", report.opening_balance.to_string()),
+        ("Closing balance", report.closing_balance.to_string()),
+        ("Total return", percent(report.total_return)),
+        ("CAGR", report.cagr.map_or("-".to_string(), percent)),
+        (
+            "Max drawdown",
+            format!(
+                "{} ({})",
+                report.max_drawdown,
+                percent(report.max_drawdown_pct)
+            ),
+        ),
+        (
+            "Max drawdown duration",
+            format!("{} days", report.max_drawdown_duration.num_days()),
+        ),
+        ("Trades", report.trades.to_string()),
+        ("Win rate", report.win_rate.map_or("-".to_string(), percent)),
+        ("Average R", ratio(report.average_r)),
+        (
+            "Expectancy",
+            report.expectancy.map_or("-".to_string(), |e| e.to_string()),
+        ),
+        ("Profit factor", ratio(report.profit_factor)),
+        ("Sharpe", ratio(report.sharpe)),
+        ("Sortino", ratio(report.sortino)),
+        (
+            "Longest losing streak",
+            report.longest_losing_streak.to_string(),
+        ),
+        ("Exposure", percent(report.exposure)),
+    ];
+
+    for (name, value) in rows {
+        table.add_row(Row::new(vec![
+            TableCell::new(format!("{}{}{}", style::Bold, name, style::Reset)),
+            TableCell::new(value),
+        ]));
+    }
+
+    table.render()
+}
+
+pub fn format_evaluations(evaluations: &[Evaluation], objective: Objective) -> String {
+    // Pretty print optimisation results
+    let mut table = Table::new();
+    table.style = TableStyle::simple();
+    table.add_row(Row::new(
+        vec![
+            "#", "Short", "Long", "Signal", "Entry", "Exit", "Channel", "Risk", "Score", "Return",
+            "Max DD", "Trades", "Win rate", "PF", "Sharpe",
+        ]
+        .into_iter()
+        .map(|it| TableCell::new(format!("{}{}{}", style::Bold, it, style::Reset))),
+    ));
+
+    let percent = |d: Decimal| format!("{}%", (d * dec!(100)).round_dp(2));
+    let ratio = |d: Option<Decimal>| d.map_or("-".to_string(), |d| d.round_dp(2).to_string());
+
+    for (rank, evaluation) in evaluations.iter().enumerate() {
+        let parameters = &evaluation.parameters;
+        let report = &evaluation.report;
+
+        table.add_row(Row::new(
+            vec![
+                (rank + 1).to_string(),
+                parameters.short.to_string(),
+                parameters.long.to_string(),
+                parameters.signal.to_string(),
+                parameters.entry_lim.to_string(),
+                parameters.exit_lim.to_string(),
+                parameters.channel_length.to_string(),
+                percent(parameters.risk_per_trade),
+                format!("{}{}{}", style::Bold, ratio(evaluation.score), style::Reset),
+                percent(report.total_return),
+                percent(report.max_drawdown_pct),
+                report.trades.to_string(),
+                report.win_rate.map_or("-".to_string(), percent),
+                ratio(report.profit_factor),
+                ratio(report.sharpe),
+            ]
+            .into_iter()
+            .map(TableCell::new),
+        ));
+    }
+
+    format!("Best results by {}\n{}", objective, table.render())
+}
+
+// Best score after each iteration of an optimiser, as a sparkline
+pub fn format_convergence(convergence: &[Option<Decimal>]) -> String {
+    let bars = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let scores: Vec<Decimal> = convergence.iter().flatten().cloned().collect();
+    let (min, max) = match (scores.iter().min(), scores.iter().max()) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => return "Convergence: -".to_string(),
+    };
+
+    let line: String = convergence
+        .iter()
+        .map(|score| match score {
+            Some(score) if max > min => {
+                let level = (*score - min) / (max - min) * Decimal::from(bars.len() - 1);
+                bars[level.round().to_usize().unwrap_or(0)]
+            }
+            Some(_) => bars[bars.len() - 1],
+            None => ' ',
+        })
+        .collect();
+
+    format!("Convergence: {} {}", line, max.round_dp(2))
+}
+
+pub fn format_walk_forward(result: &WalkForwardResult, objective: Objective) -> String {
+    // Pretty print walk-forward windows
+    let mut table = Table::new();
+    table.style = TableStyle::simple();
+    table.add_row(Row::new(
+        vec![
+            "Out of sample",
+            "Short",
+            "Long",
+            "Signal",
+            "Entry",
+            "Exit",
+            "Channel",
+            "Risk",
+            "IS score",
+            "IS return",
+            "OOS score",
+            "OOS return",
+            "OOS trades",
+        ]
+        .into_iter()
+        .map(|it| TableCell::new(format!("{}{}{}", style::Bold, it, style::Reset))),
+    ));
+
+    let percent = |d: Decimal| format!("{}%", (d * dec!(100)).round_dp(2));
+    let ratio = |d: Option<Decimal>| d.map_or("-".to_string(), |d| d.round_dp(2).to_string());
+
+    for window in &result.windows {
+        let parameters = &window.in_sample.parameters;
+        let oos = &window.out_of_sample;
+
+        table.add_row(Row::new(
+            vec![
+                format!(
+                    "{} - {}",
+                    oos.start.format("%e-%b-%Y"),
+                    oos.end.format("%e-%b-%Y")
+                ),
+                parameters.short.to_string(),
+                parameters.long.to_string(),
+                parameters.signal.to_string(),
+                parameters.entry_lim.to_string(),
+                parameters.exit_lim.to_string(),
+                parameters.channel_length.to_string(),
+                percent(parameters.risk_per_trade),
+                ratio(window.in_sample.score),
+                percent(window.in_sample.report.total_return),
+                ratio(objective.score(oos)),
+                percent(oos.total_return),
+                oos.trades.to_string(),
+            ]
+            .into_iter()
+            .map(TableCell::new),
+        ));
+    }
+
+    format!(
+        "Walk-forward optimising {}\n{}Efficiency: {}",
+        objective,
+        table.render(),
+        ratio(result.efficiency)
+    )
+}
+
+pub fn format_monte_carlo(results: &[MonteCarloResult], monte_carlo: &MonteCarlo) -> String {
+    // Pretty print Monte Carlo distributions for each risk per trade
+    let mut table = Table::new();
+    table.style = TableStyle::simple();
+    table.add_row(Row::new(
+        vec![
+            "Risk",
+            "Balance 5%",
+            "Balance median",
+            "Balance 95%",
+            "Max DD median",
+            "Max DD 95%",
+            "Max DD worst",
+            "Risk of ruin",
+        ]
+        .into_iter()
+        .map(|it| TableCell::new(format!("{}{}{}", style::Bold, it, style::Reset))),
+    ));
+
+    let percent = |d: Decimal| format!("{}%", (d * dec!(100)).round_dp(2));
+
+    for result in results {
+        let balance = &result.final_balance;
+        let drawdown = &result.max_drawdown;
+
+        table.add_row(Row::new(
+            vec![
+                percent(result.risk_per_trade),
+                balance.p5.round_dp(2).to_string(),
+                balance.median.round_dp(2).to_string(),
+                balance.p95.round_dp(2).to_string(),
+                percent(drawdown.median),
+                percent(drawdown.p95),
+                percent(drawdown.max),
+                format!(
+                    "{}{}{}",
+                    ruin_colour(result.risk_of_ruin),
+                    percent(result.risk_of_ruin),
+                    style::Reset
+                ),
+            ]
+            .into_iter()
+            .map(TableCell::new),
+        ));
+    }
+
+    format!(
+        "Monte Carlo, {} runs ({}), ruin at {} loss\n{}",
+        monte_carlo.runs,
+        monte_carlo.sampling,
+        percent(monte_carlo.ruin),
+        table.render()
+    )
+}
+
+pub fn format_event(event: &Result<Order, String>) -> String {
+    // One line for each order placed while trading
+    let time = |t: DateTime<Utc>| t.format("%e-%b-%Y %k:%M").to_string();
+
+    match event {
+        Ok(Order::Open(entry)) => format!(
+            "{} Open {} {} {} at {}, stop {}",
+            time(entry.time),
+            entry.position_id,
+            entry.direction,
+            entry.size,
+            entry.price,
+            entry.stop
+        ),
+        Ok(Order::Close(exit)) => format!(
+            "{} Close {} at {}",
+            time(exit.time),
+            exit.position_id,
+            exit.price
+        ),
+        Ok(Order::Stop(exit)) => format!(
+            "{} Stop {} at {}",
+            time(exit.time),
+            exit.position_id,
+            exit.price
+        ),
+        Ok(Order::Liquidate(exit)) => format!(
+            "{} Liquidate {} at {}",
+            time(exit.time),
+            exit.position_id,
+            exit.price
+        ),
+        Ok(Order::Amend(amendment)) => format!(
+            "{} Amend {} stop to {}",
+            time(amendment.time),
+            amendment.position_id,
+            amendment.stop
+        ),
+        Err(e) => format!("{}{}{}", color::Fg(color::Red), e, color::Fg(color::Reset)),
+    }
+}
+
+pub fn format_margin_call(call: &MarginCall) -> String {
+    // One line for each time the margin level fell below the policy's levels
+    let (name, equity) = match call {
+        MarginCall::Warning(equity) => ("Margin call", equity),
+        MarginCall::Liquidation(equity) => ("Liquidation", equity),
+    };
+    let level = equity.margin_level().map_or("-".to_string(), |l| {
+        format!("{}%", (l * dec!(100)).round_dp(2))
+    });
+
+    format!(
+        "{}{} {}, worth {} holding {} of margin ({}){}",
+        color::Fg(color::Red),
+        equity.time.format("%e-%b-%Y %k:%M"),
+        name,
+        equity.value(),
+        equity.margin_used,
+        level,
+        color::Fg(color::Reset)
+    )
 }
 
 fn outcome_color(outcome: TradeOutcome) -> String {
@@ -88,12 +409,23 @@ fn outcome_color(outcome: TradeOutcome) -> String {
     }
 }
 
+// Initial stop, followed by the last one if it was moved
+fn format_stop(trade: &Trade) -> String {
+    if trade.stop_history.is_empty() {
+        trade.stop.to_string()
+    } else {
+        format!("{} → {}", trade.stop, trade.last_stop())
+    }
+}
+
 fn stop_colour(trade: &Trade, latest_price: Price) -> String {
+    let stop = trade.last_stop();
+
     match trade.direction {
-        Direction::Buy if trade.stop >= trade.exit_price.unwrap_or(latest_price.bid) => {
+        Direction::Buy if stop >= trade.exit_price.unwrap_or(latest_price.bid) => {
             format!("{}", color::Fg(color::Red))
         }
-        Direction::Sell if trade.stop <= trade.exit_price.unwrap_or(latest_price.ask) => {
+        Direction::Sell if stop <= trade.exit_price.unwrap_or(latest_price.ask) => {
             format!("{}", color::Fg(color::Red))
         }
         _ => String::new(),
@@ -111,3 +443,11 @@ fn risk_colour(risk: Decimal) -> String {
 
     String::new()
 }
+
+fn ruin_colour(risk_of_ruin: Decimal) -> String {
+    if risk_of_ruin > dec!(0) {
+        return format!("{}", color::Fg(color::Red));
+    }
+
+    String::new()
+}
@@ -1,9 +1,15 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
 use chrono::{DateTime, TimeZone, Utc};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Deserializer};
 
+use betty::fx::RateHistory;
 use betty::price::{Frame, Price};
+use iso_currency::Currency;
 
 #[derive(Deserialize, Debug)]
 struct PriceRecord {
@@ -19,7 +25,19 @@ struct PriceRecord {
     close: Decimal,
 }
 
-const DATE_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S";
+#[derive(Deserialize, Debug)]
+struct RateRecord {
+    #[serde(rename = "Date", deserialize_with = "parse_date")]
+    date: DateTime<Utc>,
+    #[serde(rename = "From")]
+    from: String,
+    #[serde(rename = "To")]
+    to: String,
+    #[serde(rename = "Rate")]
+    rate: Decimal,
+}
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
 
 fn parse_date<'de, D>(de: D) -> Result<DateTime<Utc>, D::Error>
 where
@@ -45,10 +63,127 @@ pub fn read_prices_csv<R>(io: R) -> Vec<Frame>
 where
     R: std::io::Read,
 {
-    let mut reader = csv::Reader::from_reader(io);
+    read_prices(io).collect()
+}
+
+// Frames as they're read, e.g. from a stream still being written to
+pub fn read_prices<R>(io: R) -> impl Iterator<Item = Frame>
+where
+    R: std::io::Read,
+{
+    let reader = csv::Reader::from_reader(io);
 
     reader
-        .deserialize()
+        .into_deserialize()
         .flat_map(|line| -> Result<Frame, csv::Error> { Ok(frame_from(line?, dec!(5))) })
-        .collect()
+}
+
+// Follows a prices file being appended to, reading only the lines added since the last read
+pub struct PricesFile {
+    path: PathBuf,
+    offset: u64, // end of the last complete line read
+}
+
+impl PricesFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset: 0,
+        }
+    }
+
+    // Frames of the complete lines added since the last read, a line still being written
+    // is left for the next one
+    pub fn read(&mut self) -> Result<Vec<Frame>, String> {
+        self.read_lines()
+            .map(|(header, lines)| read_prices_csv(header.as_bytes().chain(&lines[..])))
+            .map_err(|e| format!("Cannot read {}: {}", self.path.display(), e))
+    }
+
+    fn read_lines(&mut self) -> io::Result<(String, Vec<u8>)> {
+        let mut file = File::open(&self.path)?;
+
+        let mut header = String::new();
+        BufReader::new(&file).read_line(&mut header)?;
+        if !header.ends_with('\n') {
+            return Ok((header, vec![]));
+        }
+
+        let start = self.offset.max(header.len() as u64);
+        let mut lines = vec![];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut lines)?;
+
+        let complete = lines.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        lines.truncate(complete);
+        self.offset = start + complete as u64;
+
+        Ok((header, lines))
+    }
+}
+
+// Exchange rates, one per line - units of the To currency for one unit of the From one
+pub fn read_rates_csv<R>(io: R) -> Result<RateHistory, String>
+where
+    R: std::io::Read,
+{
+    let mut rates = RateHistory::new();
+    let mut reader = csv::Reader::from_reader(io);
+
+    for line in reader.deserialize() {
+        let record: RateRecord = line.map_err(|e| format!("Invalid rate: {}", e))?;
+
+        rates.insert(
+            parse_currency(&record.from)?,
+            parse_currency(&record.to)?,
+            record.date,
+            record.rate,
+        );
+    }
+
+    Ok(rates)
+}
+
+pub fn parse_currency(code: &str) -> Result<Currency, String> {
+    Currency::from_code(code.trim()).ok_or_else(|| format!("Unknown currency {}", code))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn reads_the_complete_lines_added_since_the_last_read() {
+        let path = env::temp_dir().join(format!("prices-{}.csv", std::process::id()));
+        let append = |text: &str| {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+        let _ = fs::remove_file(&path);
+        let mut prices = PricesFile::new(&path);
+
+        append("Date,Open,High,Low,Close,Volume\n");
+        append("2018-01-02T20:00:00,12897.69,12924.16,12745.15,12871.39,0\n");
+        append("2018-01-03T20:00:00,12916.18,13023");
+        let first = prices.read().unwrap();
+
+        append(".59,12893.05,12978.21,0\n");
+        let second = prices.read().unwrap();
+        let third = prices.read().unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].high.mid_price(), dec!(13023.59));
+        assert!(third.is_empty());
+    }
 }
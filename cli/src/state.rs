@@ -0,0 +1,44 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use betty::account::AccountSnapshot;
+use betty::price::CurrencyAmount;
+
+// Paper trading account persisted between runs
+//
+// The paper account keeps all of the price history, so its snapshot is enough to warm
+// the strategies up again and carry on exactly where they stopped.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PaperState {
+    pub opening_balance: CurrencyAmount,
+    pub next_id: usize, // of the simulated broker
+    pub account: AccountSnapshot,
+}
+
+impl PaperState {
+    // State saved in a file, if there is one
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file =
+            File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map(Some)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+    }
+
+    // Writes the state next to the file first and swaps it in, so the file is never left
+    // half written
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let temp = path.with_extension("tmp");
+        let json = serde_json::to_string(self).map_err(|e| format!("Cannot save state: {}", e))?;
+
+        fs::write(&temp, json).map_err(|e| format!("Cannot write {}: {}", temp.display(), e))?;
+        fs::rename(&temp, path).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,75 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use betty::optimise::Evaluation;
+
+#[derive(Serialize, Debug)]
+struct EvaluationRecord {
+    short: usize,
+    long: usize,
+    signal: usize,
+    entry_lim: Decimal,
+    exit_lim: Decimal,
+    channel_length: usize,
+    risk_per_trade: Decimal,
+    score: Option<Decimal>,
+    total_return: Decimal,
+    cagr: Option<Decimal>,
+    max_drawdown: Decimal,
+    max_drawdown_pct: Decimal,
+    max_drawdown_days: i64,
+    trades: usize,
+    win_rate: Option<Decimal>,
+    average_r: Option<Decimal>,
+    expectancy: Option<Decimal>,
+    profit_factor: Option<Decimal>,
+    sharpe: Option<Decimal>,
+    sortino: Option<Decimal>,
+    longest_losing_streak: usize,
+    exposure: Decimal,
+}
+
+fn record_from(evaluation: &Evaluation) -> EvaluationRecord {
+    let parameters = &evaluation.parameters;
+    let report = &evaluation.report;
+
+    EvaluationRecord {
+        short: parameters.short,
+        long: parameters.long,
+        signal: parameters.signal,
+        entry_lim: parameters.entry_lim,
+        exit_lim: parameters.exit_lim,
+        channel_length: parameters.channel_length,
+        risk_per_trade: parameters.risk_per_trade,
+        score: evaluation.score,
+        total_return: report.total_return,
+        cagr: report.cagr,
+        max_drawdown: report.max_drawdown.amount,
+        max_drawdown_pct: report.max_drawdown_pct,
+        max_drawdown_days: report.max_drawdown_duration.num_days(),
+        trades: report.trades,
+        win_rate: report.win_rate,
+        average_r: report.average_r,
+        expectancy: report.expectancy.map(|e| e.amount),
+        profit_factor: report.profit_factor,
+        sharpe: report.sharpe,
+        sortino: report.sortino,
+        longest_losing_streak: report.longest_losing_streak,
+        exposure: report.exposure,
+    }
+}
+
+pub fn write_evaluations_csv<W>(io: W, evaluations: &[Evaluation]) -> Result<(), csv::Error>
+where
+    W: std::io::Write,
+{
+    let mut writer = csv::Writer::from_writer(io);
+
+    for evaluation in evaluations {
+        writer.serialize(record_from(evaluation))?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
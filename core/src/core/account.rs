@@ -1,14 +1,22 @@
-use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::error::Error;
 use std::fmt::Display;
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
+#[cfg(feature = "serde")]
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde_json::{json, Value};
 
+use crate::core::fx::{FxError, RateSource, SingleCurrency};
 use crate::core::market::Market;
-use crate::core::price::{CurrencyAmount, Frame, Price, PriceHistory, Resolution};
-use crate::core::strategy::{RiskStrategy, TradingStrategy, Trend};
-use crate::core::trade::{Direction, Entry, Order, Trade};
+use crate::core::price::{
+    CurrencyAmount, CurrencyError, DerivedHistory, Frame, Price, PriceHistory, Resolution,
+};
+use crate::core::strategy::{Context, RiskStrategy, TradingStrategy, Trend};
+use crate::core::trade::{Direction, Entry, Exit, Order, Position, Trade};
 
 // Account holds the state of the trading account and history of all the orders placed
 // in response to price updates.
@@ -23,8 +31,13 @@ where
     pub trading_strategy: TS,
     pub risk_strategy: RS,
     pub risk_per_trade: Decimal,
+    derived_histories: Vec<DerivedHistory>, // other resolutions the strategies need
+    pub pyramiding: Pyramiding,
+    pub guaranteed_stops: bool, // placed on the entries, where the market offers them
+    pub rates: Arc<dyn RateSource>, // to convert profits into the currency of the balance
+    rate: Option<Decimal>,      // latest one from the market's currency into the balance's
     closed_trades: Vec<Trade>,
-    live_trade: Option<Entry>,
+    live_trades: Vec<Position>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -32,6 +45,8 @@ pub enum AccountError {
     DuplicateEntry(String),
     NoMatchingEntry(String),
     PositionAlreadyClosed(String),
+    Fx(FxError),             // no exchange rate to value a position with
+    Currency(CurrencyError), // amounts in different currencies added up
 }
 
 impl Error for AccountError {}
@@ -42,10 +57,227 @@ impl Display for AccountError {
             AccountError::DuplicateEntry(s) => writeln!(f, "Duplicate position {}", s),
             AccountError::NoMatchingEntry(s) => writeln!(f, "No matching entry {}", s),
             AccountError::PositionAlreadyClosed(s) => writeln!(f, "Position {} alerady closed", s),
+            AccountError::Fx(e) => writeln!(f, "{}", e),
+            AccountError::Currency(e) => writeln!(f, "{}", e),
         }
     }
 }
 
+impl From<FxError> for AccountError {
+    fn from(error: FxError) -> Self {
+        AccountError::Fx(error)
+    }
+}
+
+impl From<CurrencyError> for AccountError {
+    fn from(error: CurrencyError) -> Self {
+        AccountError::Currency(error)
+    }
+}
+
+// Rules for adding units to a winning position
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Pyramiding {
+    pub max_units: usize,  // open positions at a time, including the first
+    pub step: Decimal, // move in favour of the latest unit before adding the next one, in multiples of its initial risk
+    pub max_risk: Decimal, // total risk of the open positions, as a fraction of the balance
+}
+
+impl Pyramiding {
+    pub fn new(max_units: usize, step: Decimal, max_risk: Decimal) -> Self {
+        Self {
+            max_units,
+            step,
+            max_risk,
+        }
+    }
+
+    // A single position at a time
+    pub fn none() -> Self {
+        Self::new(1, Decimal::ZERO, Decimal::ONE)
+    }
+
+    // Whether the price moved far enough in favour of an entry to add another unit
+    fn step_reached(&self, entry: &Entry, price: Price) -> bool {
+        let initial_risk = (entry.price - entry.stop).abs();
+        let moved = match entry.direction {
+            Direction::Buy => price.bid - entry.price,
+            Direction::Sell => entry.price - price.ask,
+        };
+
+        moved >= initial_risk * self.step
+    }
+}
+
+// Version of the snapshot format, bumped on any change to it
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+// State of an account, without the strategies
+//
+// Snapshots saved in an earlier version of the format are migrated as they're read
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(remote = "Self")
+)]
+pub struct AccountSnapshot {
+    pub version: u32,
+    pub market: Market,
+    pub resolution: Resolution,
+    pub risk_per_trade: Decimal,
+    pub balance: CurrencyAmount,
+    pub price_history: Vec<Frame>,
+    pub live_trades: Vec<Position>,
+    pub closed_trades: Vec<Trade>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    UnsupportedVersion(u32),
+    ResolutionMismatch(Resolution),
+}
+
+impl Error for SnapshotError {}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported snapshot version {}", v)
+            }
+            SnapshotError::ResolutionMismatch(r) => {
+                write!(f, "Snapshot of an account trading at {:?}", r)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for AccountSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AccountSnapshot::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AccountSnapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // The version decides how to read the rest
+        let mut snapshot = Value::deserialize(deserializer)?;
+        migrate(&mut snapshot).map_err(D::Error::custom)?;
+
+        AccountSnapshot::deserialize(snapshot).map_err(D::Error::custom)
+    }
+}
+
+// Brings a snapshot saved in an earlier version of the format up to the current one
+#[cfg(feature = "serde")]
+fn migrate(snapshot: &mut Value) -> Result<(), SnapshotError> {
+    let mut version = match snapshot.get("version").and_then(Value::as_u64) {
+        Some(version) => version as u32,
+        None => return Ok(()), // left for the format to reject
+    };
+
+    while version != SNAPSHOT_VERSION {
+        match version {
+            1 => {
+                // Only one position could be open before version 2
+                let live_trade = snapshot
+                    .as_object_mut()
+                    .and_then(|s| s.remove("live_trade"))
+                    .filter(|p| !p.is_null());
+                snapshot["live_trades"] = json!(live_trade.into_iter().collect::<Vec<_>>());
+            }
+            2 => {
+                // No funding was charged before version 3
+                for (trades, currency) in &[
+                    ("live_trades", "/entry/size/currency"),
+                    ("closed_trades", "/profit/currency"),
+                ] {
+                    for trade in snapshot[*trades].as_array_mut().into_iter().flatten() {
+                        let currency = trade.pointer(currency).cloned().unwrap_or(Value::Null);
+                        trade["funding"] = json!({ "amount": "0", "currency": currency });
+                    }
+                }
+            }
+            _ => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+
+        version += 1;
+    }
+
+    snapshot["version"] = json!(SNAPSHOT_VERSION);
+    Ok(())
+}
+
+// Mark-to-market snapshot of the account at a point in time
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Equity {
+    pub time: DateTime<Utc>,
+    pub balance: CurrencyAmount,     // realised, changes as trades close
+    pub open_pnl: CurrencyAmount,    // unrealised profit of the open positions
+    pub margin_used: CurrencyAmount, // held by the open positions
+    pub free_margin: CurrencyAmount, // available to open new positions
+}
+
+impl Equity {
+    // Value of the account if the open positions were closed
+    pub fn value(&self) -> CurrencyAmount {
+        CurrencyAmount::new(
+            self.balance.amount + self.open_pnl.amount,
+            self.balance.currency,
+        )
+    }
+
+    // Value of the account as a fraction of the margin held, None without any held
+    pub fn margin_level(&self) -> Option<Decimal> {
+        self.value().amount.checked_div(self.margin_used.amount)
+    }
+}
+
+// When the broker acts on the margin level of the account
+//
+// Below the warning level the broker calls for more funds, below the liquidation level
+// it closes positions, the biggest loss first, until the level is back above it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MarginPolicy {
+    pub warning: Decimal, // margin level, e.g. 1 when the value is the margin held
+    pub liquidation: Decimal, // margin level, below the warning
+}
+
+impl MarginPolicy {
+    pub fn new(warning: Decimal, liquidation: Decimal) -> Self {
+        Self {
+            warning,
+            liquidation,
+        }
+    }
+
+    // Margin call at 100%, closing positions at 50%, like IG
+    pub fn ig() -> Self {
+        Self::new(Decimal::ONE, Decimal::new(5, 1))
+    }
+
+    // What the broker does about a margin level, if anything
+    pub fn call(&self, equity: &Equity) -> Option<MarginCall> {
+        match equity.margin_level()? {
+            level if level < self.liquidation => Some(MarginCall::Liquidation(*equity)),
+            level if level < self.warning => Some(MarginCall::Warning(*equity)),
+            _ => None,
+        }
+    }
+}
+
+// Margin level of the account falling below the thresholds of the policy
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MarginCall {
+    Warning(Equity),
+    Liquidation(Equity), // before the positions were closed
+}
+
 impl<TS, RS> Account<TS, RS>
 where
     TS: TradingStrategy,
@@ -59,18 +291,48 @@ where
         opening_balance: CurrencyAmount,
         resolution: Resolution,
     ) -> Self {
+        // Only keep as much history as the strategies need, at the account's resolution
+        // enough to warm them up again when restoring a snapshot
+        let longest = |ts: Option<usize>, rs: Option<usize>| Some(ts?.max(rs?));
+        let history = |resolution, length: Option<usize>| match length {
+            Some(length) => PriceHistory::bounded(resolution, length),
+            None => PriceHistory::new(resolution),
+        };
+        let history_length = longest(
+            trading_strategy.history_length(),
+            risk_strategy.history_length(),
+        );
+        let warm_up_length = longest(
+            trading_strategy.warm_up_length(resolution),
+            risk_strategy.warm_up_length(resolution),
+        );
+
+        let mut resolutions = trading_strategy.resolutions();
+        resolutions.extend(risk_strategy.resolutions());
+
+        let mut derived_histories: Vec<DerivedHistory> = vec![];
+        for r in resolutions {
+            if r != resolution && !derived_histories.iter().any(|d| d.history.resolution == r) {
+                derived_histories.push(DerivedHistory::new(history(r, history_length)));
+            }
+        }
+
+        let price_history = history(resolution, longest(history_length, warm_up_length));
+
         Account {
             balance: opening_balance,
             market,
             trading_strategy,
             risk_strategy,
             risk_per_trade,
-            price_history: PriceHistory {
-                resolution,
-                history: VecDeque::new(),
-            },
+            price_history,
+            derived_histories,
+            pyramiding: Pyramiding::none(),
+            guaranteed_stops: false,
+            rates: Arc::new(SingleCurrency),
+            rate: None,
             closed_trades: vec![],
-            live_trade: None,
+            live_trades: vec![],
         }
     }
 
@@ -79,64 +341,228 @@ where
             .closed_trades
             .iter()
             .cloned()
-            .chain(
-                self.live_trade
-                    .as_ref()
-                    .map(|e| Trade::open(&e, latest_price)),
-            )
+            .chain(self.live_trades.iter().map(|p| {
+                self.trade_in_balance_currency(
+                    Trade {
+                        stop_history: p.stop_history.clone(),
+                        ..Trade::open(&p.entry, latest_price)
+                    }
+                    .with_funding(p.funding),
+                )
+            }))
             .collect();
 
-        trades.sort_by(|a, b| a.entry_time.cmp(&b.entry_time));
+        trades.sort_by_key(|t| t.entry_time);
 
         trades
     }
 
+    // Value the account at the close price of a frame
+    //
+    // Fails for positions in another currency than the balance's before there's a rate
+    // to convert them at, e.g. straight after restoring a snapshot.
+    pub fn equity(&self, frame: &Frame) -> Result<Equity, AccountError> {
+        let mut open_pnl = CurrencyAmount::new(Decimal::ZERO, self.balance.currency);
+        let mut margin_used = CurrencyAmount::new(Decimal::ZERO, self.balance.currency);
+        for position in &self.live_trades {
+            let profit = Trade::open(&position.entry, frame.close).profit;
+            let margin = (self.market).margin(position.entry.size, frame.close.mid_price());
+
+            open_pnl = (open_pnl + self.in_balance_currency(profit))?;
+            margin_used = (margin_used + self.in_balance_currency(margin))?;
+        }
+
+        Ok(Equity {
+            time: frame.close_time,
+            balance: self.balance,
+            open_pnl,
+            margin_used,
+            free_margin: CurrencyAmount::new(
+                self.balance.amount + open_pnl.amount - margin_used.amount,
+                self.balance.currency,
+            ),
+        })
+    }
+
+    // Amount in the currency of the balance, at the latest exchange rate
+    //
+    // Positions aren't opened without a rate, so there's always one for their amounts.
+    pub fn in_balance_currency(&self, amount: CurrencyAmount) -> CurrencyAmount {
+        match self.rate {
+            Some(rate) if amount.currency != self.balance.currency => {
+                amount.convert(self.balance.currency, rate)
+            }
+            _ => amount,
+        }
+    }
+
+    // Amount in the currency of the market, at the latest exchange rate
+    fn in_market_currency(&self, amount: CurrencyAmount) -> Option<CurrencyAmount> {
+        let currency = self.market.currency();
+        if amount.currency == currency {
+            return Some(amount);
+        }
+
+        self.rate
+            .and_then(|rate| Decimal::ONE.checked_div(rate))
+            .map(|rate| amount.convert(currency, rate))
+    }
+
+    fn trade_in_balance_currency(&self, trade: Trade) -> Trade {
+        match self.rate {
+            Some(rate) if trade.profit.currency != self.balance.currency => {
+                trade.in_currency(self.balance.currency, rate)
+            }
+            _ => trade,
+        }
+    }
+
+    // Charge the open positions funding for the nights since the latest price
+    //
+    // The positions are valued at the latest close. The funding is taken off the balance
+    // straight away, the total charged is returned in the currency of the balance.
+    pub fn roll_over(&mut self, time: DateTime<Utc>) -> Result<CurrencyAmount, AccountError> {
+        let mut charged = CurrencyAmount::new(Decimal::ZERO, self.balance.currency);
+        let (funding, latest) = match (self.market.funding, self.price_history.latest()) {
+            (Some(funding), Some(latest)) => (funding, *latest),
+            _ => return Ok(charged),
+        };
+
+        let costs: Vec<CurrencyAmount> = self
+            .live_trades
+            .iter()
+            .map(|p| funding.cost(&p.entry, latest.close.mid_price(), latest.close_time, time))
+            .collect();
+        let mut funded = Vec::with_capacity(costs.len());
+        for (position, cost) in self.live_trades.iter().zip(&costs) {
+            charged = (charged + self.in_balance_currency(*cost))?;
+            funded.push((position.funding + *cost)?);
+        }
+        for (position, funding) in self.live_trades.iter_mut().zip(funded) {
+            position.funding = funding;
+        }
+
+        self.balance =
+            CurrencyAmount::new(self.balance.amount - charged.amount, self.balance.currency);
+
+        Ok(charged)
+    }
+
+    // Market data available to the strategies
+    pub fn context(&self) -> Context<'_> {
+        Context::with_derived(&self.price_history, &self.derived_histories)
+    }
+
     // Add new price information
     // This potentially results in new orders to be executed
     pub fn update_price(&mut self, frame: Frame) -> Vec<Order> {
-        self.price_history.history.push_front(frame);
+        self.price_history.push(frame);
+        for derived in &mut self.derived_histories {
+            derived.push(frame);
+        }
+
+        if let Ok(rate) = self.rates.rate(
+            self.market.currency(),
+            self.balance.currency,
+            frame.close_time,
+        ) {
+            self.rate = Some(rate);
+        }
+
+        let context = Context::with_derived(&self.price_history, &self.derived_histories);
+        self.trading_strategy.update(&context);
+        self.risk_strategy.update(&context);
 
         let time = frame.close_time;
-        let trend = self.trading_strategy.trend(&self.price_history);
+        let trend = self.trading_strategy.trend(&context);
 
         let mut orders = vec![];
+        let mut staying = vec![];
+
+        // Handle exits first, each position has its own stop
+        for lt in &self.live_trades {
+            let entry = &lt.entry;
 
-        // Handle exits first
-        if let Some(lt) = &self.live_trade {
             match trend {
                 // Stop - thes are only in the match so we don't generate both stop and close at the same time
-                _ if lt.direction == Direction::Buy && frame.low.bid < lt.stop => {
-                    orders.push(Order::Stop(lt.exit(frame.close, time)));
+                // The stop is placed at its level, how it's filled is up to the broker
+                _ if entry.direction == Direction::Buy && frame.low.bid < lt.stop => {
+                    orders.push(Order::Stop(lt.stopped(time)));
                 }
-                _ if lt.direction == Direction::Sell && frame.high.ask > lt.stop => {
-                    orders.push(Order::Stop(lt.exit(frame.close, time)));
+                _ if entry.direction == Direction::Sell && frame.high.ask > lt.stop => {
+                    orders.push(Order::Stop(lt.stopped(time)));
                 }
                 // Exit
                 Trend::Neutral => {
-                    orders.push(Order::Close(lt.exit(frame.close, time)));
+                    orders.push(Order::Close(entry.exit(frame.close, time)));
                 }
                 // Reverse
-                Trend::Bullish if lt.direction == Direction::Sell => {
-                    orders.push(Order::Close(lt.exit(frame.close, time)));
+                Trend::Bullish if entry.direction == Direction::Sell => {
+                    orders.push(Order::Close(entry.exit(frame.close, time)));
                 }
-                Trend::Bearish if lt.direction == Direction::Buy => {
-                    orders.push(Order::Close(lt.exit(frame.close, time)));
+                Trend::Bearish if entry.direction == Direction::Buy => {
+                    orders.push(Order::Close(entry.exit(frame.close, time)));
                 }
                 // Stay
-                _ => (),
+                _ => staying.push(lt),
+            }
+        }
+
+        let exiting = !orders.is_empty();
+
+        // Trail the stops of positions staying open
+        if !exiting {
+            for lt in &staying {
+                if let Some(stop) = self.risk_strategy.trailing_stop(lt, &context) {
+                    // The stop can't be placed beyond the current price
+                    let valid = match lt.entry.direction {
+                        Direction::Buy => stop < frame.close.bid,
+                        Direction::Sell => stop > frame.close.ask,
+                    };
+
+                    if valid && lt.is_tighter(stop) {
+                        orders.push(Order::Amend(lt.entry.amend(stop, time)));
+                    }
+                }
             }
         }
 
-        if self.live_trade.is_none() || orders.len() > 0 {
+        // Enter when out of the market, or add a unit to the positions staying open
+        let entering = match staying.last() {
+            None => true,
+            Some(latest) => {
+                staying.len() < self.pyramiding.max_units
+                    && self.pyramiding.step_reached(&latest.entry, frame.close)
+            }
+        };
+
+        if entering {
             match trend {
                 Trend::Bullish | Trend::Bearish => {
-                    let risk = self.balance * self.risk_per_trade;
+                    let open_risk = staying.iter().fold(Decimal::ZERO, |risk, lt| {
+                        risk + self.in_balance_currency(lt.open_risk()).amount
+                    });
+                    let limit = self.balance.amount * self.pyramiding.max_risk - open_risk;
+                    let risk = CurrencyAmount::new(
+                        (self.balance * self.risk_per_trade).amount.min(limit),
+                        self.balance.currency,
+                    );
                     let dir = trend
                         .try_into()
                         .expect("Trend could not convert to direction");
 
-                    if let Ok(entry) = self.risk_strategy.entry(dir, &self.price_history, risk) {
-                        orders.push(Order::Open(entry));
+                    // Sized in the currency of the market
+                    if let Some(risk) = self
+                        .in_market_currency(risk)
+                        .filter(|risk| risk.amount > Decimal::ZERO)
+                    {
+                        if let Ok(entry) = self.risk_strategy.entry(dir, &context, risk) {
+                            if self.guaranteed_stops {
+                                orders.push(Order::Open(self.market.guarantee(entry)));
+                            } else {
+                                orders.push(Order::Open(entry));
+                            }
+                        }
                     }
                 }
                 _ => (),
@@ -148,32 +574,165 @@ where
 
     // Log an order that has been placed
     pub fn log_order(&mut self, order: Order) -> Result<(), AccountError> {
-        match (order, &self.live_trade) {
-            (Order::Open(entry), None) => {
-                self.live_trade = Some(entry);
+        match order {
+            Order::Open(entry) => {
+                if self.live_trade(&entry.position_id).is_some() {
+                    return Err(AccountError::DuplicateEntry(entry.position_id));
+                }
 
-                return Ok(());
-            }
-            (Order::Open(_), Some(entry)) => {
-                return Err(AccountError::DuplicateEntry(entry.position_id.clone()));
+                if self.rate.is_none() {
+                    self.rate = Some(self.rates.rate(
+                        entry.size.currency,
+                        self.balance.currency,
+                        entry.time,
+                    )?);
+                }
+
+                self.live_trades.push(Position::new(entry));
+
+                Ok(())
             }
-            (Order::Close(exit) | Order::Stop(exit), None) => {
-                if self.closed_trades.iter().any(|t| t.id == exit.position_id) {
-                    return Err(AccountError::PositionAlreadyClosed(
-                        exit.position_id.clone(),
-                    ));
-                } else {
-                    return Err(AccountError::NoMatchingEntry(exit.position_id.clone()));
+            Order::Close(exit) | Order::Liquidate(exit) => self.log_exit(exit, Trade::closed),
+            Order::Stop(exit) => self.log_exit(exit, Trade::stopped),
+            Order::Amend(amendment) => {
+                let position_id = amendment.position_id.clone();
+
+                match self.live_trade_mut(&position_id) {
+                    Some(position) => {
+                        position.amend(amendment);
+
+                        Ok(())
+                    }
+                    None => Err(self.missing_position(position_id)),
                 }
             }
-            (Order::Close(exit) | Order::Stop(exit), Some(entry)) => {
-                let trade = Trade::closed(&entry, &exit);
-                self.balance += trade.profit;
-                self.live_trade = None;
+        }
+    }
+
+    // Close a position with the trade an exit makes of it
+    fn log_exit(
+        &mut self,
+        exit: Exit,
+        trade: fn(&Entry, &Exit) -> Trade,
+    ) -> Result<(), AccountError> {
+        let index = self
+            .live_trades
+            .iter()
+            .position(|p| p.entry.position_id == exit.position_id)
+            .ok_or_else(|| self.missing_position(exit.position_id.clone()))?;
+
+        // The profit goes into the balance at the rate of the close
+        let rate = self.rates.rate(
+            self.live_trades[index].entry.size.currency,
+            self.balance.currency,
+            exit.time,
+        )?;
+        let position = &self.live_trades[index];
+
+        let trade = Trade {
+            stop_history: position.stop_history.clone(),
+            ..trade(&position.entry, &exit)
+        }
+        .with_funding(position.funding)
+        .in_currency(self.balance.currency, rate);
+        // The funding has been taken off the balance already
+        self.balance = ((self.balance + trade.profit)? + trade.funding)?;
+        self.live_trades.remove(index);
+        self.closed_trades.push(trade);
+
+        Ok(())
+    }
+
+    // Open positions, in the order they were entered
+    pub fn live_trades(&self) -> &[Position] {
+        &self.live_trades
+    }
+
+    pub fn live_trade(&self, position_id: &str) -> Option<&Position> {
+        self.live_trades
+            .iter()
+            .find(|p| p.entry.position_id == position_id)
+    }
+
+    fn live_trade_mut(&mut self, position_id: &str) -> Option<&mut Position> {
+        self.live_trades
+            .iter_mut()
+            .find(|p| p.entry.position_id == position_id)
+    }
 
-                return Ok(self.closed_trades.push(trade));
+    pub fn closed_trades(&self) -> &[Trade] {
+        &self.closed_trades
+    }
+
+    // Change the size of a live trade, e.g. after it was partially closed at the broker
+    pub(crate) fn resize_live_trade(
+        &mut self,
+        position_id: &str,
+        size: CurrencyAmount,
+    ) -> Result<(), AccountError> {
+        match self.live_trade_mut(position_id) {
+            Some(position) => {
+                position.entry.size = size;
+
+                Ok(())
             }
-        };
+            None => Err(self.missing_position(position_id.to_string())),
+        }
+    }
+
+    // Current state of the account, to be saved and restored later
+    pub fn snapshot(&self) -> AccountSnapshot {
+        AccountSnapshot {
+            version: SNAPSHOT_VERSION,
+            market: self.market.clone(),
+            resolution: self.price_history.resolution,
+            risk_per_trade: self.risk_per_trade,
+            balance: self.balance,
+            price_history: self.price_history.frames().to_vec(),
+            live_trades: self.live_trades.clone(),
+            closed_trades: self.closed_trades.clone(),
+        }
+    }
+
+    // Carry on from a snapshot of an account trading with the same strategies
+    //
+    // The strategies are warmed up on the snapshot's prices newer than any the account
+    // has already seen. The snapshot keeps as many frames as the strategies need to warm
+    // up, indicators smoothing over all of the history are rebuilt to within the error
+    // they allow. To pick up exactly where the snapshot was taken, warm the account up on
+    // the full history first.
+    pub fn restore(&mut self, snapshot: AccountSnapshot) -> Result<(), SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        if snapshot.resolution != self.price_history.resolution {
+            return Err(SnapshotError::ResolutionMismatch(snapshot.resolution));
+        }
+
+        let latest = self.price_history.latest().map(|f| f.close_time);
+        // is_none_or would need Rust 1.82
+        #[allow(clippy::unnecessary_map_or)]
+        for frame in snapshot.price_history {
+            if latest.map_or(true, |time| frame.close_time > time) {
+                self.update_price(frame);
+            }
+        }
+
+        self.market = snapshot.market;
+        self.risk_per_trade = snapshot.risk_per_trade;
+        self.balance = snapshot.balance;
+        self.live_trades = snapshot.live_trades;
+        self.closed_trades = snapshot.closed_trades;
+
+        Ok(())
+    }
+
+    fn missing_position(&self, position_id: String) -> AccountError {
+        if self.closed_trades.iter().any(|t| t.id == position_id) {
+            AccountError::PositionAlreadyClosed(position_id)
+        } else {
+            AccountError::NoMatchingEntry(position_id)
+        }
     }
 }
 
@@ -181,13 +740,18 @@ where
 mod test {
     use super::*;
 
+    use crate::core::fx::RateHistory;
+    use crate::core::market::{Funding, GuaranteedStop};
     use crate::core::price::{Points, Price};
     use crate::core::strategy::RiskStrategyError;
-    use crate::core::trade::{Direction, Entry, Exit, TradeOutcome, TradeStatus};
+    use crate::core::trade::{
+        Amendment, Direction, Entry, Exit, StopType, TradeOutcome, TradeStatus,
+    };
+    use crate::strategies::{Donchian, MACD};
     use crate::strategy::Trend;
 
-    use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
-    use iso_currency::Currency::GBP;
+    use chrono::{DateTime, Duration, TimeZone, Timelike, Utc, Weekday};
+    use iso_currency::Currency::{EUR, GBP};
     use rust_decimal_macros::dec;
 
     // Trading
@@ -204,9 +768,37 @@ mod test {
         };
         account.update_price(expected);
 
-        let actual = account.price_history.history[0];
+        let actual = account.price_history.latest();
 
-        assert_eq!(actual, expected);
+        assert_eq!(actual, Some(&expected));
+    }
+
+    #[test]
+    fn keeps_history_at_resolutions_strategies_need() {
+        let mut account = Account::new(
+            market(),
+            Hourly {},
+            risk_strategy(),
+            dec!(0.01),
+            CurrencyAmount::new(dec!(1000), GBP),
+            Resolution::Minute(10),
+        );
+
+        for i in 1..=13 {
+            account.update_price(Frame {
+                close_time: date().with_minute(0).unwrap() + Duration::minutes(10 * i),
+                ..frame()
+            });
+        }
+
+        let context = account.context();
+
+        assert_eq!(context.history.len(), 13);
+        assert_eq!(
+            context.history(Resolution::Hour(1)).map(|h| h.len()),
+            Some(2)
+        );
+        assert!(context.history(Resolution::Day).is_none());
     }
 
     #[test]
@@ -218,6 +810,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(2), GBP),
             time: date(),
         };
@@ -234,6 +827,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -250,11 +844,13 @@ mod test {
         let actual = account.update_price(price);
         let expected = vec![Order::Stop(Exit {
             position_id: "2".to_string(),
-            price: dec!(199.5),
+            price: dec!(90),
             time: date() + Duration::minutes(10),
         })];
 
-        Ok(assert_eq!(actual, expected))
+        assert_eq!(actual, expected);
+
+        Ok(())
     }
 
     #[test]
@@ -280,7 +876,7 @@ mod test {
 
         let expected_long = vec![Order::Open(long_account.risk_strategy.entry(
             Direction::Buy,
-            &history(),
+            &Context::new(&history()),
             CurrencyAmount::new(dec!(10), GBP),
         )?)];
         let actual_long = long_account.update_price(frame());
@@ -289,7 +885,7 @@ mod test {
 
         let expected_long = vec![Order::Open(short_account.risk_strategy.entry(
             Direction::Sell,
-            &history(),
+            &Context::new(&history()),
             CurrencyAmount::new(dec!(10), GBP),
         )?)];
         let actual_long = short_account.update_price(frame());
@@ -315,6 +911,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(40),
             stop: dec!(30),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -334,6 +931,7 @@ mod test {
             direction: Direction::Sell,
             price: dec!(250),
             stop: dec!(260),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -376,6 +974,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(40),
             stop: dec!(30),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -397,6 +996,7 @@ mod test {
             direction: Direction::Sell,
             price: dec!(250),
             stop: dec!(260),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -415,7 +1015,7 @@ mod test {
                     .risk_strategy
                     .entry(
                         Direction::Sell,
-                        &history(),
+                        &Context::new(&history()),
                         CurrencyAmount::new(dec!(10), GBP),
                     )
                     .map_err(|_| ())?,
@@ -436,7 +1036,7 @@ mod test {
                     .risk_strategy
                     .entry(
                         Direction::Buy,
-                        &history(),
+                        &Context::new(&history()),
                         CurrencyAmount::new(dec!(10), GBP),
                     )
                     .map_err(|_| ())?,
@@ -451,6 +1051,67 @@ mod test {
 
     // Trade log
 
+    #[test]
+    fn trails_the_stop_of_an_open_position() -> Result<(), AccountError> {
+        let open = Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(40),
+            stop: dec!(30),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        };
+
+        let mut tighter = trailing_account(dec!(45));
+        tighter.log_order(Order::Open(open.clone()))?;
+        let expected = vec![Order::Amend(Amendment {
+            position_id: "1".to_string(),
+            stop: dec!(45),
+            time: date(),
+        })];
+
+        assert_eq!(tighter.update_price(frame()), expected);
+
+        // Stops are never loosened or placed beyond the current price
+        let mut looser = trailing_account(dec!(20));
+        looser.log_order(Order::Open(open.clone()))?;
+        assert_eq!(looser.update_price(frame()), vec![]);
+
+        let mut beyond = trailing_account(dec!(210));
+        beyond.log_order(Order::Open(open))?;
+        assert_eq!(beyond.update_price(frame()), vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stops_at_the_amended_level() -> Result<(), AccountError> {
+        let mut account = trailing_account(dec!(20));
+        let open = Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(40),
+            stop: dec!(30),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        };
+        account.log_order(Order::Open(open.clone()))?;
+        account.log_order(Order::Amend(open.amend(dec!(60), date())))?;
+
+        let expected = Order::Stop(Exit {
+            position_id: "1".to_string(),
+            price: dec!(60),
+            time: date(),
+        });
+
+        // The bullish strategy opens a new position straight after
+        assert_eq!(account.update_price(frame())[0], expected);
+
+        Ok(())
+    }
+
     #[test]
     fn gives_an_empty_trade_log_for_no_orders() {
         let account = account();
@@ -478,6 +1139,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -492,11 +1154,13 @@ mod test {
             exit_time: None,
             exit_price: None,
             stop: dec!(90),
+            stop_history: vec![],
             size: open.size,
             risk: CurrencyAmount::new(dec!(10), GBP),
             outcome: TradeOutcome::Profit,
             price_diff: dec!(10),
             profit: CurrencyAmount::new(dec!(10), GBP),
+            funding: CurrencyAmount::new(dec!(0), GBP),
             risk_reward: dec!(1.0),
         }];
         let actual = account.trade_log(latest_price);
@@ -519,6 +1183,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
@@ -540,11 +1205,13 @@ mod test {
             exit_time: Some(close.time),
             exit_price: Some(close.price),
             stop: open.stop,
+            stop_history: vec![],
             size: open.size,
             risk: CurrencyAmount::new(dec!(10), GBP),
             outcome: TradeOutcome::Profit,
             price_diff: dec!(50),
             profit: CurrencyAmount::new(dec!(50), GBP),
+            funding: CurrencyAmount::new(dec!(0), GBP),
             risk_reward: dec!(5.0),
         }];
         let actual = account.trade_log(latest_price);
@@ -568,6 +1235,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(2), GBP),
             time: date(),
         };
@@ -581,6 +1249,7 @@ mod test {
             direction: Direction::Sell,
             price: dec!(80),
             stop: dec!(85),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date() + Duration::minutes(20),
         };
@@ -594,6 +1263,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(70),
             stop: dec!(60),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(1), GBP),
             time: date() + Duration::minutes(40),
         };
@@ -617,35 +1287,115 @@ mod test {
         Ok(())
     }
 
-    // Order validation
-
     #[test]
-    fn does_not_allow_to_log_a_close_order_without_matching_open() -> Result<(), AccountError> {
+    fn logs_stop_amendments_of_a_trade() -> Result<(), AccountError> {
         let mut account = account();
+        let latest_price = Price {
+            bid: dec!(110),
+            ask: dec!(112),
+        };
 
-        let open_1 = Entry {
+        let open = Entry {
             position_id: "1".to_string(),
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
-            size: CurrencyAmount::new(dec!(2), GBP),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
             time: date(),
         };
-        let close_1 = Exit {
-            position_id: "1".to_string(),
-            price: dec!(89), // slippage
-            time: date() + Duration::minutes(10),
-        };
-        account.log_order(Order::Open(open_1))?;
-        account.log_order(Order::Stop(close_1))?;
+        let amendment_1 = open.amend(dec!(95), date() + Duration::minutes(10));
+        let amendment_2 = open.amend(dec!(105), date() + Duration::minutes(20));
+        account.log_order(Order::Open(open.clone()))?;
+        account.log_order(Order::Amend(amendment_1.clone()))?;
+        account.log_order(Order::Amend(amendment_2.clone()))?;
 
-        let close = Exit {
-            position_id: "3".to_string(),
-            price: dec!(89), // slippage
-            time: date() + Duration::minutes(10),
-        };
+        let open_trade = &account.trade_log(latest_price)[0];
 
-        assert_eq!(
+        assert_eq!(open_trade.stop, dec!(90));
+        assert_eq!(open_trade.stop_history, vec![amendment_1, amendment_2]);
+        assert_eq!(open_trade.last_stop(), dec!(105));
+
+        account.log_order(Order::Stop(Exit {
+            position_id: "1".to_string(),
+            price: dec!(105),
+            time: date() + Duration::minutes(30),
+        }))?;
+
+        let closed_trade = &account.trade_log(latest_price)[0];
+
+        assert_eq!(closed_trade.status, TradeStatus::Closed);
+        assert_eq!(closed_trade.risk, CurrencyAmount::new(dec!(10), GBP));
+        assert_eq!(closed_trade.last_stop(), dec!(105));
+        assert_eq!(closed_trade.stop_history.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn values_the_account_at_market_price() -> Result<(), AccountError> {
+        let mut account = account();
+
+        let flat = account.equity(&frame()).unwrap();
+
+        assert_eq!(flat.balance, CurrencyAmount::new(dec!(1000), GBP));
+        assert_eq!(flat.open_pnl, CurrencyAmount::new(dec!(0), GBP));
+        assert_eq!(flat.margin_used, CurrencyAmount::new(dec!(0), GBP));
+        assert_eq!(flat.free_margin, CurrencyAmount::new(dec!(1000), GBP));
+
+        account.log_order(Order::Open(Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(180),
+            stop: dec!(170),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(2), GBP),
+            time: date(),
+        }))?;
+
+        // Closes at 199.5 bid, 200 mid with 0.5 margin factor
+        let open = account.equity(&frame()).unwrap();
+
+        assert_eq!(open.time, date());
+        assert_eq!(open.balance, CurrencyAmount::new(dec!(1000), GBP));
+        assert_eq!(open.open_pnl, CurrencyAmount::new(dec!(39), GBP));
+        assert_eq!(open.margin_used, CurrencyAmount::new(dec!(200), GBP));
+        assert_eq!(open.free_margin, CurrencyAmount::new(dec!(839), GBP));
+        assert_eq!(open.value(), CurrencyAmount::new(dec!(1039), GBP));
+
+        Ok(())
+    }
+
+    // Order validation
+
+    #[test]
+    fn does_not_allow_to_log_a_close_order_without_matching_open() -> Result<(), AccountError> {
+        let mut account = account();
+
+        let open_1 = Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(100),
+            stop: dec!(90),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(2), GBP),
+            time: date(),
+        };
+        let close_1 = Exit {
+            position_id: "1".to_string(),
+            price: dec!(89), // slippage
+            time: date() + Duration::minutes(10),
+        };
+        account.log_order(Order::Open(open_1))?;
+        account.log_order(Order::Stop(close_1))?;
+
+        let close = Exit {
+            position_id: "3".to_string(),
+            price: dec!(89), // slippage
+            time: date() + Duration::minutes(10),
+        };
+
+        assert_eq!(
             Err(AccountError::NoMatchingEntry("3".to_string())),
             account.log_order(Order::Close(close.clone()))
         );
@@ -658,6 +1408,44 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn does_not_allow_to_amend_a_stop_without_matching_open() -> Result<(), AccountError> {
+        let mut account = account();
+
+        let amendment = Amendment {
+            position_id: "1".to_string(),
+            stop: dec!(95),
+            time: date(),
+        };
+
+        assert_eq!(
+            Err(AccountError::NoMatchingEntry("1".to_string())),
+            account.log_order(Order::Amend(amendment.clone()))
+        );
+
+        account.log_order(Order::Open(Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(100),
+            stop: dec!(90),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        }))?;
+        account.log_order(Order::Close(Exit {
+            position_id: "1".to_string(),
+            price: dec!(100),
+            time: date(),
+        }))?;
+
+        assert_eq!(
+            Err(AccountError::PositionAlreadyClosed("1".to_string())),
+            account.log_order(Order::Amend(amendment))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn rejects_an_order_with_duplicate_position_id() -> Result<(), AccountError> {
         let mut account = account();
@@ -667,6 +1455,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(2), GBP),
             time: date(),
         };
@@ -689,6 +1478,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(2), GBP),
             time: date(),
         };
@@ -705,6 +1495,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(100),
             stop: dec!(90),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(2), GBP),
             time: date(),
         };
@@ -736,38 +1527,556 @@ mod test {
         Ok(())
     }
 
+    // Pyramiding
+
+    #[test]
+    fn adds_units_to_a_winning_position() -> Result<(), AccountError> {
+        let mut account = pyramiding_account(Pyramiding::new(3, dec!(1), dec!(1)));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+
+        // Not far enough in favour yet, 1R is 10 points
+        assert_eq!(account.update_price(price_frame(dec!(105))), vec![]);
+
+        let orders = account.update_price(price_frame(dec!(111)));
+        let expected = Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(111.5),
+            stop: dec!(101.5),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        };
+        assert_eq!(orders, vec![Order::Open(expected)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn adds_units_up_to_the_maximum() -> Result<(), AccountError> {
+        let mut account = pyramiding_account(Pyramiding::new(3, dec!(1), dec!(1)));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+        account.log_order(Order::Open(unit("2", dec!(110), dec!(100))))?;
+        account.log_order(Order::Open(unit("3", dec!(120), dec!(110))))?;
+
+        assert_eq!(account.update_price(price_frame(dec!(200))), vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits_the_total_risk_of_the_units() -> Result<(), AccountError> {
+        // Risking 10, 1% of the balance, on the first unit
+        let mut account = pyramiding_account(Pyramiding::new(3, dec!(1), dec!(0.015)));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+
+        let orders = account.update_price(price_frame(dec!(111)));
+        assert!(
+            matches!(&orders[..], [Order::Open(entry)] if entry.size == CurrencyAmount::new(dec!(0.5), GBP))
+        );
+
+        // Nothing left to risk, until the stop is moved past the entry
+        let mut account = pyramiding_account(Pyramiding::new(3, dec!(1), dec!(0.01)));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+        assert_eq!(account.update_price(price_frame(dec!(111))), vec![]);
+
+        account.log_order(Order::Amend(
+            unit("1", dec!(100), dec!(90)).amend(dec!(100), date()),
+        ))?;
+        assert_eq!(account.update_price(price_frame(dec!(111))).len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stops_each_unit_at_its_own_stop() -> Result<(), AccountError> {
+        let mut account = pyramiding_account(Pyramiding::new(3, dec!(1), dec!(1)));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+        account.log_order(Order::Open(unit("2", dec!(110), dec!(105))))?;
+        assert_eq!(account.trade_log(price_frame(dec!(106)).close).len(), 2);
+
+        let frame = price_frame(dec!(106)); // low bid at 104.5
+        let expected = Order::Stop(Position::new(unit("2", dec!(110), dec!(105))).stopped(date()));
+        assert_eq!(account.update_price(frame), vec![expected.clone()]);
+
+        account.log_order(expected)?;
+        assert_eq!(account.closed_trades()[0].id, "2");
+        assert_eq!(account.live_trades()[0].entry.position_id, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn charges_the_premium_of_guaranteed_stops_when_hit() -> Result<(), AccountError> {
+        let mut account = pyramiding_account(Pyramiding::none());
+        account.market.guaranteed_stop = Some(GuaranteedStop::new(dec!(2), dec!(10)));
+        account.guaranteed_stops = true;
+
+        let orders = account.update_price(price_frame(dec!(100)));
+        let entry = match &orders[..] {
+            [Order::Open(entry)] => Entry {
+                position_id: "1".to_string(),
+                ..entry.clone()
+            },
+            _ => panic!("Expected an entry, got {:?}", orders),
+        };
+        assert_eq!(entry.stop_type, StopType::Guaranteed { premium: dec!(2) });
+
+        account.log_order(Order::Open(entry.clone()))?;
+        account.log_order(Order::Stop(Position::new(entry.clone()).stopped(date())))?;
+
+        // Risking 10 points, stopped out 10 points down and paying 2 more for the stop
+        assert_eq!(account.closed_trades()[0].profit.amount, dec!(-12));
+        assert_eq!(account.balance.amount, dec!(988));
+
+        // Closed before the stop is hit, there's no premium
+        let entry = Entry {
+            position_id: "2".to_string(),
+            ..entry
+        };
+        account.log_order(Order::Open(entry.clone()))?;
+        account.log_order(Order::Close(Exit {
+            position_id: "2".to_string(),
+            price: entry.price - dec!(5),
+            time: date(),
+        }))?;
+
+        assert_eq!(account.closed_trades()[1].profit.amount, dec!(-5));
+        assert_eq!(account.balance.amount, dec!(983));
+
+        Ok(())
+    }
+
+    #[test]
+    fn charges_funding_for_the_nights_held() -> Result<(), AccountError> {
+        let mut account = pyramiding_account(Pyramiding::none());
+        // 3.65% a year, a penny a night at 100
+        account.market.funding = Some(Funding::new(dec!(0.01), dec!(0.0265), Weekday::Fri));
+        account.log_order(Order::Open(unit("1", dec!(100), dec!(90))))?;
+        account.update_price(price_frame(dec!(100)));
+
+        // Held from Friday to Monday
+        let monday = date() + Duration::days(3);
+        assert_eq!(
+            account.roll_over(monday)?,
+            CurrencyAmount::new(dec!(0.03), GBP)
+        );
+        assert_eq!(account.balance.amount, dec!(999.97));
+
+        account.log_order(Order::Stop(
+            Position::new(unit("1", dec!(100), dec!(90))).stopped(monday),
+        ))?;
+
+        let trade = &account.closed_trades()[0];
+        assert_eq!(trade.funding, CurrencyAmount::new(dec!(0.03), GBP));
+        assert_eq!(trade.profit, CurrencyAmount::new(dec!(-10.03), GBP));
+        assert_eq!(account.balance.amount, dec!(989.97));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sizes_entries_in_the_currency_of_the_market() {
+        let mut account = fx_account();
+
+        let orders = account.update_price(price_frame(dec!(100)));
+
+        // 10 GBP of risk is 12.5 EUR at 0.8, over 10 points to the stop
+        assert_eq!(
+            orders,
+            vec![Order::Open(Entry {
+                position_id: String::new(),
+                direction: Direction::Buy,
+                price: dec!(100.5),
+                stop: dec!(90.5),
+                stop_type: StopType::Normal,
+                size: CurrencyAmount::new(dec!(1.25), EUR),
+                time: date(),
+            })]
+        );
+    }
+
+    #[test]
+    fn converts_profits_at_the_rate_of_the_close() -> Result<(), AccountError> {
+        let mut account = fx_account();
+        let entry = Entry {
+            size: CurrencyAmount::new(dec!(1), EUR),
+            ..unit("1", dec!(100), dec!(90))
+        };
+        account.update_price(price_frame(dec!(100)));
+        account.log_order(Order::Open(entry.clone()))?;
+
+        // 4.5 EUR open profit at 0.8
+        assert_eq!(
+            account.equity(&price_frame(dec!(105))).unwrap().open_pnl,
+            CurrencyAmount::new(dec!(3.6), GBP)
+        );
+
+        let exit = Exit {
+            position_id: "1".to_string(),
+            price: dec!(110),
+            time: date() + Duration::days(1),
+        };
+        account.log_order(Order::Close(exit))?;
+
+        // 10 EUR profit at 0.9
+        let trade = &account.closed_trades()[0];
+        assert_eq!(account.balance, CurrencyAmount::new(dec!(1009), GBP));
+        assert_eq!(trade.profit, CurrencyAmount::new(dec!(9), GBP));
+        assert_eq!(trade.risk, CurrencyAmount::new(dec!(9), GBP));
+        assert_eq!(trade.size, CurrencyAmount::new(dec!(1), EUR));
+
+        Ok(())
+    }
+
+    #[test]
+    fn needs_an_exchange_rate_for_positions() {
+        let mut account = fx_account();
+        account.rates = Arc::new(SingleCurrency);
+
+        let entry = Entry {
+            size: CurrencyAmount::new(dec!(1), EUR),
+            ..unit("1", dec!(100), dec!(90))
+        };
+
+        assert_eq!(account.update_price(price_frame(dec!(100))), vec![]);
+        assert_eq!(
+            account.log_order(Order::Open(entry)),
+            Err(AccountError::Fx(FxError::NoRate {
+                from: EUR,
+                to: GBP,
+                time: date()
+            }))
+        );
+    }
+
+    #[test]
+    fn does_not_value_positions_without_a_rate() -> Result<(), AccountError> {
+        let mut account = fx_account();
+        let entry = Entry {
+            size: CurrencyAmount::new(dec!(1), EUR),
+            ..unit("1", dec!(100), dec!(90))
+        };
+        account.log_order(Order::Open(entry))?;
+
+        // As restored from a snapshot, before the next price
+        account.rate = None;
+
+        assert_eq!(
+            account.equity(&price_frame(dec!(105))),
+            Err(AccountError::Currency(CurrencyError::Mismatch(GBP, EUR)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn calls_for_margin_below_the_levels_of_the_policy() {
+        let policy = MarginPolicy::ig();
+        let equity = |open_pnl| Equity {
+            time: date(),
+            balance: CurrencyAmount::new(dec!(1000), GBP),
+            open_pnl: CurrencyAmount::new(open_pnl, GBP),
+            margin_used: CurrencyAmount::new(dec!(400), GBP),
+            free_margin: CurrencyAmount::new(dec!(600) + open_pnl, GBP),
+        };
+
+        assert_eq!(equity(dec!(-500)).margin_level(), Some(dec!(1.25)));
+        assert_eq!(policy.call(&equity(dec!(-500))), None);
+        assert_eq!(
+            policy.call(&equity(dec!(-700))),
+            Some(MarginCall::Warning(equity(dec!(-700))))
+        );
+        assert_eq!(
+            policy.call(&equity(dec!(-900))),
+            Some(MarginCall::Liquidation(equity(dec!(-900))))
+        );
+        // Without any margin held
+        assert_eq!(account().equity(&frame()).unwrap().margin_level(), None);
+    }
+
+    #[test]
+    fn restores_a_snapshot() -> Result<(), AccountError> {
+        let mut account = trailing_account(dec!(45));
+        account.update_price(frame());
+        account.log_order(Order::Open(open()))?;
+        account.log_order(Order::Amend(open().amend(dec!(35), date())))?;
+
+        let snapshot = account.snapshot();
+        let mut restored = trailing_account(dec!(45));
+        restored.restore(snapshot.clone()).unwrap();
+
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.live_trade("1").unwrap().stop, dec!(35));
+        assert_eq!(
+            restored.trade_log(frame().close),
+            account.trade_log(frame().close)
+        );
+
+        let next = Frame {
+            close_time: date() + Duration::minutes(10),
+            ..frame()
+        };
+        assert_eq!(restored.update_price(next), account.update_price(next));
+
+        Ok(())
+    }
+
+    #[test]
+    fn warms_up_indicators_when_restoring_a_snapshot() -> Result<(), AccountError> {
+        let mut account = indicator_account();
+        let rising = |i: i64| Frame {
+            close_time: date() + Duration::days(i),
+            ..price_frame(dec!(1000) + Decimal::from(i * 10))
+        };
+        for i in 0..60 {
+            for order in account.update_price(rising(i)) {
+                account.log_order(order)?;
+            }
+        }
+        assert_eq!(account.live_trades().len(), 1);
+
+        let mut restored = indicator_account();
+        restored.restore(account.snapshot()).unwrap();
+
+        // Still bullish, the position stays open
+        let orders = restored.update_price(rising(60));
+        assert!(!orders.iter().any(|o| matches!(o, Order::Close(_))));
+        assert_eq!(orders, account.update_price(rising(60)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_replay_prices_already_seen() {
+        let mut account = trailing_account(dec!(45));
+        account.update_price(frame());
+        let snapshot = account.snapshot();
+
+        let mut restored = trailing_account(dec!(45));
+        restored.update_price(frame());
+        restored.restore(snapshot).unwrap();
+
+        assert_eq!(restored.price_history.len(), 1);
+    }
+
+    #[test]
+    fn rejects_incompatible_snapshots() {
+        let snapshot = AccountSnapshot {
+            version: SNAPSHOT_VERSION + 1,
+            ..account().snapshot()
+        };
+        assert_eq!(
+            trailing_account(dec!(45)).restore(snapshot),
+            Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+
+        let snapshot = AccountSnapshot {
+            resolution: Resolution::Day,
+            ..account().snapshot()
+        };
+        assert_eq!(
+            trailing_account(dec!(45)).restore(snapshot),
+            Err(SnapshotError::ResolutionMismatch(Resolution::Day))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialises_a_snapshot() -> Result<(), AccountError> {
+        let mut account = trailing_account(dec!(45));
+        account.update_price(frame());
+        account.log_order(Order::Open(open()))?;
+        account.log_order(Order::Close(open().exit(frame().close, date())))?;
+        account.log_order(Order::Open(Entry {
+            position_id: "2".to_string(),
+            ..open()
+        }))?;
+
+        let json = serde_json::to_string(&account.snapshot()).unwrap();
+        let snapshot: AccountSnapshot = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(snapshot, account.snapshot());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn migrates_a_snapshot_saved_before_funding() {
+        let json = r#"{
+            "version": 2,
+            "market": {
+                "code": "UKX",
+                "margin_factor": "0.5",
+                "min_deal_size": { "amount": "0.50", "currency": "GBP" },
+                "min_stop_distance": "8",
+                "guaranteed_stop": null
+            },
+            "resolution": { "Minute": 10 },
+            "risk_per_trade": "0.01",
+            "balance": { "amount": "1159.5", "currency": "GBP" },
+            "price_history": [],
+            "live_trades": [{
+                "entry": {
+                    "position_id": "2",
+                    "direction": "Buy",
+                    "price": "40",
+                    "stop": "30",
+                    "size": { "amount": "1", "currency": "EUR" },
+                    "time": "2021-01-01T10:01:00Z"
+                },
+                "stop": "30",
+                "stop_history": []
+            }],
+            "closed_trades": [{
+                "id": "1",
+                "status": "Closed",
+                "direction": "Buy",
+                "entry_time": "2021-01-01T10:01:00Z",
+                "entry_price": "40",
+                "exit_time": "2021-01-01T10:01:00Z",
+                "exit_price": "199.5",
+                "stop": "30",
+                "stop_history": [],
+                "size": { "amount": "1", "currency": "EUR" },
+                "risk": { "amount": "10", "currency": "GBP" },
+                "outcome": "Profit",
+                "price_diff": "159.5",
+                "profit": { "amount": "159.5", "currency": "GBP" },
+                "risk_reward": "15.950"
+            }]
+        }"#;
+
+        let snapshot: AccountSnapshot = serde_json::from_str(json).unwrap();
+
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.market.funding, None);
+        assert_eq!(
+            snapshot.live_trades[0].funding,
+            CurrencyAmount::new(dec!(0), EUR)
+        );
+        assert_eq!(
+            snapshot.closed_trades[0].funding,
+            CurrencyAmount::new(dec!(0), GBP)
+        );
+        assert!(account().restore(snapshot).is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn migrates_a_snapshot_with_a_single_position() {
+        let json = r#"{
+            "version": 1,
+            "market": {
+                "code": "UKX",
+                "margin_factor": "0.5",
+                "min_deal_size": { "amount": "0.50", "currency": "GBP" },
+                "min_stop_distance": "8"
+            },
+            "resolution": { "Minute": 10 },
+            "risk_per_trade": "0.01",
+            "balance": { "amount": "1000", "currency": "GBP" },
+            "price_history": [],
+            "live_trade": {
+                "entry": {
+                    "position_id": "1",
+                    "direction": "Buy",
+                    "price": "40",
+                    "stop": "30",
+                    "size": { "amount": "1", "currency": "GBP" },
+                    "time": "2021-01-01T10:01:00Z"
+                },
+                "stop": "30",
+                "stop_history": []
+            },
+            "closed_trades": []
+        }"#;
+
+        let snapshot: AccountSnapshot = serde_json::from_str(json).unwrap();
+
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.live_trades, vec![Position::new(open())]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rejects_snapshots_of_unknown_versions() {
+        let json = format!(r#"{{ "version": {}, "balance": 0 }}"#, SNAPSHOT_VERSION + 1);
+        let error = serde_json::from_str::<AccountSnapshot>(&json).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1).to_string()
+        );
+    }
+
     // Fixtures
 
     struct Neutral {}
     impl TradingStrategy for Neutral {
-        fn trend(&self, _history: &PriceHistory) -> crate::strategy::Trend {
+        fn trend(&self, _context: &Context) -> crate::strategy::Trend {
             Trend::Neutral
         }
     }
 
     struct Bullish {}
     impl TradingStrategy for Bullish {
-        fn trend(&self, _history: &PriceHistory) -> crate::strategy::Trend {
+        fn trend(&self, _context: &Context) -> crate::strategy::Trend {
             Trend::Bullish
         }
     }
 
     struct Bearish {}
     impl TradingStrategy for Bearish {
-        fn trend(&self, _history: &PriceHistory) -> crate::strategy::Trend {
+        fn trend(&self, _context: &Context) -> crate::strategy::Trend {
             Trend::Bearish
         }
     }
 
+    struct Hourly {}
+    impl TradingStrategy for Hourly {
+        fn trend(&self, _context: &Context) -> crate::strategy::Trend {
+            Trend::Neutral
+        }
+
+        fn resolutions(&self) -> Vec<Resolution> {
+            vec![Resolution::Hour(1)]
+        }
+    }
+
     struct NoRisk {}
 
     impl RiskStrategy for NoRisk {
         fn stop(
             &self,
             _direction: Direction,
-            history: &PriceHistory,
+            context: &Context,
         ) -> Result<Points, RiskStrategyError> {
-            Ok(history.history[0].close.mid_price())
+            context
+                .history
+                .latest()
+                .map(|f| f.close.mid_price())
+                .ok_or(RiskStrategyError::NotEnoughHistory)
+        }
+    }
+
+    // Proposes the same trailing stop on every update
+    struct Trailing {
+        stop: Points,
+    }
+
+    impl RiskStrategy for Trailing {
+        fn stop(
+            &self,
+            direction: Direction,
+            context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            NoRisk {}.stop(direction, context)
+        }
+
+        fn trailing_stop(&self, _position: &Position, _context: &Context) -> Option<Points> {
+            Some(self.stop)
         }
     }
 
@@ -782,11 +2091,128 @@ mod test {
         )
     }
 
+    fn open() -> Entry {
+        Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(40),
+            stop: dec!(30),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        }
+    }
+
+    // Stop 10 points away from the entry
+    struct FixedStop {}
+
+    impl RiskStrategy for FixedStop {
+        fn stop(
+            &self,
+            direction: Direction,
+            context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            let latest = context
+                .history
+                .latest()
+                .ok_or(RiskStrategyError::NotEnoughHistory)?;
+
+            Ok(match direction {
+                Direction::Buy => latest.close.ask - dec!(10),
+                Direction::Sell => latest.close.bid + dec!(10),
+            })
+        }
+    }
+
+    fn pyramiding_account(pyramiding: Pyramiding) -> Account<Bullish, FixedStop> {
+        let mut account = Account::new(
+            market(),
+            Bullish {},
+            FixedStop {},
+            dec!(0.01),
+            CurrencyAmount::new(dec!(1000), GBP),
+            Resolution::Minute(10),
+        );
+        account.pyramiding = pyramiding;
+
+        account
+    }
+
+    // Trading a market in EUR from a balance in GBP
+    fn fx_account() -> Account<Bullish, FixedStop> {
+        let market = Market {
+            min_deal_size: CurrencyAmount::new(dec!(0.5), EUR),
+            ..market()
+        };
+        let mut account = Account::new(
+            market,
+            Bullish {},
+            FixedStop {},
+            dec!(0.01),
+            CurrencyAmount::new(dec!(1000), GBP),
+            Resolution::Minute(10),
+        );
+
+        let mut rates = RateHistory::new();
+        rates.insert(EUR, GBP, date(), dec!(0.8));
+        rates.insert(EUR, GBP, date() + Duration::days(1), dec!(0.9));
+        account.rates = Arc::new(rates);
+
+        account
+    }
+
+    fn unit(id: &str, price: Points, stop: Points) -> Entry {
+        Entry {
+            position_id: id.to_string(),
+            direction: Direction::Buy,
+            price,
+            stop,
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), GBP),
+            time: date(),
+        }
+    }
+
+    // Price moving 1 point either side of the mid price
+    fn price_frame(mid: Points) -> Frame {
+        Frame {
+            open: Price::new_mid(mid, dec!(1)),
+            close: Price::new_mid(mid, dec!(1)),
+            low: Price::new_mid(mid - dec!(1), dec!(1)),
+            high: Price::new_mid(mid + dec!(1), dec!(1)),
+            close_time: date(),
+        }
+    }
+
+    fn trailing_account(stop: Points) -> Account<Bullish, Trailing> {
+        Account::new(
+            market(),
+            Bullish {},
+            Trailing { stop },
+            dec!(0.01),
+            CurrencyAmount::new(dec!(1000), GBP),
+            Resolution::Minute(10),
+        )
+    }
+
+    fn indicator_account() -> Account<MACD, Donchian> {
+        Account::new(
+            market(),
+            MACD::new(3, 8, 3, dec!(2), dec!(1)),
+            Donchian::new(5),
+            dec!(0.01),
+            CurrencyAmount::new(dec!(1000), GBP),
+            Resolution::Day,
+        )
+    }
+
     fn market() -> Market {
         Market {
             code: "UKX".to_string(),
             min_deal_size: CurrencyAmount::new(dec!(0.50), GBP),
             min_stop_distance: dec!(8),
+            guaranteed_stop: None,
+            funding: None,
             margin_factor: dec!(0.5),
         }
     }
@@ -814,9 +2240,6 @@ mod test {
     }
 
     fn history() -> PriceHistory {
-        PriceHistory {
-            resolution: Resolution::Minute(10),
-            history: vec![frame()].into(),
-        }
+        PriceHistory::from_frames(Resolution::Minute(10), vec![frame()])
     }
 }
@@ -1,7 +1,11 @@
-use crate::account::Account;
-use crate::price::Frame;
+use crate::account::{Account, Equity, MarginCall, MarginPolicy};
+use crate::broker::SimulatedBroker;
+use crate::fill::FillModel;
+use crate::live::execute;
+use crate::price::{CurrencyAmount, Frame, Price};
+use crate::report::Report;
 use crate::strategy::{RiskStrategy, TradingStrategy};
-use crate::trade::{Entry, Exit, Order};
+use crate::trade::{Entry, Order, Trade};
 
 pub struct Backtest<TS, RS>
 where
@@ -9,8 +13,14 @@ where
     RS: RiskStrategy,
 {
     pub account: Account<TS, RS>,
-    pub p_id: usize,
+    pub broker: SimulatedBroker,
+    pub fill_model: FillModel,
+    pub pending: Vec<Order>, // held until the next frame, depending on the execution
+    pub margin_policy: MarginPolicy,
+    pub margin_calls: Vec<MarginCall>,
     pub trace: Vec<Result<Order, String>>,
+    pub opening_balance: CurrencyAmount,
+    pub equity: Vec<Equity>, // mark-to-market account value after each price update
 }
 
 impl<TS, RS> Backtest<TS, RS>
@@ -19,69 +29,499 @@ where
     RS: RiskStrategy,
 {
     pub fn new(account: Account<TS, RS>) -> Self {
+        let mut broker = SimulatedBroker::new(account.market.clone(), account.balance);
+        broker.rates = account.rates.clone();
+
         Self {
+            opening_balance: account.balance,
+            broker,
+            fill_model: FillModel::default(),
+            pending: Vec::new(),
+            margin_policy: MarginPolicy::ig(),
+            margin_calls: Vec::new(),
             account,
-            p_id: 0,
             trace: Vec::new(),
+            equity: Vec::new(),
         }
     }
 
-    pub fn run(&mut self, prices: &Vec<Frame>) {
+    pub fn run(&mut self, prices: &[Frame]) {
         for price in prices {
-            let orders = self.account.update_price(*price);
+            self.step(price, |_, order| Ok(order));
+        }
+    }
+
+    // Trade on the next frame
+    //
+    // Orders of the account are passed through the limit first, which can change them
+    // or turn them down with a reason logged in the trace.
+    pub(crate) fn step<F>(&mut self, frame: &Frame, limit: F)
+    where
+        F: FnMut(&Account<TS, RS>, Order) -> Result<Order, String>,
+    {
+        self.trade(frame, limit);
+        self.call_margin(frame);
+        self.value(frame);
+    }
+
+    // Place the orders of a frame, before the margin is called on it
+    pub(crate) fn trade<F>(&mut self, frame: &Frame, mut limit: F)
+    where
+        F: FnMut(&Account<TS, RS>, Order) -> Result<Order, String>,
+    {
+        self.roll_over(frame);
+        self.fill_pending(frame);
+
+        let orders = self.account.update_price(*frame);
 
-            for order in orders {
-                let event = self.place_order(&order);
+        for order in orders {
+            let event = match limit(&self.account, order) {
+                Ok(order) => self.submit(order, frame),
+                Err(e) => Some(Err(e)),
+            };
+            if let Some(event) = event {
                 self.trace.push(event);
             }
         }
     }
 
-    fn place_order(&mut self, order: &Order) -> Result<Order, String> {
-        match order {
-            Order::Open(entry) => {
-                self.account
-                    .market
-                    .validate_entry(&entry, self.account.balance)
-                    .map_err(|e| format!("Market rejected entry: {:?}, {}", entry, e))?;
-
-                let o = Order::Open(Entry {
-                    position_id: self.p_id.to_string(),
-                    ..entry.clone()
-                });
-
-                self.account
-                    .log_order(o.clone())
-                    .map_err(|e| format!("{}", e))?;
-
-                Ok(o)
-            }
-            Order::Close(exit) => {
-                let o = Order::Close(Exit {
-                    position_id: self.p_id.to_string(),
-                    ..exit.clone()
-                });
-
-                self.account
-                    .log_order(o.clone())
-                    .map_err(|e| format!("{}", e))?;
-
-                self.p_id += 1;
-                Ok(o)
-            }
-            Order::Stop(exit) => {
-                let o = Order::Stop(Exit {
-                    position_id: self.p_id.to_string(),
-                    ..exit.clone()
-                });
-
-                self.account
-                    .log_order(o.clone())
-                    .map_err(|e| format!("{}", e))?;
-
-                self.p_id += 1;
-                Ok(o)
+    // Log the equity at the close of a frame
+    pub(crate) fn value(&mut self, frame: &Frame) {
+        match self.account.equity(frame) {
+            Ok(equity) => self.equity.push(equity),
+            Err(e) => self.trace.push(Err(e.to_string())),
+        }
+    }
+
+    // Liquidate a position at the close of a frame, false when it couldn't be
+    pub(crate) fn liquidate(&mut self, position_id: &str, frame: &Frame) -> bool {
+        let exit = match self.account.live_trade(position_id) {
+            Some(position) => position.entry.exit(frame.close, frame.close_time),
+            None => return false,
+        };
+
+        let event = self.place(Order::Liquidate(exit), frame);
+        let liquidated = event.is_ok();
+        self.trace.push(event);
+
+        liquidated
+    }
+
+    // Debit the funding of the positions held overnight, before a frame
+    fn roll_over(&mut self, frame: &Frame) {
+        let charged = match self.account.roll_over(frame.close_time) {
+            Ok(charged) => charged,
+            Err(e) => return self.trace.push(Err(e.to_string())),
+        };
+
+        self.broker.balance = CurrencyAmount::new(
+            self.broker.balance.amount - charged.amount,
+            self.broker.balance.currency,
+        );
+    }
+
+    // Fill the orders held from the previous frame, before the strategies see this one
+    //
+    // With the next close or mid, a position can still be stopped out on the frame it
+    // was filled on, which errs on the side of caution.
+    fn fill_pending(&mut self, frame: &Frame) {
+        for order in std::mem::take(&mut self.pending) {
+            let order = match self.position(&order) {
+                Some(position) => {
+                    self.fill_model
+                        .execution
+                        .reprice(order, position.direction, frame)
+                }
+                None => order,
+            };
+
+            let event = self.place(order, frame);
+            self.trace.push(event);
+        }
+    }
+
+    // Place an order, or hold it until the next frame
+    fn submit(&mut self, order: Order, frame: &Frame) -> Option<Result<Order, String>> {
+        if self.fill_model.execution.holds(&order) {
+            self.pending.push(order);
+            return None;
+        }
+
+        Some(self.place(order, frame))
+    }
+
+    // Act on the margin level at the close of a frame, as the policy says
+    //
+    // Positions are liquidated at the close, the biggest loss first, until the level is
+    // back above the liquidation level. The calls are logged, the liquidations are also
+    // in the trace. An account that can't be valued isn't called, the error is logged
+    // with the equity of the frame.
+    fn call_margin(&mut self, frame: &Frame) {
+        let call = match self
+            .account
+            .equity(frame)
+            .map(|e| self.margin_policy.call(&e))
+        {
+            Ok(Some(call)) => call,
+            _ => return,
+        };
+        self.margin_calls.push(call);
+
+        while let Ok(Some(MarginCall::Liquidation(_))) = self
+            .account
+            .equity(frame)
+            .map(|e| self.margin_policy.call(&e))
+        {
+            let position_id = match self
+                .account
+                .live_trades()
+                .iter()
+                .min_by_key(|p| Trade::open(&p.entry, frame.close).profit.amount)
+            {
+                Some(position) => position.entry.position_id.clone(),
+                None => break,
+            };
+
+            if !self.liquidate(&position_id, frame) {
+                break;
             }
         }
     }
+
+    // Fill an order on a frame and log it into the account
+    fn place(&mut self, order: Order, frame: &Frame) -> Result<Order, String> {
+        let order = match self.position(&order) {
+            Some(position) => self.fill_model.fill(order, &position, frame),
+            None => order,
+        };
+
+        execute(&mut self.account, &mut self.broker, &order)
+    }
+
+    // Entry of the position an order opens or closes
+    fn position(&self, order: &Order) -> Option<Entry> {
+        match order {
+            Order::Open(entry) => Some(entry.clone()),
+            Order::Close(exit) | Order::Stop(exit) | Order::Liquidate(exit) => self
+                .account
+                .live_trade(&exit.position_id)
+                .map(|p| p.entry.clone()),
+            Order::Amend(_) => None,
+        }
+    }
+
+    // Feed prices to the strategies without placing any orders, e.g. to initialise indicators
+    pub fn warm_up(&mut self, prices: &[Frame]) {
+        for price in prices {
+            self.account.update_price(*price);
+        }
+    }
+
+    // Performance summary of the trades placed so far
+    pub fn report(&self, latest_price: Price) -> Report {
+        let trade_log = self.account.trade_log(latest_price);
+
+        Report::new(self.opening_balance, &trade_log, &self.equity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc, Weekday};
+    use iso_currency::Currency;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::core::fill::{Execution, Slippage};
+    use crate::core::market::{Funding, GuaranteedStop, Market};
+    use crate::core::price::{Points, Resolution};
+    use crate::core::strategy::{Context, RiskStrategyError, Trend};
+    use crate::core::trade::Direction;
+    use crate::strategies::{Donchian, MACD};
+
+    #[test]
+    fn stops_out_at_the_open_after_a_gap_down() {
+        let mut backtest = backtest();
+
+        // Bought at 101.5 with the stop at 90.5, opens at 80 the next day
+        backtest.run(&[frame(1, dec!(100), dec!(100)), frame(2, dec!(80), dec!(85))]);
+
+        let trade = &backtest.account.closed_trades()[0];
+        assert_eq!(trade.entry_price, dec!(101.5));
+        assert_eq!(trade.exit_price, Some(dec!(78.5))); // open bid, slipped by a point
+        assert_eq!(backtest.account.balance, gbp(dec!(977)));
+        assert_eq!(backtest.broker.balance, gbp(dec!(977)));
+    }
+
+    #[test]
+    fn stops_out_at_the_stop_without_a_gap() {
+        let mut backtest = backtest();
+
+        backtest.run(&[frame(1, dec!(100), dec!(100)), frame(2, dec!(95), dec!(85))]);
+
+        let trade = &backtest.account.closed_trades()[0];
+        assert_eq!(trade.exit_price, Some(dec!(89.5))); // stop, slipped by a point
+        assert_eq!(backtest.account.balance, gbp(dec!(988)));
+    }
+
+    #[test]
+    fn stops_out_at_a_guaranteed_stop_despite_a_gap() {
+        let mut backtest = backtest();
+        backtest.account.market.guaranteed_stop = Some(GuaranteedStop::new(dec!(1), dec!(10)));
+        backtest.broker.market = backtest.account.market.clone();
+        backtest.account.guaranteed_stops = true;
+
+        backtest.run(&[frame(1, dec!(100), dec!(100)), frame(2, dec!(80), dec!(85))]);
+
+        let trade = &backtest.account.closed_trades()[0];
+        assert_eq!(trade.exit_price, Some(dec!(90.5))); // no gap, no slippage
+        assert_eq!(trade.profit, gbp(dec!(-12))); // 11 points down and a point of premium
+        assert_eq!(backtest.account.balance, gbp(dec!(988)));
+        assert_eq!(backtest.broker.balance, gbp(dec!(988)));
+    }
+
+    #[test]
+    fn debits_funding_at_each_rollover() {
+        let mut backtest = backtest();
+        // 3.65% a year, a penny a night at 100
+        let funding = Funding::new(dec!(0.01), dec!(0.0265), Weekday::Fri);
+        backtest.account.market.funding = Some(funding);
+
+        // Bought on Tuesday, held over two nights
+        backtest.run(&[
+            frame(1, dec!(100), dec!(100)),
+            frame(2, dec!(100), dec!(100)),
+            frame(3, dec!(100), dec!(100)),
+        ]);
+
+        let trade = &backtest
+            .account
+            .trade_log(Price::new_mid(dec!(100), dec!(1)))[0];
+        assert_eq!(trade.funding, gbp(dec!(0.02)));
+        assert_eq!(backtest.account.balance, gbp(dec!(999.98)));
+        assert_eq!(backtest.broker.balance, gbp(dec!(999.98)));
+    }
+
+    #[test]
+    fn liquidates_when_the_margin_runs_out() {
+        let mut backtest = backtest();
+        // 100 GBP per point at 101.5, holding 500 GBP of margin
+        backtest.account.risk_per_trade = dec!(1);
+
+        backtest.run(&[
+            frame(1, dec!(100), dec!(100)),
+            frame(2, dec!(100), dec!(96)),
+        ]);
+
+        // Worth 400 GBP, holding 480 GBP
+        assert!(matches!(
+            backtest.margin_calls[..],
+            [MarginCall::Warning(_)]
+        ));
+        assert_eq!(backtest.account.live_trades().len(), 1);
+
+        backtest.run(&[frame(3, dec!(96), dec!(94))]);
+
+        // Worth 200 GBP, holding 470 GBP, closed at the bid and slipped by a point
+        assert!(matches!(
+            backtest.margin_calls[1..],
+            [MarginCall::Liquidation(_)]
+        ));
+        assert!(matches!(
+            backtest.trace.last(),
+            Some(Ok(Order::Liquidate(_)))
+        ));
+        assert!(backtest.account.live_trades().is_empty());
+        assert_eq!(
+            backtest.account.closed_trades()[0].exit_price,
+            Some(dec!(92.5))
+        );
+        assert_eq!(backtest.account.balance, gbp(dec!(100)));
+        assert_eq!(backtest.broker.balance, gbp(dec!(100)));
+    }
+
+    #[test]
+    fn holds_entries_until_the_next_open() {
+        let mut backtest = backtest();
+        backtest.fill_model = FillModel::new(Slippage::None, Execution::NextOpen);
+
+        backtest.run(&[frame(1, dec!(100), dec!(100))]);
+
+        assert!(backtest.account.live_trades().is_empty());
+        assert_eq!(backtest.pending.len(), 1);
+
+        backtest.run(&[frame(2, dec!(104), dec!(106))]);
+
+        let entry = &backtest.account.live_trades()[0].entry;
+        assert_eq!(entry.price, dec!(104.5));
+        assert_eq!(entry.stop, dec!(94.5));
+        assert_eq!(entry.time, date() + Duration::days(2));
+        assert!(backtest.pending.is_empty());
+    }
+
+    // Regression of the CLI's strategy on the DAX under each execution timing, the same
+    // trades filled at different prices
+    #[test]
+    fn executes_later_on_the_dax() {
+        let results: Vec<(Decimal, usize)> = [
+            Execution::SameClose,
+            Execution::NextOpen,
+            Execution::NextClose,
+            Execution::NextMid,
+        ]
+        .iter()
+        .map(|execution| {
+            let prices = dax();
+            let mut backtest = Backtest::new(dax_account());
+            backtest.fill_model = FillModel::new(Slippage::None, *execution);
+            backtest.run(&prices);
+
+            let report = backtest.report(prices.last().unwrap().close);
+            (report.closing_balance.amount.round_dp(2), report.trades)
+        })
+        .collect();
+
+        // The signals don't move, so there are as many trades under every timing. On this
+        // data the later fills come out ahead of the close they were signalled on, and the
+        // larger balance compounds into larger sizes. Stops fill at their level either way
+        // and lose the same risk.
+        assert_eq!(
+            results,
+            vec![
+                (dec!(21571.32), 21),
+                // Up 286: the next open is better for entries (+135) and closes (+93)
+                (dec!(21857.36), 21),
+                // Up 521: entries (+231) and closes (+242) at the next close, and the first
+                // sell, its stop moved up with the fill, is closed on the signal before
+                // the stop for 557 rather than 600
+                (dec!(22092.06), 21),
+                // Up 528: entries at the middle of the next range gain the most (+343),
+                // closes less (+126), and the first sell is also closed before the stop
+                (dec!(22099.55), 21),
+            ]
+        );
+    }
+
+    // Fixtures
+
+    struct Bullish {}
+
+    impl TradingStrategy for Bullish {
+        fn trend(&self, _context: &Context) -> Trend {
+            Trend::Bullish
+        }
+    }
+
+    // Stop 10 points away from the entry
+    struct FixedStop {}
+
+    impl RiskStrategy for FixedStop {
+        fn stop(
+            &self,
+            direction: Direction,
+            context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            let latest = context
+                .history
+                .latest()
+                .ok_or(RiskStrategyError::NotEnoughHistory)?;
+
+            Ok(match direction {
+                Direction::Buy => latest.close.ask - dec!(10),
+                Direction::Sell => latest.close.bid + dec!(10),
+            })
+        }
+    }
+
+    fn backtest() -> Backtest<Bullish, FixedStop> {
+        let account = Account::new(
+            Market {
+                code: "UKX".to_string(),
+                margin_factor: dec!(0.05),
+                min_deal_size: gbp(dec!(0.5)),
+                min_stop_distance: dec!(5),
+                guaranteed_stop: None,
+                funding: None,
+            },
+            Bullish {},
+            FixedStop {},
+            dec!(0.01),
+            gbp(dec!(1000)),
+            Resolution::Day,
+        );
+
+        let mut backtest = Backtest::new(account);
+        backtest.fill_model = FillModel::new(Slippage::Points(dec!(1)), Execution::SameClose);
+
+        backtest
+    }
+
+    fn dax_account() -> Account<MACD, Donchian> {
+        Account::new(
+            Market {
+                code: "GDAXI".to_string(),
+                margin_factor: dec!(0.05),
+                min_deal_size: gbp(dec!(0.5)),
+                min_stop_distance: dec!(12),
+                guaranteed_stop: None,
+                funding: None,
+            },
+            MACD::new(12, 42, 10, dec!(40), dec!(40)),
+            Donchian::new(20),
+            dec!(0.03),
+            gbp(dec!(20000)),
+            Resolution::Day,
+        )
+    }
+
+    // Daily prices of the DAX, 2018 to 2021, read like the CLI reads them
+    //
+    // Columns are found by the header, with a spread of 5 points, and lines that don't
+    // parse are skipped.
+    fn dax() -> Vec<Frame> {
+        let mut lines = include_str!("../../../dax-2018-2021-daily.csv").lines();
+        let header: Vec<&str> = lines.next().unwrap_or_default().split(',').collect();
+        let column = |name: &str| header.iter().position(|h| *h == name);
+
+        lines
+            .filter_map(|line| {
+                let columns: Vec<&str> = line.split(',').collect();
+                let field = |name: &str| columns.get(column(name)?).copied();
+                let price = |name: &str| -> Option<Price> {
+                    Some(Price::new_mid(field(name)?.parse().ok()?, dec!(5)))
+                };
+
+                Some(Frame {
+                    close_time: Utc
+                        .datetime_from_str(field("Date")?, "%Y-%m-%dT%H:%M:%S")
+                        .ok()?,
+                    open: price("Open")?,
+                    high: price("High")?,
+                    low: price("Low")?,
+                    close: price("Close")?,
+                })
+            })
+            .collect()
+    }
+
+    // Opening at one price and closing at another, the range between them
+    fn frame(n: i64, open: Points, close: Points) -> Frame {
+        Frame {
+            open: Price::new_mid(open, dec!(1)),
+            close: Price::new_mid(close, dec!(1)),
+            high: Price::new_mid(open.max(close), dec!(1)),
+            low: Price::new_mid(open.min(close), dec!(1)),
+            close_time: date() + Duration::days(n),
+        }
+    }
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)
+    }
 }
@@ -0,0 +1,383 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::core::fx::{FxError, RateSource, SingleCurrency};
+use crate::core::market::Market;
+use crate::core::price::{CurrencyAmount, Points};
+use crate::core::trade::{Amendment, Direction, Entry, Exit, Order, Trade};
+
+// Executes the orders of an account
+//
+// Each method returns the order as it was filled, which can differ from the order placed,
+// e.g. the broker assigns the position id and the actual fill price.
+pub trait OrderBroker {
+    fn open(&mut self, entry: &Entry) -> Result<Entry, OrderError>;
+    fn close(&mut self, exit: &Exit) -> Result<Exit, OrderError>;
+    // The position was closed by its stop-loss
+    fn stop(&mut self, exit: &Exit) -> Result<Exit, OrderError>;
+    fn amend_stop(&mut self, amendment: &Amendment) -> Result<Amendment, OrderError>;
+
+    // What the broker holds, to reconcile the account with
+    fn positions(&mut self) -> Result<Vec<OpenPosition>, OrderError>;
+    fn balance(&mut self) -> Result<CurrencyAmount, OrderError>;
+
+    fn place(&mut self, order: &Order) -> Result<Order, OrderError> {
+        match order {
+            Order::Open(entry) => self.open(entry).map(Order::Open),
+            Order::Close(exit) => self.close(exit).map(Order::Close),
+            Order::Stop(exit) => self.stop(exit).map(Order::Stop),
+            Order::Amend(amendment) => self.amend_stop(amendment).map(Order::Amend),
+            Order::Liquidate(exit) => self.close(exit).map(Order::Liquidate),
+        }
+    }
+}
+
+// A position open at the broker
+#[derive(Debug, PartialEq, Clone)]
+pub struct OpenPosition {
+    pub position_id: String,
+    pub direction: Direction,
+    pub size: CurrencyAmount,
+    pub level: Points, // entry price
+    pub stop: Option<Points>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OrderError {
+    Rejected(String), // the broker refused the order
+    Failed(String),   // the order couldn't be placed, e.g. the broker is unreachable
+}
+
+impl Error for OrderError {}
+
+impl Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderError::Rejected(s) => write!(f, "Order rejected: {}", s),
+            OrderError::Failed(s) => write!(f, "Order failed: {}", s),
+        }
+    }
+}
+
+// Broker filling every valid order at the requested price, used for backtesting
+//
+// Entries are checked against the market rules and the balance not held as margin by
+// the open positions. The broker keeps the balance up to date with the profit of the
+// closed positions, converted at the rate of the close.
+#[derive(Debug, Clone)]
+pub struct SimulatedBroker {
+    pub market: Market,
+    pub balance: CurrencyAmount,
+    pub positions: Vec<Entry>,
+    pub next_id: usize, // id of the next position opened
+    pub rates: Arc<dyn RateSource>,
+}
+
+impl SimulatedBroker {
+    pub fn new(market: Market, balance: CurrencyAmount) -> Self {
+        Self {
+            market,
+            balance,
+            positions: vec![],
+            next_id: 0,
+            rates: Arc::new(SingleCurrency),
+        }
+    }
+
+    fn fill_exit(
+        &mut self,
+        exit: &Exit,
+        trade: fn(&Entry, &Exit) -> Trade,
+    ) -> Result<Exit, OrderError> {
+        let index = self.position_index(&exit.position_id)?;
+        let profit = trade(&self.positions[index], exit).profit;
+        let profit = self
+            .rates
+            .convert(profit, self.balance.currency, exit.time)
+            .map_err(|e| OrderError::Rejected(format!("{}", e)))?;
+
+        self.balance =
+            (self.balance + profit).map_err(|e| OrderError::Rejected(format!("{}", e)))?;
+        self.positions.remove(index);
+
+        Ok(exit.clone())
+    }
+
+    fn position_index(&self, position_id: &str) -> Result<usize, OrderError> {
+        self.positions
+            .iter()
+            .position(|entry| entry.position_id == position_id)
+            .ok_or_else(|| OrderError::Rejected(format!("No open position {}", position_id)))
+    }
+
+    // Balance not held as margin by the open positions, at their entry prices and rates
+    fn available(&self) -> Result<CurrencyAmount, FxError> {
+        let mut available = self.balance;
+        for entry in &self.positions {
+            let margin = self.market.margin(entry.size, entry.price);
+            let margin = self
+                .rates
+                .convert(margin, self.balance.currency, entry.time)?;
+
+            available = CurrencyAmount::new(available.amount - margin.amount, available.currency);
+        }
+
+        Ok(available)
+    }
+}
+
+impl OrderBroker for SimulatedBroker {
+    fn open(&mut self, entry: &Entry) -> Result<Entry, OrderError> {
+        let available = self
+            .available()
+            .and_then(|a| self.rates.convert(a, self.market.currency(), entry.time))
+            .map_err(|e| OrderError::Rejected(format!("{:?}, {}", entry, e)))?;
+
+        self.market
+            .validate_entry(entry, available)
+            .map_err(|e| OrderError::Rejected(format!("{:?}, {}", entry, e)))?;
+
+        let filled = Entry {
+            position_id: self.next_id.to_string(),
+            ..entry.clone()
+        };
+        self.next_id += 1;
+        self.positions.push(filled.clone());
+
+        Ok(filled)
+    }
+
+    fn close(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+        self.fill_exit(exit, Trade::closed)
+    }
+
+    fn stop(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+        self.fill_exit(exit, Trade::stopped)
+    }
+
+    fn amend_stop(&mut self, amendment: &Amendment) -> Result<Amendment, OrderError> {
+        let index = self.position_index(&amendment.position_id)?;
+        self.positions[index].stop = amendment.stop;
+
+        Ok(amendment.clone())
+    }
+
+    fn positions(&mut self) -> Result<Vec<OpenPosition>, OrderError> {
+        Ok(self
+            .positions
+            .iter()
+            .map(|entry| OpenPosition {
+                position_id: entry.position_id.clone(),
+                direction: entry.direction,
+                size: entry.size,
+                level: entry.price,
+                stop: Some(entry.stop),
+                time: entry.time,
+            })
+            .collect())
+    }
+
+    fn balance(&mut self) -> Result<CurrencyAmount, OrderError> {
+        Ok(self.balance)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+    use iso_currency::Currency;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::core::fx::RateHistory;
+    use crate::core::price::Price;
+    use crate::core::trade::StopType;
+
+    #[test]
+    fn fills_a_valid_entry_with_a_new_position_id() {
+        let mut broker = broker();
+
+        let first = broker.open(&entry(dec!(1))).unwrap();
+        broker
+            .close(&first.exit(price(dec!(1010)), date()))
+            .unwrap();
+        let second = broker.open(&entry(dec!(1))).unwrap();
+
+        assert_eq!(first.position_id, "0");
+        assert_eq!(second.position_id, "1");
+        assert_eq!(broker.positions, vec![second]);
+    }
+
+    #[test]
+    fn rejects_entries_breaking_market_rules() {
+        let mut broker = broker();
+
+        let actual = broker.open(&entry(dec!(0.1)));
+
+        assert!(matches!(actual, Err(OrderError::Rejected(_))));
+        assert_eq!(broker.positions, vec![]);
+    }
+
+    #[test]
+    fn holds_margin_for_each_open_position() {
+        let mut broker = broker();
+
+        // 2 * 1000 * 0.05 = 100 held by each
+        for _ in 0..10 {
+            broker.open(&entry(dec!(2))).unwrap();
+        }
+
+        assert!(matches!(
+            broker.open(&entry(dec!(2))),
+            Err(OrderError::Rejected(_))
+        ));
+        assert_eq!(broker.positions.len(), 10);
+    }
+
+    #[test]
+    fn settles_closed_positions() {
+        let mut broker = broker();
+
+        let entry = broker.open(&entry(dec!(2))).unwrap();
+        let exit = entry.exit(price(dec!(1050)), date());
+
+        assert_eq!(broker.stop(&exit), Ok(exit.clone()));
+        assert_eq!(broker.balance, gbp(dec!(1098))); // 2 * (1049 - 1000)
+        assert_eq!(
+            broker.close(&exit),
+            Err(OrderError::Rejected("No open position 0".to_string()))
+        );
+    }
+
+    #[test]
+    fn amends_the_stop_of_the_open_position() {
+        let mut broker = broker();
+        let entry = broker.open(&entry(dec!(1))).unwrap();
+
+        let amendment = entry.amend(dec!(990), date());
+
+        assert_eq!(broker.amend_stop(&amendment), Ok(amendment));
+        assert_eq!(broker.positions[0].stop, dec!(990));
+    }
+
+    #[test]
+    fn reports_the_open_position_and_balance() {
+        let mut broker = broker();
+        let entry = broker.open(&entry(dec!(1))).unwrap();
+        broker.amend_stop(&entry.amend(dec!(990), date())).unwrap();
+
+        assert_eq!(
+            broker.positions(),
+            Ok(vec![OpenPosition {
+                position_id: "0".to_string(),
+                direction: Direction::Buy,
+                size: gbp(dec!(1)),
+                level: dec!(1000),
+                stop: Some(dec!(990)),
+                time: date(),
+            }])
+        );
+        assert_eq!(broker.balance(), Ok(gbp(dec!(1000))));
+    }
+
+    #[test]
+    fn settles_profits_in_the_currency_of_the_balance() {
+        let mut broker = eur_broker();
+        let mut rates = RateHistory::new();
+        rates.insert(Currency::EUR, Currency::GBP, date(), dec!(0.8));
+        broker.rates = Arc::new(rates);
+
+        let entry = broker.open(&eur_entry()).unwrap();
+        broker
+            .close(&entry.exit(price(dec!(1050)), date()))
+            .unwrap();
+
+        assert_eq!(broker.balance, gbp(dec!(1078.4))); // 2 * (1049 - 1000) EUR at 0.8
+    }
+
+    #[test]
+    fn rejects_entries_without_an_exchange_rate() {
+        let mut broker = eur_broker();
+
+        assert!(matches!(
+            broker.open(&eur_entry()),
+            Err(OrderError::Rejected(_))
+        ));
+    }
+
+    #[test]
+    fn places_any_order() {
+        let mut broker = broker();
+
+        let opened = broker.place(&Order::Open(entry(dec!(1)))).unwrap();
+        let entry = match opened {
+            Order::Open(entry) => entry,
+            _ => panic!("Expected an entry"),
+        };
+
+        assert_eq!(
+            broker.place(&Order::Close(entry.exit(price(dec!(1000)), date()))),
+            Ok(Order::Close(entry.exit(price(dec!(1000)), date())))
+        );
+    }
+
+    // Fixtures
+
+    fn broker() -> SimulatedBroker {
+        SimulatedBroker::new(
+            Market {
+                code: "UKX".to_string(),
+                margin_factor: dec!(0.05),
+                min_deal_size: gbp(dec!(0.5)),
+                min_stop_distance: dec!(10),
+                guaranteed_stop: None,
+                funding: None,
+            },
+            gbp(dec!(1000)),
+        )
+    }
+
+    fn eur_broker() -> SimulatedBroker {
+        let mut broker = broker();
+        broker.market.min_deal_size = CurrencyAmount::new(dec!(0.5), Currency::EUR);
+
+        broker
+    }
+
+    fn eur_entry() -> Entry {
+        Entry {
+            size: CurrencyAmount::new(dec!(2), Currency::EUR),
+            ..entry(dec!(2))
+        }
+    }
+
+    fn entry(size: Decimal) -> Entry {
+        Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(1000),
+            stop: dec!(950),
+            stop_type: StopType::Normal,
+            size: gbp(size),
+            time: date(),
+        }
+    }
+
+    fn price(mid: Decimal) -> Price {
+        Price::new_mid(mid, dec!(2))
+    }
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)
+    }
+}
@@ -0,0 +1,357 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::core::price::{Frame, Points, Price};
+use crate::core::trade::{Direction, Entry, Exit, Order, StopType};
+
+// When market orders are filled, relative to the frame they were placed on
+//
+// The strategies decide on the close of a frame, filling at that same close assumes the
+// order could be placed the moment the frame closed. The other timings hold the order
+// until the next frame arrives.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Execution {
+    SameClose,
+    NextOpen,
+    NextClose,
+    NextMid, // halfway between the high and the low of the next frame
+}
+
+impl Execution {
+    // Whether an order is held until the next frame
+    //
+    // Stops and amendments sit at the broker, they take effect without waiting, as do
+    // liquidations the broker makes itself.
+    pub fn holds(&self, order: &Order) -> bool {
+        match order {
+            Order::Open(_) | Order::Close(_) => *self != Execution::SameClose,
+            Order::Stop(_) | Order::Amend(_) | Order::Liquidate(_) => false,
+        }
+    }
+
+    // Held order priced on the frame it's filled on, the direction is of the position
+    //
+    // An entry keeps the distance to its stop, so it still risks what it was sized for.
+    pub fn reprice(&self, order: Order, direction: Direction, frame: &Frame) -> Order {
+        let price = match self {
+            Execution::SameClose => return order,
+            Execution::NextOpen => frame.open,
+            Execution::NextClose => frame.close,
+            Execution::NextMid => Price {
+                ask: (frame.high.ask + frame.low.ask) / dec!(2),
+                bid: (frame.high.bid + frame.low.bid) / dec!(2),
+            },
+        };
+        let time = frame.close_time;
+
+        match order {
+            Order::Open(entry) => {
+                let filled = match direction {
+                    Direction::Buy => price.ask,
+                    Direction::Sell => price.bid,
+                };
+
+                Order::Open(Entry {
+                    price: filled,
+                    stop: entry.stop + (filled - entry.price),
+                    time,
+                    ..entry
+                })
+            }
+            Order::Close(exit) => Order::Close(Exit {
+                price: match direction {
+                    Direction::Buy => price.bid,
+                    Direction::Sell => price.ask,
+                },
+                time,
+                ..exit
+            }),
+            _ => order,
+        }
+    }
+}
+
+// Points lost on every fill, against the position
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Slippage {
+    None,
+    Points(Points),      // the same every time
+    Spread(Decimal),     // fraction of the spread at the close
+    Volatility(Decimal), // fraction of the range of the frame, from high to low
+}
+
+impl Slippage {
+    pub fn points(&self, frame: &Frame) -> Points {
+        match self {
+            Slippage::None => Decimal::ZERO,
+            Slippage::Points(points) => *points,
+            Slippage::Spread(fraction) => frame.close.spread() * fraction,
+            Slippage::Volatility(fraction) => {
+                (frame.high.mid_price() - frame.low.mid_price()) * fraction
+            }
+        }
+    }
+}
+
+// How the simulated broker fills orders, given the frame they were placed on
+//
+// Market orders fill at the price they were placed at, the close, or when they're held
+// as the execution says. A stop fills at its level, unless the market opened beyond
+// it - gapped through it - in which case it fills at the open. All fills then slip
+// against the position, except for guaranteed stops, which always fill at their level.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FillModel {
+    pub slippage: Slippage,
+    pub execution: Execution,
+}
+
+impl FillModel {
+    pub fn new(slippage: Slippage, execution: Execution) -> Self {
+        Self {
+            slippage,
+            execution,
+        }
+    }
+
+    // Order as it would be filled, given the entry of the position it opens or closes
+    pub fn fill(&self, order: Order, position: &Entry, frame: &Frame) -> Order {
+        let direction = position.direction;
+        let slippage = self.slippage.points(frame);
+
+        match order {
+            Order::Open(entry) => Order::Open(Entry {
+                price: match direction {
+                    Direction::Buy => entry.price + slippage,
+                    Direction::Sell => entry.price - slippage,
+                },
+                ..entry
+            }),
+            Order::Close(exit) => Order::Close(slip(exit, direction, slippage)),
+            Order::Liquidate(exit) => Order::Liquidate(slip(exit, direction, slippage)),
+            Order::Stop(exit) if position.stop_type != StopType::Normal => Order::Stop(exit),
+            Order::Stop(exit) => {
+                let gapped = match direction {
+                    Direction::Buy => frame.open.bid.min(exit.price),
+                    Direction::Sell => frame.open.ask.max(exit.price),
+                };
+
+                Order::Stop(slip(
+                    Exit {
+                        price: gapped,
+                        ..exit
+                    },
+                    direction,
+                    slippage,
+                ))
+            }
+            Order::Amend(amendment) => Order::Amend(amendment),
+        }
+    }
+}
+
+impl Default for FillModel {
+    fn default() -> Self {
+        Self::new(Slippage::None, Execution::SameClose)
+    }
+}
+
+fn slip(exit: Exit, direction: Direction, slippage: Points) -> Exit {
+    Exit {
+        price: match direction {
+            Direction::Buy => exit.price - slippage,
+            Direction::Sell => exit.price + slippage,
+        },
+        ..exit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use iso_currency::Currency;
+
+    use crate::core::price::CurrencyAmount;
+
+    #[test]
+    fn fills_stops_at_the_stop_level() {
+        let model = FillModel::default();
+
+        // Opened at 100, fell to 85 during the frame
+        assert_eq!(
+            model.fill(stop(dec!(90)), &entry(), &frame(dec!(100), dec!(85))),
+            stop(dec!(90))
+        );
+        assert_eq!(
+            model.fill(stop(dec!(110)), &short(), &frame(dec!(100), dec!(115))),
+            stop(dec!(110))
+        );
+    }
+
+    #[test]
+    fn fills_stops_at_the_open_after_a_gap() {
+        let model = FillModel::default();
+
+        // Opened at 80, below the stop of the long position
+        assert_eq!(
+            model.fill(stop(dec!(90)), &entry(), &frame(dec!(80), dec!(85))),
+            stop(dec!(79.5))
+        );
+        // Opened at 120, above the stop of the short position
+        assert_eq!(
+            model.fill(stop(dec!(110)), &short(), &frame(dec!(120), dec!(115))),
+            stop(dec!(120.5))
+        );
+    }
+
+    #[test]
+    fn slips_fills_against_the_position() {
+        let model = FillModel::new(Slippage::Points(dec!(2)), Execution::SameClose);
+        let frame = frame(dec!(80), dec!(85));
+
+        assert_eq!(
+            model.fill(stop(dec!(90)), &entry(), &frame),
+            stop(dec!(77.5))
+        );
+        assert_eq!(
+            model.fill(close(dec!(100)), &entry(), &frame),
+            close(dec!(98))
+        );
+        assert_eq!(
+            model.fill(close(dec!(100)), &short(), &frame),
+            close(dec!(102))
+        );
+        assert_eq!(
+            model.fill(Order::Open(entry()), &entry(), &frame),
+            Order::Open(Entry {
+                price: dec!(102),
+                ..entry()
+            })
+        );
+    }
+
+    #[test]
+    fn fills_guaranteed_stops_exactly_at_the_stop() {
+        let model = FillModel::new(Slippage::Points(dec!(2)), Execution::SameClose);
+        let guaranteed = Entry {
+            stop_type: StopType::Guaranteed { premium: dec!(1) },
+            ..entry()
+        };
+
+        // Gapped through the stop and slipping
+        assert_eq!(
+            model.fill(stop(dec!(90)), &guaranteed, &frame(dec!(80), dec!(85))),
+            stop(dec!(90))
+        );
+    }
+
+    #[test]
+    fn holds_market_orders_only() {
+        assert!(Execution::NextOpen.holds(&close(dec!(100))));
+        assert!(Execution::NextMid.holds(&Order::Open(entry())));
+        assert!(!Execution::NextOpen.holds(&stop(dec!(90))));
+        assert!(!Execution::SameClose.holds(&close(dec!(100))));
+    }
+
+    #[test]
+    fn reprices_held_orders_on_the_next_frame() {
+        // Opens at 80, closes at 85, spread of 1
+        let next = Frame {
+            close_time: date() + Duration::days(1),
+            ..frame(dec!(80), dec!(85))
+        };
+
+        assert_eq!(
+            Execution::NextOpen.reprice(Order::Open(entry()), Direction::Buy, &next),
+            Order::Open(Entry {
+                price: dec!(80.5),
+                stop: dec!(70.5),
+                time: next.close_time,
+                ..entry()
+            })
+        );
+        assert_eq!(
+            Execution::NextClose.reprice(close(dec!(100)), Direction::Buy, &next),
+            close_at(dec!(84.5), next.close_time)
+        );
+        assert_eq!(
+            Execution::NextMid.reprice(close(dec!(100)), Direction::Sell, &next),
+            close_at(dec!(83), next.close_time)
+        );
+        assert_eq!(
+            Execution::SameClose.reprice(close(dec!(100)), Direction::Buy, &next),
+            close(dec!(100))
+        );
+    }
+
+    #[test]
+    fn scales_slippage_to_the_market() {
+        // Spread of 1, range of 20
+        let frame = frame(dec!(100), dec!(80));
+
+        assert_eq!(Slippage::Spread(dec!(0.5)).points(&frame), dec!(0.5));
+        assert_eq!(Slippage::Volatility(dec!(0.1)).points(&frame), dec!(2));
+        assert_eq!(Slippage::None.points(&frame), dec!(0));
+    }
+
+    // Fixtures
+
+    // Opening at one price and closing at another, the range between them
+    fn frame(open: Points, close: Points) -> Frame {
+        Frame {
+            open: Price::new_mid(open, dec!(1)),
+            close: Price::new_mid(close, dec!(1)),
+            high: Price::new_mid(open.max(close), dec!(1)),
+            low: Price::new_mid(open.min(close), dec!(1)),
+            close_time: date(),
+        }
+    }
+
+    fn stop(price: Points) -> Order {
+        Order::Stop(exit(price))
+    }
+
+    fn close(price: Points) -> Order {
+        Order::Close(exit(price))
+    }
+
+    fn close_at(price: Points, time: DateTime<Utc>) -> Order {
+        Order::Close(Exit {
+            time,
+            ..exit(price)
+        })
+    }
+
+    fn exit(price: Points) -> Exit {
+        Exit {
+            position_id: "1".to_string(),
+            price,
+            time: date(),
+        }
+    }
+
+    fn entry() -> Entry {
+        Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(100),
+            stop: dec!(90),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), Currency::GBP),
+            time: date(),
+        }
+    }
+
+    fn short() -> Entry {
+        Entry {
+            direction: Direction::Sell,
+            stop: dec!(110),
+            ..entry()
+        }
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)
+    }
+}
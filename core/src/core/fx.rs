@@ -0,0 +1,206 @@
+use std::error::Error;
+use std::fmt::{Debug, Display};
+
+use chrono::{DateTime, Utc};
+use iso_currency::Currency;
+use rust_decimal::Decimal;
+
+use crate::core::price::CurrencyAmount;
+
+// Exchange rates between currencies over time
+pub trait RateSource: Debug + Send + Sync {
+    // Units of `to` for one unit of `from`, as of a point in time
+    fn rate(&self, from: Currency, to: Currency, time: DateTime<Utc>) -> Result<Decimal, FxError>;
+
+    fn convert(
+        &self,
+        amount: CurrencyAmount,
+        to: Currency,
+        time: DateTime<Utc>,
+    ) -> Result<CurrencyAmount, FxError> {
+        if amount.currency == to {
+            return Ok(amount);
+        }
+
+        self.rate(amount.currency, to, time)
+            .map(|rate| amount.convert(to, rate))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FxError {
+    NoRate {
+        from: Currency,
+        to: Currency,
+        time: DateTime<Utc>,
+    },
+}
+
+impl Error for FxError {}
+
+impl Display for FxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FxError::NoRate { from, to, time } => write!(
+                f,
+                "No {}/{} exchange rate as of {}",
+                from.code(),
+                to.code(),
+                time
+            ),
+        }
+    }
+}
+
+// No exchange rates, for accounts trading in their own currency only
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SingleCurrency;
+
+impl RateSource for SingleCurrency {
+    fn rate(&self, from: Currency, to: Currency, time: DateTime<Utc>) -> Result<Decimal, FxError> {
+        if from == to {
+            Ok(Decimal::ONE)
+        } else {
+            Err(FxError::NoRate { from, to, time })
+        }
+    }
+}
+
+// Rates of a currency pair, in order of time
+type Quotes = Vec<(DateTime<Utc>, Decimal)>;
+
+// Rates quoted over time, e.g. read from a CSV file
+//
+// The rate as of a time is the latest one quoted at or before it. A pair is also
+// converted the other way round, at the inverse rate.
+#[derive(Debug, Clone, Default)]
+pub struct RateHistory {
+    pairs: Vec<(Currency, Currency, Quotes)>,
+}
+
+impl RateHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Units of `to` for one unit of `from` from a point in time on
+    pub fn insert(&mut self, from: Currency, to: Currency, time: DateTime<Utc>, rate: Decimal) {
+        let index = match self
+            .pairs
+            .iter()
+            .position(|(f, t, _)| *f == from && *t == to)
+        {
+            Some(index) => index,
+            None => {
+                self.pairs.push((from, to, vec![]));
+                self.pairs.len() - 1
+            }
+        };
+
+        let quotes = &mut self.pairs[index].2;
+        let at = quotes.partition_point(|(t, _)| *t <= time);
+        quotes.insert(at, (time, rate));
+    }
+
+    fn quote(&self, from: Currency, to: Currency, time: DateTime<Utc>) -> Option<Decimal> {
+        let (_, _, quotes) = self.pairs.iter().find(|(f, t, _)| *f == from && *t == to)?;
+        let at = quotes.partition_point(|(t, _)| *t <= time);
+
+        at.checked_sub(1).map(|i| quotes[i].1)
+    }
+}
+
+impl RateSource for RateHistory {
+    fn rate(&self, from: Currency, to: Currency, time: DateTime<Utc>) -> Result<Decimal, FxError> {
+        if from == to {
+            return Ok(Decimal::ONE);
+        }
+
+        self.quote(from, to, time)
+            .or_else(|| {
+                self.quote(to, from, time)
+                    .and_then(|rate| Decimal::ONE.checked_div(rate))
+            })
+            .ok_or(FxError::NoRate { from, to, time })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn takes_the_latest_rate_quoted() {
+        let rates = rates();
+
+        assert_eq!(
+            rates.rate(Currency::EUR, Currency::GBP, day(1)),
+            Ok(dec!(0.8))
+        );
+        assert_eq!(
+            rates.rate(Currency::EUR, Currency::GBP, day(2)),
+            Ok(dec!(0.8))
+        );
+        assert_eq!(
+            rates.rate(Currency::EUR, Currency::GBP, day(5)),
+            Ok(dec!(0.9))
+        );
+    }
+
+    #[test]
+    fn converts_both_ways() {
+        let rates = rates();
+
+        assert_eq!(
+            rates.rate(Currency::GBP, Currency::EUR, day(1)),
+            Ok(dec!(1.25))
+        );
+        assert_eq!(
+            rates.convert(gbp(dec!(100)), Currency::EUR, day(1)),
+            Ok(CurrencyAmount::new(dec!(125), Currency::EUR))
+        );
+        assert_eq!(
+            rates.convert(gbp(dec!(100)), Currency::GBP, day(0)),
+            Ok(gbp(dec!(100)))
+        );
+    }
+
+    #[test]
+    fn has_no_rate_before_the_first_quote() {
+        let rates = rates();
+
+        assert_eq!(
+            rates.rate(Currency::EUR, Currency::GBP, day(0)),
+            Err(FxError::NoRate {
+                from: Currency::EUR,
+                to: Currency::GBP,
+                time: day(0)
+            })
+        );
+        assert!(rates.rate(Currency::USD, Currency::GBP, day(1)).is_err());
+        assert!(SingleCurrency
+            .rate(Currency::EUR, Currency::GBP, day(1))
+            .is_err());
+    }
+
+    // Fixtures
+
+    fn rates() -> RateHistory {
+        let mut rates = RateHistory::new();
+        rates.insert(Currency::EUR, Currency::GBP, day(3), dec!(0.9));
+        rates.insert(Currency::EUR, Currency::GBP, day(1), dec!(0.8));
+
+        rates
+    }
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn day(n: i64) -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0) + Duration::days(n)
+    }
+}
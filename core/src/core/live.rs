@@ -0,0 +1,359 @@
+use chrono::Utc;
+use rust_decimal::Decimal;
+
+use crate::core::account::Account;
+use crate::core::broker::{OrderBroker, OrderError};
+use crate::core::price::Frame;
+use crate::core::reconcile::{adopt, discrepancies, Discrepancy, Policy};
+use crate::core::strategy::{RiskStrategy, TradingStrategy};
+use crate::core::trade::Order;
+
+// Trades an account through a broker as new prices arrive
+pub struct LiveRunner<TS, RS, B>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+    B: OrderBroker,
+{
+    pub account: Account<TS, RS>,
+    pub broker: B,
+    pub trace: Vec<Result<Order, String>>,
+    pub halted: bool, // orders aren't placed until the account is reconciled by hand
+}
+
+impl<TS, RS, B> LiveRunner<TS, RS, B>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+    B: OrderBroker,
+{
+    pub fn new(account: Account<TS, RS>, broker: B) -> Self {
+        Self {
+            account,
+            broker,
+            trace: Vec::new(),
+            halted: false,
+        }
+    }
+
+    // Feed past prices to the strategies without trading, e.g. history before going live
+    pub fn warm_up(&mut self, prices: &[Frame]) {
+        for price in prices {
+            self.account.update_price(*price);
+        }
+    }
+
+    // Update the account with a new price and place the resulting orders,
+    // returns the orders as they were filled or why they weren't
+    pub fn update_price(&mut self, frame: Frame) -> Vec<Result<Order, String>> {
+        let orders = self.account.update_price(frame);
+
+        let events: Vec<Result<Order, String>> = orders
+            .iter()
+            .map(|order| match self.halted {
+                true => Err(format!("Trading halted, not placing {:?}", order)),
+                false => execute(&mut self.account, &mut self.broker, order),
+            })
+            .collect();
+
+        self.trace.extend(events.iter().cloned());
+
+        events
+    }
+
+    // Compare the account with the broker and deal with any discrepancies as the policy says,
+    // returns the discrepancies found
+    //
+    // Trading halts when the account can't be brought in line with the broker either.
+    pub fn reconcile(
+        &mut self,
+        policy: Policy,
+        tolerance: Decimal,
+    ) -> Result<Vec<Discrepancy>, OrderError> {
+        let positions = self.broker.positions()?;
+        let balance = self.broker.balance()?;
+
+        let found = discrepancies(&self.account, &positions, balance, tolerance);
+
+        match policy {
+            Policy::Halt => self.halted = self.halted || !found.is_empty(),
+            Policy::Adopt => {
+                let time = self
+                    .account
+                    .price_history
+                    .latest()
+                    .map_or_else(Utc::now, |f| f.close_time);
+
+                for discrepancy in &found {
+                    if let Err(e) = adopt(&mut self.account, discrepancy, time) {
+                        self.trace
+                            .push(Err(format!("Cannot adopt {:?}: {}", discrepancy, e)));
+                        self.halted = true;
+                    }
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+// Place an order with a broker and log the fill in the account
+pub fn execute<TS, RS, B>(
+    account: &mut Account<TS, RS>,
+    broker: &mut B,
+    order: &Order,
+) -> Result<Order, String>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+    B: OrderBroker + ?Sized,
+{
+    let filled = broker.place(order).map_err(|e| format!("{}", e))?;
+
+    account
+        .log_order(filled.clone())
+        .map_err(|e| format!("{}", e))?;
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use iso_currency::Currency;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::core::broker::{OpenPosition, SimulatedBroker};
+    use crate::core::market::Market;
+    use crate::core::price::{CurrencyAmount, Points, Price, Resolution};
+    use crate::core::strategy::{Context, RiskStrategyError, Trend};
+    use crate::core::trade::{Amendment, Direction, Entry, Exit, StopType, TradeStatus};
+
+    #[test]
+    fn logs_fills_into_the_account() {
+        let mut runner = LiveRunner::new(account(), Slipping::new());
+
+        let events = runner.update_price(frame(1, dec!(1000)));
+
+        let filled = Entry {
+            position_id: "deal-1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(1002), // one point worse than the close ask
+            stop: dec!(951),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), Currency::GBP), // risking 5% of 1000
+            time: date(1),
+        };
+        assert_eq!(events, vec![Ok(Order::Open(filled))]);
+
+        let trades = runner.account.trade_log(frame(1, dec!(1000)).close);
+        assert_eq!(trades[0].id, "deal-1");
+        assert_eq!(trades[0].entry_price, dec!(1002));
+    }
+
+    #[test]
+    fn reports_rejections_without_logging_them() {
+        let mut broker = Slipping::new();
+        broker.reject = true;
+        let mut runner = LiveRunner::new(account(), broker);
+
+        let events = runner.update_price(frame(1, dec!(1000)));
+
+        assert_eq!(
+            events,
+            vec![Err("Order rejected: MARKET_CLOSED".to_string())]
+        );
+        assert_eq!(runner.account.trade_log(frame(1, dec!(1000)).close), vec![]);
+        assert_eq!(runner.trace, events);
+    }
+
+    #[test]
+    fn closes_positions_at_the_broker() {
+        let mut runner = LiveRunner::new(account(), Slipping::new());
+
+        runner.update_price(frame(1, dec!(1000)));
+        let events = runner.update_price(frame(2, dec!(940))); // through the stop
+
+        // Stopped out and back in
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], Ok(Order::Stop(_))));
+        assert!(matches!(events[1], Ok(Order::Open(_))));
+
+        let trades = runner.account.trade_log(frame(2, dec!(940)).close);
+        assert_eq!(trades[0].status, TradeStatus::Closed);
+        assert_eq!(trades[0].exit_price, Some(dec!(950))); // one point worse than the stop
+        assert_eq!(runner.trace.len(), 3);
+    }
+
+    #[test]
+    fn trades_like_a_backtest_with_a_simulated_broker() {
+        let broker = SimulatedBroker::new(market(), CurrencyAmount::new(dec!(1000), Currency::GBP));
+        let mut runner = LiveRunner::new(account(), broker);
+
+        runner.update_price(frame(1, dec!(1000)));
+        runner.update_price(frame(2, dec!(940)));
+
+        assert_eq!(runner.account.balance, runner.broker.balance);
+        assert!(runner.account.balance < CurrencyAmount::new(dec!(1000), Currency::GBP));
+    }
+
+    #[test]
+    fn halts_trading_on_discrepancies() {
+        let broker = SimulatedBroker::new(market(), CurrencyAmount::new(dec!(1000), Currency::GBP));
+        let mut runner = LiveRunner::new(account(), broker);
+        runner.update_price(frame(1, dec!(1000)));
+        runner.broker.positions.clear(); // closed by hand
+
+        let found = runner.reconcile(Policy::Halt, dec!(0.01)).unwrap();
+
+        assert!(matches!(found[..], [Discrepancy::MissingPosition(_)]));
+        assert!(runner.halted);
+        assert_eq!(
+            runner.update_price(frame(2, dec!(940))).len(),
+            2 // stop and reopen, neither placed
+        );
+        assert!(runner.trace[1..].iter().all(|event| event.is_err()));
+    }
+
+    #[test]
+    fn adopts_the_broker_state() {
+        let broker = SimulatedBroker::new(market(), CurrencyAmount::new(dec!(1000), Currency::GBP));
+        let mut runner = LiveRunner::new(account(), broker);
+        runner.update_price(frame(1, dec!(1000)));
+        runner.broker.positions.clear(); // stopped out at the broker
+        runner.broker.balance = CurrencyAmount::new(dec!(950), Currency::GBP);
+
+        let found = runner.reconcile(Policy::Adopt, dec!(0.01)).unwrap();
+
+        assert_eq!(found.len(), 2);
+        assert!(!runner.halted);
+        assert_eq!(runner.account.live_trades(), &[]);
+        assert_eq!(runner.account.balance, runner.broker.balance);
+        assert_eq!(runner.reconcile(Policy::Halt, dec!(0.01)), Ok(vec![]));
+    }
+
+    // Fixtures
+
+    // Always bullish, reopens as soon as it gets stopped out
+    struct Bullish {}
+
+    impl TradingStrategy for Bullish {
+        fn update(&mut self, _context: &Context) {}
+
+        fn trend(&self, _context: &Context) -> Trend {
+            Trend::Bullish
+        }
+    }
+
+    // Stop 50 points below the entry
+    struct FixedStop {}
+
+    impl RiskStrategy for FixedStop {
+        fn stop(
+            &self,
+            _direction: Direction,
+            context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            let latest = context
+                .history
+                .latest()
+                .ok_or(RiskStrategyError::NotEnoughHistory)?;
+
+            Ok(latest.close.ask - dec!(50))
+        }
+    }
+
+    // Broker filling a point worse than asked, with its own deal ids
+    struct Slipping {
+        reject: bool,
+        deals: usize,
+    }
+
+    impl Slipping {
+        fn new() -> Self {
+            Self {
+                reject: false,
+                deals: 0,
+            }
+        }
+    }
+
+    impl OrderBroker for Slipping {
+        fn open(&mut self, entry: &Entry) -> Result<Entry, OrderError> {
+            if self.reject {
+                return Err(OrderError::Rejected("MARKET_CLOSED".to_string()));
+            }
+
+            self.deals += 1;
+
+            Ok(Entry {
+                position_id: format!("deal-{}", self.deals),
+                price: entry.price + dec!(1),
+                ..entry.clone()
+            })
+        }
+
+        fn close(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+            Ok(Exit {
+                price: exit.price - dec!(1),
+                ..exit.clone()
+            })
+        }
+
+        fn stop(&mut self, exit: &Exit) -> Result<Exit, OrderError> {
+            self.close(exit)
+        }
+
+        fn amend_stop(&mut self, amendment: &Amendment) -> Result<Amendment, OrderError> {
+            Ok(amendment.clone())
+        }
+
+        fn positions(&mut self) -> Result<Vec<OpenPosition>, OrderError> {
+            Ok(vec![])
+        }
+
+        fn balance(&mut self) -> Result<CurrencyAmount, OrderError> {
+            Ok(CurrencyAmount::new(dec!(1000), Currency::GBP))
+        }
+    }
+
+    fn market() -> Market {
+        Market {
+            code: "UKX".to_string(),
+            margin_factor: dec!(0.05),
+            min_deal_size: CurrencyAmount::new(dec!(0.1), Currency::GBP),
+            min_stop_distance: dec!(10),
+            guaranteed_stop: None,
+            funding: None,
+        }
+    }
+
+    fn account() -> Account<Bullish, FixedStop> {
+        Account::new(
+            market(),
+            Bullish {},
+            FixedStop {},
+            dec!(0.05),
+            CurrencyAmount::new(dec!(1000), Currency::GBP),
+            Resolution::Day,
+        )
+    }
+
+    fn frame(day: i64, mid: Decimal) -> Frame {
+        Frame {
+            open: Price::new_mid(mid, dec!(2)),
+            close: Price::new_mid(mid, dec!(2)),
+            low: Price::new_mid(mid - dec!(5), dec!(2)),
+            high: Price::new_mid(mid + dec!(5), dec!(2)),
+            close_time: date(day),
+        }
+    }
+
+    fn date(day: i64) -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 1).and_hms(0, 0, 0) + Duration::days(day)
+    }
+}
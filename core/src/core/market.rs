@@ -1,16 +1,102 @@
 use std::fmt::Display;
 
+use chrono::{DateTime, Datelike, Utc, Weekday};
+use iso_currency::Currency;
 use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use super::price::{CurrencyAmount, Points};
-use super::trade::Entry;
+use super::trade::{Direction, Entry, StopType};
 
 // Market holds information about a particular market and the trading rules that apply
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Market {
     pub code: String,
     pub margin_factor: Decimal,
     pub min_deal_size: CurrencyAmount, // per point
     pub min_stop_distance: Points,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub guaranteed_stop: Option<GuaranteedStop>, // None when the market doesn't offer them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub funding: Option<Funding>, // charged overnight, None for markets without
+}
+
+// Terms of guaranteed stops in a market
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GuaranteedStop {
+    pub premium: Points, // per unit of size, charged when the stop is hit
+    pub min_distance: Points,
+}
+
+impl GuaranteedStop {
+    pub fn new(premium: Points, min_distance: Points) -> Self {
+        Self {
+            premium,
+            min_distance,
+        }
+    }
+}
+
+// Funding of the positions held overnight, charged on their value each weekday
+//
+// Long positions pay the benchmark rate plus the admin fee, short ones receive the
+// benchmark rate less the fee - or pay, when the fee is higher. The weekend is charged
+// on one of the weekdays, e.g. Friday for indices.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Funding {
+    pub benchmark: Decimal, // annual rate, e.g. 0.01 for 1%
+    pub admin_fee: Decimal, // annual, on top of the benchmark
+    pub triple_day: Weekday,
+}
+
+impl Funding {
+    pub fn new(benchmark: Decimal, admin_fee: Decimal, triple_day: Weekday) -> Self {
+        Self {
+            benchmark,
+            admin_fee,
+            triple_day,
+        }
+    }
+
+    // Annual rate a position pays, negative when it receives funding
+    pub fn rate(&self, direction: Direction) -> Decimal {
+        match direction {
+            Direction::Buy => self.benchmark + self.admin_fee,
+            Direction::Sell => self.admin_fee - self.benchmark,
+        }
+    }
+
+    // Days charged for the nights between two times, a night belongs to the day before
+    pub fn days(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Decimal {
+        let mut days = dec!(0);
+        let mut night = from.date();
+        while night < to.date() {
+            match night.weekday() {
+                Weekday::Sat | Weekday::Sun => (),
+                weekday if weekday == self.triple_day => days += dec!(3),
+                _ => days += dec!(1),
+            }
+            night = night.succ();
+        }
+
+        days
+    }
+
+    // Paid by a position held between two times at a price, negative when received
+    pub fn cost(
+        &self,
+        entry: &Entry,
+        price: Points,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> CurrencyAmount {
+        entry.size * price * self.rate(entry.direction) * self.days(from, to) / dec!(365)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -18,6 +104,7 @@ pub enum MarketError {
     DealTooSmall,        // size below min_deal_size
     StopTooClose,        // stop-loss is not far enough
     InsufficientBalance, // would result in margin call
+    NoGuaranteedStops,   // guaranteed stop in a market that doesn't offer them
 }
 
 impl Display for MarketError {
@@ -26,6 +113,7 @@ impl Display for MarketError {
             MarketError::DealTooSmall => write!(f, "Deal size is below minimum"),
             MarketError::StopTooClose => write!(f, "Stop is is below minimum distance"),
             MarketError::InsufficientBalance => write!(f, "Insufficient balance to place trade"),
+            MarketError::NoGuaranteedStops => write!(f, "Guaranteed stops are not available"),
         }
     }
 }
@@ -44,21 +132,50 @@ impl Market {
             return Err(MarketError::InsufficientBalance);
         }
 
-        if (order.price - order.stop).abs() < self.min_stop_distance {
+        let min_stop_distance = match (order.stop_type, self.guaranteed_stop) {
+            (StopType::Normal, _) => self.min_stop_distance,
+            (StopType::Guaranteed { .. }, Some(guaranteed)) => guaranteed.min_distance,
+            (StopType::Guaranteed { .. }, None) => return Err(MarketError::NoGuaranteedStops),
+        };
+
+        if (order.price - order.stop).abs() < min_stop_distance {
             return Err(MarketError::StopTooClose);
         }
 
         Ok(())
     }
 
+    // Entry with a guaranteed stop, on the market's terms, if it offers them
+    pub fn guarantee(&self, entry: Entry) -> Entry {
+        match self.guaranteed_stop {
+            Some(guaranteed) => Entry {
+                stop_type: StopType::Guaranteed {
+                    premium: guaranteed.premium,
+                },
+                ..entry
+            },
+            None => entry,
+        }
+    }
+
     fn margin_requirement(&self, order: &Entry) -> CurrencyAmount {
-        order.size * order.price * self.margin_factor
+        self.margin(order.size, order.price)
+    }
+
+    // Currency the deal sizes, and so the profits, are in
+    pub fn currency(&self) -> Currency {
+        self.min_deal_size.currency
+    }
+
+    // Margin held by a position of a given size at a given price
+    pub fn margin(&self, size: CurrencyAmount, price: Points) -> CurrencyAmount {
+        size * price * self.margin_factor
     }
 }
 
 #[cfg(test)]
 mod test {
-    use chrono::{DateTime, TimeZone, Utc};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
     use iso_currency::Currency;
     use rust_decimal_macros::dec;
 
@@ -79,6 +196,7 @@ mod test {
             direction: Direction::Buy,
             price,
             stop: price - stop_distance,
+            stop_type: StopType::Normal,
             size: balance * risk_per_trade / stop_distance,
             time: date(),
         };
@@ -102,6 +220,7 @@ mod test {
             direction: Direction::Buy,
             price,
             stop: price - stop_distance,
+            stop_type: StopType::Normal,
             size: balance * risk_per_trade / stop_distance,
             time: date(),
         };
@@ -125,6 +244,7 @@ mod test {
             direction: Direction::Buy,
             price,
             stop: price - stop_distance,
+            stop_type: StopType::Normal,
             size: balance * risk_per_trade / stop_distance,
             time: date(),
         };
@@ -148,6 +268,7 @@ mod test {
             direction: Direction::Buy,
             price,
             stop: price - stop_distance,
+            stop_type: StopType::Normal,
             size: balance * risk_per_trade / stop_distance,
             time: date(),
         };
@@ -158,12 +279,113 @@ mod test {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn enforces_the_minimum_distance_of_guaranteed_stops() {
+        let market = Market {
+            guaranteed_stop: Some(GuaranteedStop::new(dec!(2), dec!(50))),
+            ..market()
+        };
+        let balance = CurrencyAmount::new(dec!(1000), Currency::GBP);
+        let guaranteed = |stop_distance| {
+            market.guarantee(Entry {
+                stop: dec!(15000) - stop_distance,
+                ..entry(dec!(1))
+            })
+        };
+
+        assert_eq!(
+            guaranteed(dec!(50)).stop_type,
+            StopType::Guaranteed { premium: dec!(2) }
+        );
+        assert_eq!(
+            market.validate_entry(&guaranteed(dec!(50)), balance),
+            Ok(())
+        );
+        assert_eq!(
+            market.validate_entry(&guaranteed(dec!(20)), balance),
+            Err(MarketError::StopTooClose)
+        );
+    }
+
+    #[test]
+    fn rejects_guaranteed_stops_where_not_offered() {
+        let market = market();
+        let balance = CurrencyAmount::new(dec!(1000), Currency::GBP);
+        let guaranteed = Entry {
+            stop_type: StopType::Guaranteed { premium: dec!(2) },
+            ..entry(dec!(1))
+        };
+
+        assert_eq!(market.guarantee(entry(dec!(1))), entry(dec!(1)));
+        assert_eq!(
+            market.validate_entry(&guaranteed, balance),
+            Err(MarketError::NoGuaranteedStops)
+        );
+    }
+
+    #[test]
+    fn charges_funding_for_the_nights_held() {
+        let funding = Funding::new(dec!(0.01), dec!(0.025), Weekday::Fri);
+        let monday = Utc.ymd(2021, 1, 4).and_hms(20, 0, 0);
+        let long = entry(dec!(1));
+
+        // Long 1 GBP per point at 14600, 3.5% a year
+        assert_eq!(
+            funding.cost(&long, dec!(14600), monday, monday + Duration::days(1)),
+            CurrencyAmount::new(dec!(1.4), Currency::GBP)
+        );
+        // Intraday
+        assert_eq!(
+            funding.cost(&long, dec!(14600), monday, monday + Duration::hours(1)),
+            CurrencyAmount::new(dec!(0), Currency::GBP)
+        );
+    }
+
+    #[test]
+    fn charges_the_weekend_on_the_triple_day() {
+        let funding = Funding::new(dec!(0.01), dec!(0.025), Weekday::Fri);
+        let monday = Utc.ymd(2021, 1, 4).and_hms(20, 0, 0);
+
+        assert_eq!(funding.days(monday, monday + Duration::days(4)), dec!(4));
+        assert_eq!(funding.days(monday, monday + Duration::days(7)), dec!(7));
+        // Friday to Monday, with or without prices on the weekend
+        let friday = monday + Duration::days(4);
+        assert_eq!(funding.days(friday, friday + Duration::days(1)), dec!(3));
+        assert_eq!(funding.days(friday, friday + Duration::days(3)), dec!(3));
+    }
+
+    #[test]
+    fn pays_short_positions_the_benchmark_less_the_fee() {
+        let funding = Funding::new(dec!(0.01), dec!(0.025), Weekday::Fri);
+
+        assert_eq!(funding.rate(Direction::Buy), dec!(0.035));
+        assert_eq!(funding.rate(Direction::Sell), dec!(0.015));
+        assert_eq!(
+            Funding::new(dec!(0.05), dec!(0.025), Weekday::Fri).rate(Direction::Sell),
+            dec!(-0.025)
+        );
+    }
+
+    fn entry(size: Decimal) -> Entry {
+        Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(15000),
+            stop: dec!(14950),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(size, Currency::GBP),
+            time: date(),
+        }
+    }
+
     fn market() -> Market {
         Market {
             code: "GDAXI".to_string(),
             margin_factor: dec!(0.05), // 5%
             min_deal_size: CurrencyAmount::new(dec!(0.50), Currency::GBP),
             min_stop_distance: dec!(12),
+            guaranteed_stop: None,
+            funding: None,
         }
     }
 
@@ -1,24 +1,70 @@
-use rust_decimal::Decimal;
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, MathematicalOps};
 use rust_decimal_macros::dec;
 
+use super::price::{Frame, Price};
+
+// Indicator is a stateful calculation over a stream of inputs. Each new input updates
+// the state, so that the current value doesn't need to be recalculated from history.
+pub trait Indicator {
+    type Input;
+    type Output;
+
+    fn push(&mut self, input: Self::Input);
+
+    // Current value of the indicator, None if it hasn't seen any input yet
+    fn value(&self) -> Option<Self::Output>;
+}
+
+// Exponential Moving Average
+
 #[derive(Clone, Debug)]
-pub struct EMA<I> {
-    iter: I,
+pub struct EMA {
     prev: Option<Decimal>,
     alpha: Decimal,
 }
 
-impl<I> EMA<I> {
-    pub fn new(iter: I, length: usize) -> Self {
+impl EMA {
+    pub fn new(length: usize) -> Self {
         Self {
-            iter,
             prev: None,
             alpha: dec!(2.0) / Decimal::from(length + 1),
         }
     }
+
+    // Samples after which the weight of the first one drops below the given error
+    pub fn samples_needed(length: usize, error: Decimal) -> usize {
+        let alpha = dec!(2.0) / Decimal::from(length + 1);
+        (error.ln() / -alpha).round().to_usize().unwrap_or(0)
+    }
+}
+
+impl Indicator for EMA {
+    type Input = Decimal;
+    type Output = Decimal;
+
+    fn push(&mut self, current: Decimal) {
+        self.prev = match self.prev {
+            Some(prev) => Some(current * self.alpha + prev * (dec!(1.0) - self.alpha)),
+            None => Some(current),
+        };
+    }
+
+    fn value(&self) -> Option<Decimal> {
+        self.prev
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EMAIter<I> {
+    iter: I,
+    ema: EMA,
 }
 
-impl<I, T> Iterator for EMA<I>
+impl<I, T> Iterator for EMAIter<I>
 where
     I: Iterator<Item = T>,
     T: std::ops::Mul<Decimal, Output = Decimal> + Copy,
@@ -26,41 +72,207 @@ where
     type Item = Decimal;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match (self.prev, self.iter.next()) {
-            (Some(prev), Some(current)) => {
-                let v = Some(current * self.alpha + prev * (dec!(1.0) - self.alpha));
-                self.prev = v;
-
-                v
-            }
-            (None, Some(current)) => {
-                // multiply to avoid T != Decimal type error.
-                // I'm sure there's a way to constrain T to not need this
-                let v = Some(current * dec!(1.0));
-                self.prev = v;
+        // multiply to avoid T != Decimal type error.
+        // I'm sure there's a way to constrain T to not need this
+        let current = self.iter.next()? * dec!(1.0);
+        self.ema.push(current);
 
-                v
-            }
-            _ => None,
-        }
+        self.ema.value()
     }
 }
 
 pub trait EMAIterator<T>: Iterator<Item = T> + Sized {
-    fn ema(self, length: usize) -> EMA<Self> {
-        EMA::new(self, length)
+    fn ema(self, length: usize) -> EMAIter<Self> {
+        EMAIter {
+            iter: self,
+            ema: EMA::new(length),
+        }
     }
 }
 
 impl<T, I: Iterator<Item = T>> EMAIterator<T> for I {}
 
+// Moving Average Convergence/Divergence of the mid close price
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MACDLines {
+    pub short_ema: Decimal,
+    pub long_ema: Decimal,
+    pub macd: Decimal,
+    pub macd_signal: Decimal,
+    pub macd_trend: Decimal,
+}
+
+#[derive(Clone, Debug)]
+pub struct MACDIndicator {
+    short_ema: EMA,
+    long_ema: EMA,
+    macd_signal: EMA,
+}
+
+impl MACDIndicator {
+    pub fn new(short: usize, long: usize, signal: usize) -> Self {
+        Self {
+            short_ema: EMA::new(short),
+            long_ema: EMA::new(long),
+            macd_signal: EMA::new(signal),
+        }
+    }
+}
+
+impl Indicator for MACDIndicator {
+    type Input = Frame;
+    type Output = MACDLines;
+
+    fn push(&mut self, frame: Frame) {
+        let price = frame.close.mid_price();
+
+        self.short_ema.push(price);
+        self.long_ema.push(price);
+
+        if let (Some(short), Some(long)) = (self.short_ema.value(), self.long_ema.value()) {
+            self.macd_signal.push(short - long);
+        }
+    }
+
+    fn value(&self) -> Option<MACDLines> {
+        let short_ema = self.short_ema.value()?;
+        let long_ema = self.long_ema.value()?;
+        let macd = short_ema - long_ema;
+        let macd_signal = self.macd_signal.value()?;
+
+        Some(MACDLines {
+            short_ema,
+            long_ema,
+            macd,
+            macd_signal,
+            macd_trend: macd - macd_signal,
+        })
+    }
+}
+
+// Donchian Channel - moving minimum and maximum price over the past `length` frames
+//
+// The lower end of the channel is a bid price - we are selling to exit a long position that didn't go our way
+// The higher end of the channel is an ask price - we are buying to exit a short position that didn't go our way
+
+#[derive(Clone, Debug)]
+pub struct DonchianChannel {
+    length: usize,
+    count: usize,
+    // Monotonic queues of (frame number, price) candidates for the minimum and maximum,
+    // the front is always the extreme of the current window
+    lows: VecDeque<(usize, Decimal)>,
+    highs: VecDeque<(usize, Decimal)>,
+}
+
+impl DonchianChannel {
+    pub fn new(length: usize) -> Self {
+        Self {
+            length: length.max(1),
+            count: 0,
+            lows: VecDeque::new(),
+            highs: VecDeque::new(),
+        }
+    }
+}
+
+impl Indicator for DonchianChannel {
+    type Input = Frame;
+    type Output = (Decimal, Decimal);
+
+    fn push(&mut self, frame: Frame) {
+        let idx = self.count;
+        self.count += 1;
+
+        while matches!(self.lows.back(), Some((_, low)) if *low >= frame.low.bid) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((idx, frame.low.bid));
+
+        while matches!(self.highs.back(), Some((_, high)) if *high <= frame.high.ask) {
+            self.highs.pop_back();
+        }
+        self.highs.push_back((idx, frame.high.ask));
+
+        // Drop candidates which fell out of the window
+        let oldest = self.count.saturating_sub(self.length);
+
+        while matches!(self.lows.front(), Some((i, _)) if *i < oldest) {
+            self.lows.pop_front();
+        }
+        while matches!(self.highs.front(), Some((i, _)) if *i < oldest) {
+            self.highs.pop_front();
+        }
+    }
+
+    fn value(&self) -> Option<(Decimal, Decimal)> {
+        Some((self.lows.front()?.1, self.highs.front()?.1))
+    }
+}
+
+// Average True Range with Wilder's smoothing
+//
+// True range includes the spread - it spans from the lowest bid to the highest ask
+// of the frame and the previous close, so the gaps between frames are included.
+
+#[derive(Clone, Debug)]
+pub struct ATR {
+    length: usize,
+    count: usize,
+    prev_close: Option<Price>,
+    atr: Option<Decimal>,
+}
+
+impl ATR {
+    pub fn new(length: usize) -> Self {
+        Self {
+            length: length.max(1),
+            count: 0,
+            prev_close: None,
+            atr: None,
+        }
+    }
+
+    pub fn true_range(frame: &Frame, prev_close: Option<Price>) -> Decimal {
+        match prev_close {
+            Some(prev) => max(frame.high.ask, prev.ask) - min(frame.low.bid, prev.bid),
+            None => frame.high.ask - frame.low.bid,
+        }
+    }
+}
+
+impl Indicator for ATR {
+    type Input = Frame;
+    type Output = Decimal;
+
+    fn push(&mut self, frame: Frame) {
+        let tr = Self::true_range(&frame, self.prev_close);
+        self.prev_close = Some(frame.close);
+        self.count += 1;
+
+        // Simple average of the first `length` true ranges, smoothed from there on
+        let n = Decimal::from(self.count.min(self.length));
+        self.atr = match self.atr {
+            Some(atr) => Some((atr * (n - dec!(1)) + tr) / n),
+            None => Some(tr),
+        };
+    }
+
+    fn value(&self) -> Option<Decimal> {
+        self.atr
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use chrono::{TimeZone, Utc};
+
     #[test]
     fn empty_value_ema() {
-        let actual: Vec<_> = vec![].iter().ema(40).collect();
+        let actual: Vec<_> = Vec::<Decimal>::new().iter().ema(40).collect();
         let expected = vec![];
 
         assert_eq!(actual, expected);
@@ -95,4 +307,152 @@ mod tests {
         // short converges to 5.0 faster
         assert!(actual_short.iter().zip(&actual_long).all(|(s, l)| s >= l));
     }
+
+    #[test]
+    fn streaming_ema_matches_iterator() {
+        let values: Vec<_> = (0..30).map(|i| Decimal::from(i * i % 7)).collect();
+        let expected: Vec<_> = values.iter().ema(5).collect();
+
+        let mut ema = EMA::new(5);
+        let actual: Vec<_> = values
+            .iter()
+            .map(|v| {
+                ema.push(*v);
+                ema.value().unwrap()
+            })
+            .collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn macd_of_a_constant_price() {
+        let mut macd = MACDIndicator::new(12, 26, 9);
+
+        assert_eq!(macd.value(), None);
+
+        for _ in 0..10 {
+            macd.push(frame(dec!(100), dec!(100), dec!(100)));
+        }
+
+        let expected = Some(MACDLines {
+            short_ema: dec!(100),
+            long_ema: dec!(100),
+            macd: dec!(0),
+            macd_signal: dec!(0),
+            macd_trend: dec!(0),
+        });
+
+        assert_eq!(macd.value(), expected);
+    }
+
+    #[test]
+    fn macd_follows_a_rising_price() {
+        let mut macd = MACDIndicator::new(3, 10, 4);
+
+        for i in 0..20 {
+            let price = Decimal::from(100 + i * 10);
+            macd.push(frame(price, price, price));
+        }
+
+        let lines = macd.value().unwrap();
+
+        // short EMA tracks the price more closely
+        assert!(lines.short_ema > lines.long_ema);
+        assert!(lines.macd > dec!(0));
+        assert_eq!(lines.macd_trend, lines.macd - lines.macd_signal);
+    }
+
+    #[test]
+    fn donchian_channel_of_a_single_frame_window() {
+        let mut channel = DonchianChannel::new(1);
+
+        assert_eq!(channel.value(), None);
+
+        channel.push(frame(dec!(100), dec!(90), dec!(110)));
+        assert_eq!(channel.value(), Some((dec!(89.5), dec!(110.5))));
+
+        channel.push(frame(dec!(200), dec!(190), dec!(210)));
+        assert_eq!(channel.value(), Some((dec!(189.5), dec!(210.5))));
+    }
+
+    #[test]
+    fn donchian_channel_matches_window_extremes() {
+        let length = 4;
+        let frames: Vec<_> = (0..40)
+            .map(|i| {
+                let mid = Decimal::from((i * 37) % 23 + 100);
+                frame(mid, mid - Decimal::from(i % 5), mid + Decimal::from(i % 3))
+            })
+            .collect();
+
+        let mut channel = DonchianChannel::new(length);
+
+        for (idx, f) in frames.iter().enumerate() {
+            channel.push(*f);
+
+            let window = &frames[(idx + 1).saturating_sub(length)..=idx];
+            let expected = (
+                window.iter().map(|f| f.low.bid).min().unwrap(),
+                window.iter().map(|f| f.high.ask).max().unwrap(),
+            );
+
+            assert_eq!(channel.value(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn atr_of_a_constant_range() {
+        let mut atr = ATR::new(14);
+
+        assert_eq!(atr.value(), None);
+
+        for _ in 0..20 {
+            atr.push(frame(dec!(100), dec!(90), dec!(110)));
+            // 89.5 to 110.5
+            assert_eq!(atr.value(), Some(dec!(21)));
+        }
+    }
+
+    #[test]
+    fn atr_includes_gaps_between_frames() {
+        let mut atr = ATR::new(1);
+
+        atr.push(frame(dec!(100), dec!(90), dec!(110)));
+        // gap up, previous close bid 99.5 to the high ask 130.5
+        atr.push(frame(dec!(125), dec!(120), dec!(130)));
+        assert_eq!(atr.value(), Some(dec!(31)));
+
+        // gap down, low bid 99.5 to previous close ask 125.5
+        atr.push(frame(dec!(105), dec!(100), dec!(110)));
+        assert_eq!(atr.value(), Some(dec!(26)));
+    }
+
+    #[test]
+    fn atr_uses_wilder_smoothing() {
+        let mut atr = ATR::new(2);
+        let mut values = vec![];
+
+        // true ranges of 10, 20, 30 and 10 around the same close
+        for range in &[dec!(9), dec!(19), dec!(29), dec!(9)] {
+            let half = *range / dec!(2);
+            atr.push(frame(dec!(100), dec!(100) - half, dec!(100) + half));
+            values.push(atr.value().unwrap());
+        }
+
+        // average of the first two, then (previous * (n - 1) + true range) / n
+        assert_eq!(values, vec![dec!(10), dec!(15), dec!(22.5), dec!(16.25)]);
+    }
+
+    // Fixtures
+
+    fn frame(close: Decimal, low: Decimal, high: Decimal) -> Frame {
+        Frame {
+            open: Price::new_mid(close, dec!(1)),
+            close: Price::new_mid(close, dec!(1)),
+            low: Price::new_mid(low, dec!(1)),
+            high: Price::new_mid(high, dec!(1)),
+            close_time: Utc.ymd(2021, 1, 1).and_hms(10, 0, 0),
+        }
+    }
 }
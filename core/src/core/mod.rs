@@ -1,8 +1,15 @@
 pub mod account;
+pub mod broker;
+pub mod fill;
+pub mod fx;
 pub mod market;
 pub mod maths;
 pub mod price;
+pub mod reconcile;
+pub mod report;
 pub mod strategy;
 pub mod trade;
 
 pub mod backtest;
+pub mod live;
+pub mod portfolio;
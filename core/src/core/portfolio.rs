@@ -0,0 +1,731 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::core::account::{Account, AccountError, Equity, MarginCall, MarginPolicy};
+use crate::core::backtest::Backtest;
+use crate::core::price::{CurrencyAmount, Frame, PriceHistory};
+use crate::core::report::Report;
+use crate::core::strategy::{RiskStrategy, TradingStrategy};
+use crate::core::trade::{Direction, Entry, Order, Position, Trade};
+
+// Limits on the risk taken across the markets of a portfolio
+//
+// Risks are fractions of the shared balance. Entries going over a limit are sized down
+// to fit under it, or not placed at all when there's no room left.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RiskLimits {
+    pub per_market: Decimal,  // open risk in any one market
+    pub total: Decimal,       // open risk across all the markets
+    pub correlated: Decimal,  // open risk in markets moving together, in the same direction
+    pub correlation: Decimal, // of returns, above which markets are taken to move together
+    pub lookback: usize,      // returns the correlation is measured over
+}
+
+impl RiskLimits {
+    pub fn new(
+        per_market: Decimal,
+        total: Decimal,
+        correlated: Decimal,
+        correlation: Decimal,
+        lookback: usize,
+    ) -> Self {
+        Self {
+            per_market,
+            total,
+            correlated,
+            correlation,
+            lookback,
+        }
+    }
+
+    // Nothing beyond the risk each account takes
+    pub fn none() -> Self {
+        Self::new(Decimal::ONE, Decimal::ONE, Decimal::ONE, Decimal::ONE, 0)
+    }
+}
+
+// Backtest of several markets traded at once from a shared balance
+//
+// Each market has its own account and simulated broker. The accounts size their entries
+// from the shared balance and the profit of their trades goes back into it, converted
+// from the market's currency where they differ. Each market goes through the same steps
+// as a backtest of it alone, but the margin is called on the whole portfolio.
+pub struct Portfolio<TS, RS>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+{
+    pub markets: Vec<Backtest<TS, RS>>,
+    pub balance: CurrencyAmount,
+    pub opening_balance: CurrencyAmount,
+    pub limits: RiskLimits,
+    pub margin_policy: MarginPolicy, // on the whole portfolio, rather than of each market
+    pub margin_calls: Vec<MarginCall>,
+    pub equity: Vec<Equity>, // of the whole portfolio, after each time prices came in
+    histories: Vec<PriceHistory>, // recent prices of each market, to correlate them
+}
+
+impl<TS, RS> Portfolio<TS, RS>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+{
+    pub fn new(
+        accounts: Vec<Account<TS, RS>>,
+        opening_balance: CurrencyAmount,
+        limits: RiskLimits,
+    ) -> Self {
+        let histories = accounts
+            .iter()
+            .map(|a| PriceHistory::bounded(a.price_history.resolution, limits.lookback + 1))
+            .collect();
+        let markets = accounts
+            .into_iter()
+            .map(|mut account| {
+                account.balance = opening_balance;
+                Backtest::new(account)
+            })
+            .collect();
+
+        Self {
+            markets,
+            balance: opening_balance,
+            opening_balance,
+            limits,
+            margin_policy: MarginPolicy::ig(),
+            margin_calls: vec![],
+            equity: vec![],
+            histories,
+        }
+    }
+
+    // Run through the prices of each market, in the same order as the markets
+    //
+    // The histories don't need to line up, e.g. when markets close on different holidays.
+    // Prices are taken in order of time and the markets without a price at some time
+    // sit that one out.
+    pub fn run(&mut self, prices: &[Vec<Frame>]) {
+        let mut next = vec![0; prices.len()];
+
+        while let Some(time) = prices
+            .iter()
+            .zip(&next)
+            .filter_map(|(frames, &i)| frames.get(i))
+            .map(|frame| frame.close_time)
+            .min()
+        {
+            let mut traded = vec![];
+            for (market, frames) in prices.iter().enumerate() {
+                if let Some(frame) = frames.get(next[market]) {
+                    if frame.close_time == time {
+                        self.update_price(market, *frame);
+                        traded.push((market, *frame));
+                        next[market] += 1;
+                    }
+                }
+            }
+
+            self.call_margin(time);
+            for (market, frame) in traded {
+                let backtest = &mut self.markets[market];
+                backtest.account.balance = self.balance;
+                backtest.value(&frame);
+            }
+
+            // A market that can't be valued has the error in its trace already
+            if let Ok(equity) = self.valuation(time) {
+                self.equity.push(equity);
+            }
+        }
+    }
+
+    // Trades of all the markets, with the market code in front of the position ids
+    pub fn trade_log(&self) -> Vec<Trade> {
+        let mut trades = vec![];
+
+        for backtest in &self.markets {
+            let account = &backtest.account;
+
+            if let Some(latest) = account.price_history.latest() {
+                for trade in account.trade_log(latest.close) {
+                    trades.push(Trade {
+                        id: format!("{}:{}", account.market.code, trade.id),
+                        ..trade
+                    });
+                }
+            }
+        }
+
+        trades.sort_by_key(|t| t.entry_time);
+
+        trades
+    }
+
+    // Performance summary of the whole portfolio
+    pub fn report(&self) -> Report {
+        Report::new(self.opening_balance, &self.trade_log(), &self.equity)
+    }
+
+    fn update_price(&mut self, market: usize, frame: Frame) {
+        self.histories[market].push(frame);
+
+        // The broker can only use the balance not held as margin in the other markets
+        let margin_elsewhere = self
+            .markets
+            .iter()
+            .enumerate()
+            .filter(|(m, _)| *m != market)
+            .flat_map(|(_, b)| {
+                let account = &b.account;
+                account.live_trades().iter().map(move |p| {
+                    let margin = account.market.margin(p.entry.size, p.entry.price);
+                    account.in_balance_currency(margin).amount
+                })
+            })
+            .sum::<Decimal>();
+
+        // Positions elsewhere stay put while this market trades
+        let elsewhere = RiskElsewhere {
+            limits: self.limits,
+            total: self.open_risk(|m, _| m != market),
+            buying: self.open_risk(|m, p| {
+                m != market && self.moves_with(market, Direction::Buy, m, p.entry.direction)
+            }),
+            selling: self.open_risk(|m, p| {
+                m != market && self.moves_with(market, Direction::Sell, m, p.entry.direction)
+            }),
+        };
+
+        let backtest = &mut self.markets[market];
+        backtest.account.balance = self.balance;
+        backtest.broker.balance = CurrencyAmount::new(
+            self.balance.amount - margin_elsewhere,
+            self.balance.currency,
+        );
+
+        backtest.trade(&frame, |account, order| elsewhere.limit(account, order));
+        self.balance = backtest.account.balance;
+    }
+
+    // Act on the margin level of the whole portfolio, once the markets have their prices
+    //
+    // Losses and margin add up across the markets, so a portfolio can be called while no
+    // market would be on its own. Positions are liquidated at the latest close of their
+    // market, the biggest loss in any market first, until the level is back above the
+    // liquidation level.
+    fn call_margin(&mut self, time: DateTime<Utc>) {
+        let call = match self.valuation(time).map(|e| self.margin_policy.call(&e)) {
+            Ok(Some(call)) => call,
+            _ => return,
+        };
+        self.margin_calls.push(call);
+
+        while let Ok(Some(MarginCall::Liquidation(_))) =
+            self.valuation(time).map(|e| self.margin_policy.call(&e))
+        {
+            let (market, position_id, frame) = match self.biggest_loss() {
+                Some(position) => position,
+                None => break,
+            };
+
+            let backtest = &mut self.markets[market];
+            backtest.account.balance = self.balance;
+            let liquidated = backtest.liquidate(&position_id, &frame);
+            self.balance = backtest.account.balance;
+            if !liquidated {
+                break;
+            }
+        }
+    }
+
+    // Market, id and latest frame of the position losing the most in the balance currency
+    fn biggest_loss(&self) -> Option<(usize, String, Frame)> {
+        self.markets
+            .iter()
+            .enumerate()
+            .filter_map(|(m, b)| Some((m, &b.account, *b.account.price_history.latest()?)))
+            .flat_map(|(m, account, frame)| {
+                account.live_trades().iter().map(move |p| {
+                    let profit = Trade::open(&p.entry, frame.close).profit;
+                    let profit = account.in_balance_currency(profit).amount;
+
+                    (profit, (m, p.entry.position_id.clone(), frame))
+                })
+            })
+            .min_by_key(|(profit, _)| *profit)
+            .map(|(_, position)| position)
+    }
+
+    // Open risk of the positions across the markets matching a filter
+    fn open_risk<F>(&self, include: F) -> Decimal
+    where
+        F: Fn(usize, &Position) -> bool,
+    {
+        self.markets
+            .iter()
+            .enumerate()
+            .flat_map(|(m, b)| b.account.live_trades().iter().map(move |p| (m, p)))
+            .filter(|(m, p)| include(*m, p))
+            .fold(Decimal::ZERO, |risk, (m, p)| {
+                risk + self.markets[m]
+                    .account
+                    .in_balance_currency(p.open_risk())
+                    .amount
+            })
+    }
+
+    // Whether a position in one market adds to the exposure of a position in another
+    //
+    // Positions in the same direction in markets moving together do, so do positions in
+    // opposite directions in markets moving against each other.
+    fn moves_with(
+        &self,
+        a: usize,
+        a_direction: Direction,
+        b: usize,
+        b_direction: Direction,
+    ) -> bool {
+        if a == b {
+            return a_direction == b_direction;
+        }
+
+        match correlation(self.histories[a].frames(), self.histories[b].frames()) {
+            Some(c) if c >= self.limits.correlation => a_direction == b_direction,
+            Some(c) if c <= -self.limits.correlation => a_direction != b_direction,
+            _ => false,
+        }
+    }
+
+    // Value the portfolio at the latest price of each market
+    fn valuation(&self, time: DateTime<Utc>) -> Result<Equity, AccountError> {
+        let currency = self.balance.currency;
+        let mut open_pnl = CurrencyAmount::new(Decimal::ZERO, currency);
+        let mut margin_used = CurrencyAmount::new(Decimal::ZERO, currency);
+
+        for backtest in &self.markets {
+            if let Some(frame) = backtest.account.price_history.latest() {
+                let equity = backtest.account.equity(frame)?;
+                open_pnl = (open_pnl + equity.open_pnl)?;
+                margin_used = (margin_used + equity.margin_used)?;
+            }
+        }
+
+        Ok(Equity {
+            time,
+            balance: self.balance,
+            open_pnl,
+            margin_used,
+            free_margin: CurrencyAmount::new(
+                self.balance.amount + open_pnl.amount - margin_used.amount,
+                currency,
+            ),
+        })
+    }
+}
+
+// Risk open in the markets other than the one trading
+struct RiskElsewhere {
+    limits: RiskLimits,
+    total: Decimal,
+    buying: Decimal,  // correlated with buying in the trading market
+    selling: Decimal, // correlated with selling in it
+}
+
+impl RiskElsewhere {
+    // Size an entry down to fit under the risk limits
+    fn limit<TS, RS>(&self, account: &Account<TS, RS>, order: Order) -> Result<Order, String>
+    where
+        TS: TradingStrategy,
+        RS: RiskStrategy,
+    {
+        let entry = match order {
+            Order::Open(entry) => entry,
+            _ => return Ok(order),
+        };
+
+        let open_risk = |include: &dyn Fn(&Position) -> bool| {
+            account
+                .live_trades()
+                .iter()
+                .filter(|p| include(p))
+                .fold(Decimal::ZERO, |risk, p| {
+                    risk + account.in_balance_currency(p.open_risk()).amount
+                })
+        };
+        let risk = account
+            .in_balance_currency(Position::new(entry.clone()).open_risk())
+            .amount;
+        let balance = account.balance.amount;
+
+        let in_market = open_risk(&|_| true);
+        let in_total = self.total + in_market;
+        let correlated = open_risk(&|p| p.entry.direction == entry.direction)
+            + match entry.direction {
+                Direction::Buy => self.buying,
+                Direction::Sell => self.selling,
+            };
+
+        let room = (self.limits.per_market * balance - in_market)
+            .min(self.limits.total * balance - in_total)
+            .min(self.limits.correlated * balance - correlated);
+
+        if risk <= room {
+            Ok(Order::Open(entry))
+        } else if room > Decimal::ZERO {
+            Ok(Order::Open(Entry {
+                size: entry.size * (room / risk),
+                ..entry
+            }))
+        } else {
+            Err(format!(
+                "Risk limit reached in {}, not placing {:?}",
+                account.market.code,
+                Order::Open(entry)
+            ))
+        }
+    }
+}
+
+// Correlation of the returns of two markets, over the times both have prices for
+//
+// None when there are too few returns to tell, or one of the markets didn't move.
+pub fn correlation(a: &[Frame], b: &[Frame]) -> Option<Decimal> {
+    let returns = |frames: &[Frame]| -> Vec<(DateTime<Utc>, Decimal)> {
+        frames
+            .windows(2)
+            .filter_map(|w| {
+                let previous = w[0].close.mid_price();
+                (!previous.is_zero()).then(|| {
+                    (
+                        w[1].close_time,
+                        w[1].close.mid_price() / previous - Decimal::ONE,
+                    )
+                })
+            })
+            .collect()
+    };
+
+    let b_returns = returns(b);
+    let pairs: Vec<(Decimal, Decimal)> = returns(a)
+        .into_iter()
+        .filter_map(|(time, ra)| {
+            b_returns
+                .iter()
+                .find(|(t, _)| *t == time)
+                .map(|(_, rb)| (ra, *rb))
+        })
+        .collect();
+
+    if pairs.len() < 2 {
+        return None;
+    }
+
+    let n = Decimal::from(pairs.len());
+    let mean_a = pairs.iter().map(|(ra, _)| ra).sum::<Decimal>() / n;
+    let mean_b = pairs.iter().map(|(_, rb)| rb).sum::<Decimal>() / n;
+
+    let mut covariance = Decimal::ZERO;
+    let mut variance_a = Decimal::ZERO;
+    let mut variance_b = Decimal::ZERO;
+    for (ra, rb) in &pairs {
+        covariance += (ra - mean_a) * (rb - mean_b);
+        variance_a += (ra - mean_a) * (ra - mean_a);
+        variance_b += (rb - mean_b) * (rb - mean_b);
+    }
+
+    let deviation = (variance_a * variance_b).sqrt()?;
+    if deviation.is_zero() {
+        return None;
+    }
+
+    Some((covariance / deviation).round_dp(6))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use crate::core::market::Market;
+    use crate::core::price::{Points, Price, Resolution};
+    use crate::core::strategy::{Context, RiskStrategyError, Trend};
+
+    #[test]
+    fn trades_each_market_from_the_shared_balance() {
+        let mut portfolio = portfolio(&["A", "B"], RiskLimits::none());
+
+        portfolio.run(&[
+            vec![frame(1, dec!(100)), stopping_frame(2, dec!(80))],
+            vec![frame(1, dec!(200)), frame(2, dec!(200))],
+        ]);
+
+        // Gapped through the stop, filled at 79.5 after buying at 100.5, 10 per point
+        assert_eq!(portfolio.balance, gbp(dec!(9790)));
+        assert_eq!(portfolio.markets[1].account.balance, gbp(dec!(9790)));
+        assert_eq!(
+            portfolio
+                .trade_log()
+                .iter()
+                .map(|t| t.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["A:0", "B:0", "A:1"]
+        );
+        assert_eq!(portfolio.equity[1].balance, gbp(dec!(9790)));
+        assert_eq!(portfolio.report().trades, 1); // closed
+    }
+
+    #[test]
+    fn aligns_prices_by_time() {
+        let mut portfolio = portfolio(&["A", "B"], RiskLimits::none());
+
+        portfolio.run(&[
+            vec![
+                frame(1, dec!(100)),
+                frame(2, dec!(100)),
+                frame(3, dec!(100)),
+            ],
+            vec![frame(1, dec!(200)), frame(3, dec!(200))],
+        ]);
+
+        assert_eq!(
+            portfolio.equity.iter().map(|e| e.time).collect::<Vec<_>>(),
+            vec![day(1), day(2), day(3)]
+        );
+        assert_eq!(portfolio.markets[1].account.price_history.len(), 2);
+        assert_eq!(portfolio.markets[1].equity.len(), 2);
+        // Both positions valued on day 2, B at its price of day 1
+        assert_eq!(portfolio.equity[1].open_pnl, gbp(dec!(-20)));
+    }
+
+    #[test]
+    fn limits_the_risk_per_market() {
+        let limits = RiskLimits {
+            per_market: dec!(0.005),
+            ..RiskLimits::none()
+        };
+        let mut portfolio = portfolio(&["A"], limits);
+
+        portfolio.run(&[vec![frame(1, dec!(100))]]);
+
+        let position = &portfolio.markets[0].account.live_trades()[0];
+        assert_eq!(position.entry.size, gbp(dec!(5)));
+        assert_eq!(position.open_risk(), gbp(dec!(50)));
+    }
+
+    #[test]
+    fn limits_the_total_risk() {
+        let limits = RiskLimits {
+            total: dec!(0.015),
+            ..RiskLimits::none()
+        };
+        let mut portfolio = portfolio(&["A", "B", "C"], limits);
+
+        portfolio.run(&[
+            vec![frame(1, dec!(100))],
+            vec![frame(1, dec!(200))],
+            vec![frame(1, dec!(300))],
+        ]);
+
+        assert_eq!(
+            portfolio.markets[0].account.live_trades()[0].entry.size,
+            gbp(dec!(10))
+        );
+        assert_eq!(
+            portfolio.markets[1].account.live_trades()[0].entry.size,
+            gbp(dec!(5))
+        );
+        assert!(portfolio.markets[2].account.live_trades().is_empty());
+        assert!(matches!(
+            &portfolio.markets[2].trace[0],
+            Err(e) if e.starts_with("Risk limit reached in C")
+        ));
+    }
+
+    #[test]
+    fn limits_the_exposure_to_correlated_markets() {
+        let limits = RiskLimits::new(dec!(1), dec!(1), dec!(0.01), dec!(0.8), 3);
+        let a = prices(&[dec!(100), dec!(102), dec!(101), dec!(104)]);
+
+        // Moving with A
+        let mut correlated = portfolio(&["A", "B"], limits);
+        correlated.run(&[
+            a.clone(),
+            prices(&[dec!(200), dec!(204), dec!(202), dec!(208)]),
+        ]);
+
+        assert_eq!(correlated.markets[0].account.live_trades().len(), 1);
+        assert!(correlated.markets[1].account.live_trades().is_empty());
+
+        // Moving against A, buying both hedges the exposure
+        let mut hedged = portfolio(&["A", "B"], limits);
+        hedged.run(&[a, prices(&[dec!(200), dec!(196), dec!(198), dec!(192)])]);
+
+        assert_eq!(hedged.markets[0].account.live_trades().len(), 1);
+        assert_eq!(hedged.markets[1].account.live_trades().len(), 1);
+    }
+
+    #[test]
+    fn liquidates_when_the_margin_runs_out() {
+        let mut portfolio = portfolio(&["A"], RiskLimits::none());
+        // 1000 GBP per point at 100.5, holding 5025 GBP of margin
+        portfolio.markets[0].account.risk_per_trade = dec!(1);
+
+        portfolio.run(&[prices(&[dec!(100), dec!(93)])]);
+
+        // Worth 2000 GBP, holding 4650 GBP, closed at the bid
+        let market = &portfolio.markets[0];
+        assert!(matches!(
+            portfolio.margin_calls[..],
+            [MarginCall::Liquidation(_)]
+        ));
+        assert!(matches!(market.trace.last(), Some(Ok(Order::Liquidate(_)))));
+        assert!(market.account.live_trades().is_empty());
+        assert_eq!(portfolio.balance, gbp(dec!(2000)));
+    }
+
+    #[test]
+    fn liquidates_on_the_margin_of_the_whole_portfolio() {
+        let mut portfolio = portfolio(&["A", "B"], RiskLimits::none());
+        // 500 GBP per point at 100.5 in each market
+        portfolio.markets[0].account.risk_per_trade = dec!(0.5);
+        portfolio.markets[1].account.risk_per_trade = dec!(0.5);
+
+        portfolio.run(&[
+            prices(&[dec!(100), dec!(93.5)]),
+            prices(&[dec!(100), dec!(92.5)]),
+        ]);
+
+        // 3750 and 4250 GBP down, each market alone would be worth more than twice its
+        // margin, together they're worth 2000 GBP holding 4650 GBP
+        assert!(matches!(
+            portfolio.margin_calls[..],
+            [MarginCall::Liquidation(_)]
+        ));
+
+        // The biggest loss goes, which is enough
+        let (a, b) = (&portfolio.markets[0], &portfolio.markets[1]);
+        assert!(matches!(b.trace.last(), Some(Ok(Order::Liquidate(_)))));
+        assert!(b.account.live_trades().is_empty());
+        assert_eq!(a.account.live_trades().len(), 1);
+        assert_eq!(portfolio.balance, gbp(dec!(5750)));
+    }
+
+    #[test]
+    fn correlates_returns_over_common_times() {
+        let a = prices(&[dec!(100), dec!(102), dec!(101), dec!(104)]);
+        let b = prices(&[dec!(200), dec!(196), dec!(198), dec!(192)]);
+
+        assert_eq!(correlation(&a, &a), Some(dec!(1)));
+        assert!(correlation(&a, &b).unwrap() < dec!(-0.9));
+        assert_eq!(correlation(&a, &a[..2]), None); // a single return
+        assert_eq!(correlation(&a, &prices(&[dec!(100); 4])), None); // flat
+        assert_eq!(correlation(&a, &a[2..]), None); // no common returns
+    }
+
+    // Fixtures
+
+    // Bullish once there's enough history
+    struct BullishAfter {
+        frames: usize,
+    }
+
+    impl TradingStrategy for BullishAfter {
+        fn trend(&self, context: &Context) -> Trend {
+            if context.history.len() >= self.frames {
+                Trend::Bullish
+            } else {
+                Trend::Neutral
+            }
+        }
+    }
+
+    // Stop 10 points away from the entry
+    struct FixedStop {}
+
+    impl RiskStrategy for FixedStop {
+        fn stop(
+            &self,
+            direction: Direction,
+            context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            let latest = context
+                .history
+                .latest()
+                .ok_or(RiskStrategyError::NotEnoughHistory)?;
+
+            Ok(match direction {
+                Direction::Buy => latest.close.ask - dec!(10),
+                Direction::Sell => latest.close.bid + dec!(10),
+            })
+        }
+    }
+
+    fn portfolio(codes: &[&str], limits: RiskLimits) -> Portfolio<BullishAfter, FixedStop> {
+        // Trading once the correlation can be measured
+        let frames = limits.lookback.max(1);
+        let accounts = codes
+            .iter()
+            .map(|code| {
+                Account::new(
+                    market(code),
+                    BullishAfter { frames },
+                    FixedStop {},
+                    dec!(0.01),
+                    gbp(dec!(0)),
+                    Resolution::Day,
+                )
+            })
+            .collect();
+
+        Portfolio::new(accounts, gbp(dec!(10000)), limits)
+    }
+
+    fn market(code: &str) -> Market {
+        Market {
+            code: code.to_string(),
+            margin_factor: dec!(0.05),
+            min_deal_size: gbp(dec!(0.5)),
+            min_stop_distance: dec!(5),
+            guaranteed_stop: None,
+            funding: None,
+        }
+    }
+
+    fn prices(mids: &[Decimal]) -> Vec<Frame> {
+        mids.iter()
+            .enumerate()
+            .map(|(i, mid)| frame(i as i64 + 1, *mid))
+            .collect()
+    }
+
+    fn frame(n: i64, mid: Decimal) -> Frame {
+        let price = Price::new_mid(mid, dec!(1));
+
+        Frame {
+            close: price,
+            high: price,
+            low: price,
+            open: price,
+            close_time: day(n),
+        }
+    }
+
+    // Falling through the stop of a position bought 10 points higher
+    fn stopping_frame(n: i64, mid: Decimal) -> Frame {
+        Frame {
+            low: Price::new_mid(mid - dec!(20), dec!(1)),
+            ..frame(n, mid)
+        }
+    }
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn day(n: i64) -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0) + Duration::days(n)
+    }
+}
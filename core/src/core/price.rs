@@ -1,17 +1,21 @@
 use std::{
-    collections::VecDeque,
+    cmp::{max, min},
+    error::Error,
     fmt::Display,
-    ops::{Add, AddAssign, Div, Mul, Sub},
+    ops::{Add, Bound, Div, Index, Mul, RangeBounds, Sub},
 };
 
 use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
 use iso_currency::Currency;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 const CURRENCY_DECIMAL_PLACES: u32 = 6;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CurrencyAmount {
     pub amount: Decimal,
     pub currency: Currency,
@@ -21,24 +25,41 @@ impl CurrencyAmount {
     pub fn new(amount: Decimal, currency: Currency) -> Self {
         Self { amount, currency }
     }
+
+    // Amount in another currency, at a rate in units of that currency per unit of this one
+    pub fn convert(self, currency: Currency, rate: Decimal) -> Self {
+        Self::new(
+            (self.amount * rate).round_dp(CURRENCY_DECIMAL_PLACES),
+            currency,
+        )
+    }
 }
 
-impl Add for CurrencyAmount {
-    type Output = Option<CurrencyAmount>;
+#[derive(Debug, PartialEq)]
+pub enum CurrencyError {
+    Mismatch(Currency, Currency), // amounts in different currencies, convert one first
+}
 
-    fn add(self, rhs: Self) -> Self::Output {
-        if self.currency == rhs.currency {
-            Some(CurrencyAmount::new(self.amount + rhs.amount, self.currency))
-        } else {
-            None
+impl Error for CurrencyError {}
+
+impl Display for CurrencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CurrencyError::Mismatch(a, b) => {
+                write!(f, "Cannot add {} to {}", b.code(), a.code())
+            }
         }
     }
 }
 
-impl AddAssign for CurrencyAmount {
-    fn add_assign(&mut self, rhs: Self) {
+impl Add for CurrencyAmount {
+    type Output = Result<CurrencyAmount, CurrencyError>;
+
+    fn add(self, rhs: Self) -> Self::Output {
         if self.currency == rhs.currency {
-            self.amount += rhs.amount;
+            Ok(CurrencyAmount::new(self.amount + rhs.amount, self.currency))
+        } else {
+            Err(CurrencyError::Mismatch(self.currency, rhs.currency))
         }
     }
 }
@@ -99,6 +120,7 @@ pub type Points = Decimal;
 
 // Price of an instrument. Excuse my finance n00b comments
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Price {
     pub ask: Points, // price we buy at (market asks for this price level)
     pub bid: Points, // price we sell at (market bids to buy at this price level)
@@ -130,6 +152,7 @@ impl Sub for Price {
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Frame {
     pub close: Price,
     pub high: Price,
@@ -138,7 +161,8 @@ pub struct Frame {
     pub close_time: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Resolution {
     Second,
     Minute(usize),
@@ -172,10 +196,317 @@ where
     }
 }
 
-// FIXME store history in an an intuitive order, the VecDequeue just causes issues
+impl Resolution {
+    // Start of the period of this resolution containing the given time.
+    // Periods are aligned to midnight, weeks start on Monday.
+    pub fn period_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = time.date();
+
+        match *self {
+            Resolution::Second => time.with_nanosecond(0).unwrap_or(time),
+            Resolution::Minute(n) => {
+                let minutes = time.hour() * 60 + time.minute();
+                let start = minutes - minutes % (n.max(1) as u32);
+
+                date.and_hms(start / 60, start % 60, 0)
+            }
+            Resolution::Hour(n) => {
+                date.and_hms(time.hour() - time.hour() % (n.max(1) as u32), 0, 0)
+            }
+            Resolution::Day => date.and_hms(0, 0, 0),
+            Resolution::Week => (date
+                - Duration::days(date.weekday().num_days_from_monday() as i64))
+            .and_hms(0, 0, 0),
+            Resolution::Month => Utc.ymd(date.year(), date.month(), 1).and_hms(0, 0, 0),
+        }
+    }
+
+    // Approximate length of the period, only useful for comparing resolutions
+    pub fn nominal_duration(&self) -> Duration {
+        match *self {
+            Resolution::Second => Duration::seconds(1),
+            Resolution::Minute(n) => Duration::minutes(n as i64),
+            Resolution::Hour(n) => Duration::hours(n as i64),
+            Resolution::Day => Duration::days(1),
+            Resolution::Week => Duration::weeks(1),
+            Resolution::Month => Duration::days(30),
+        }
+    }
+}
+
+// Price history of an instrument at a given resolution, in chronological order
+// (the first frame is the oldest, the last frame is the most recent).
+//
+// A bounded history only keeps the most recent `capacity` frames, older frames
+// are dropped as new ones are pushed.
+#[derive(Debug, Clone)]
 pub struct PriceHistory {
     pub resolution: Resolution,
-    pub history: VecDeque<Frame>, // in reverse order - first frame is the most recent
+    capacity: Option<usize>,
+    frames: Vec<Frame>,
+}
+
+impl PriceHistory {
+    pub fn new(resolution: Resolution) -> Self {
+        Self {
+            resolution,
+            capacity: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn bounded(resolution: Resolution, capacity: usize) -> Self {
+        Self {
+            resolution,
+            capacity: Some(capacity.max(1)),
+            frames: Vec::with_capacity(capacity.max(1) * 2),
+        }
+    }
+
+    // Frames need to be in chronological order
+    pub fn from_frames(resolution: Resolution, frames: Vec<Frame>) -> Self {
+        Self {
+            resolution,
+            capacity: None,
+            frames,
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        debug_assert!(
+            !matches!(self.latest(), Some(l) if l.close_time > frame.close_time),
+            "Frames must be pushed in chronological order"
+        );
+
+        if let Some(capacity) = self.capacity {
+            // Let the buffer grow to twice the capacity before dropping old frames,
+            // so that the cost of shifting the frames is amortised over many pushes
+            if self.frames.len() >= capacity * 2 {
+                self.frames.drain(..self.frames.len() - capacity + 1);
+            }
+        }
+
+        self.frames.push(frame);
+    }
+
+    // All the frames held, oldest first
+    pub fn frames(&self) -> &[Frame] {
+        match self.capacity {
+            Some(capacity) if self.frames.len() > capacity => {
+                &self.frames[self.frames.len() - capacity..]
+            }
+            _ => &self.frames,
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Frame> {
+        self.frames().iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    // The most recent `length` frames (or fewer, if there isn't enough history), oldest first
+    pub fn lookback(&self, length: usize) -> &[Frame] {
+        let frames = self.frames();
+
+        &frames[frames.len().saturating_sub(length)..]
+    }
+
+    // Frames with close time within the given range, e.g. `history.range(from..to)`
+    pub fn range<R>(&self, range: R) -> &[Frame]
+    where
+        R: RangeBounds<DateTime<Utc>>,
+    {
+        let frames = self.frames();
+
+        let start = match range.start_bound() {
+            Bound::Included(t) => frames.partition_point(|f| f.close_time < *t),
+            Bound::Excluded(t) => frames.partition_point(|f| f.close_time <= *t),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(t) => frames.partition_point(|f| f.close_time <= *t),
+            Bound::Excluded(t) => frames.partition_point(|f| f.close_time < *t),
+            Bound::Unbounded => frames.len(),
+        };
+
+        &frames[start..end.max(start)]
+    }
+
+    // Aggregate the history into a lower resolution, e.g. 5 minute frames into hourly frames.
+    // The last frame may be incomplete if the history ends part way through a period.
+    pub fn resample(&self, resolution: Resolution) -> Result<PriceHistory, ResampleError> {
+        if resolution.nominal_duration() < self.resolution.nominal_duration() {
+            return Err(ResampleError::ResolutionTooFine);
+        }
+
+        let mut resampler = Resampler::new(resolution);
+        let mut frames: Vec<Frame> = self.iter().flat_map(|f| resampler.push(*f)).collect();
+        frames.extend(resampler.flush());
+
+        Ok(PriceHistory::from_frames(resolution, frames))
+    }
+}
+
+impl Index<usize> for PriceHistory {
+    type Output = Frame;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.frames()[index]
+    }
+}
+
+impl<'a> IntoIterator for &'a PriceHistory {
+    type Item = &'a Frame;
+    type IntoIter = std::slice::Iter<'a, Frame>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ResampleError {
+    ResolutionTooFine, // frames can only be aggregated, not split
+}
+
+impl Error for ResampleError {}
+
+impl Display for ResampleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResampleError::ResolutionTooFine => {
+                write!(f, "Cannot resample to a finer resolution")
+            }
+        }
+    }
+}
+
+// Resampler aggregates a stream of frames into frames of a (lower) resolution.
+//
+// A frame belongs to the period its close time falls into, a frame closing exactly
+// at the end of a period is the last frame of that period. Resampled frames close
+// at the end of their period.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    pub resolution: Resolution,
+    current: Option<Frame>,
+}
+
+impl Resampler {
+    pub fn new(resolution: Resolution) -> Self {
+        Self {
+            resolution,
+            current: None,
+        }
+    }
+
+    // Add the next frame. Returns the frames completed by it, oldest first.
+    pub fn push(&mut self, frame: Frame) -> Vec<Frame> {
+        let mut completed = vec![];
+
+        let start = self
+            .resolution
+            .period_start(frame.close_time - Duration::nanoseconds(1));
+        let close_time = start + self.resolution;
+
+        self.current = match self.current.take() {
+            Some(current) if current.close_time == close_time => Some(Frame {
+                open: current.open,
+                close: frame.close,
+                high: Price {
+                    ask: max(current.high.ask, frame.high.ask),
+                    bid: max(current.high.bid, frame.high.bid),
+                },
+                low: Price {
+                    ask: min(current.low.ask, frame.low.ask),
+                    bid: min(current.low.bid, frame.low.bid),
+                },
+                close_time,
+            }),
+            previous => {
+                completed.extend(previous);
+
+                Some(Frame {
+                    close_time,
+                    ..frame
+                })
+            }
+        };
+
+        if frame.close_time >= close_time {
+            completed.extend(self.current.take());
+        }
+
+        completed
+    }
+
+    // The frame of the period in progress, aggregated so far
+    pub fn current(&self) -> Option<&Frame> {
+        self.current.as_ref()
+    }
+
+    // Take the incomplete frame of the period in progress
+    pub fn flush(&mut self) -> Option<Frame> {
+        self.current.take()
+    }
+}
+
+// Price history at a lower resolution, derived from a stream of higher resolution frames
+#[derive(Debug, Clone)]
+pub struct DerivedHistory {
+    pub history: PriceHistory,
+    resampler: Resampler,
+    completed: Vec<Frame>, // by the last frame pushed
+}
+
+impl DerivedHistory {
+    pub fn new(history: PriceHistory) -> Self {
+        Self {
+            resampler: Resampler::new(history.resolution),
+            history,
+            completed: vec![],
+        }
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        self.completed = self.resampler.push(frame);
+
+        for frame in &self.completed {
+            self.history.push(*frame);
+        }
+    }
+
+    // Whether the last frame pushed completed a frame of the derived history
+    pub fn updated(&self) -> bool {
+        !self.completed.is_empty()
+    }
+
+    // Frames of the derived history completed by the last frame pushed, oldest first
+    //
+    // After a gap in the data there can be more than one.
+    pub fn completed(&self) -> &[Frame] {
+        &self.completed
+    }
+
+    // The frame of the period in progress, aggregated so far
+    pub fn current(&self) -> Option<&Frame> {
+        self.resampler.current()
+    }
 }
 
 #[cfg(test)]
@@ -184,6 +515,36 @@ mod test {
     use chrono::Utc;
     use rust_decimal_macros::dec;
 
+    #[test]
+    fn adds_amounts_in_the_same_currency() {
+        let amount = CurrencyAmount::new(dec!(10), Currency::GBP);
+
+        assert_eq!(
+            amount + CurrencyAmount::new(dec!(2.5), Currency::GBP),
+            Ok(CurrencyAmount::new(dec!(12.5), Currency::GBP))
+        );
+    }
+
+    #[test]
+    fn does_not_drop_amounts_in_another_currency() {
+        let amount = CurrencyAmount::new(dec!(10), Currency::GBP);
+
+        assert_eq!(
+            amount + CurrencyAmount::new(dec!(1), Currency::EUR),
+            Err(CurrencyError::Mismatch(Currency::GBP, Currency::EUR))
+        );
+    }
+
+    #[test]
+    fn converts_amounts() {
+        let amount = CurrencyAmount::new(dec!(100), Currency::EUR);
+
+        assert_eq!(
+            amount.convert(Currency::GBP, dec!(0.85)),
+            CurrencyAmount::new(dec!(85), Currency::GBP)
+        );
+    }
+
     #[test]
     fn adds_seconds_to_date() {
         let actual = Utc.ymd(2021, 1, 1).and_hms(10, 0, 0) + Resolution::Second;
@@ -261,6 +622,308 @@ mod test {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn keeps_history_in_chronological_order() {
+        let mut history = PriceHistory::new(Resolution::Minute(10));
+        let frames = frames(5);
+
+        for frame in &frames {
+            history.push(*frame);
+        }
+
+        assert_eq!(history.len(), 5);
+        assert_eq!(history.frames(), &frames[..]);
+        assert_eq!(history[0], frames[0]);
+        assert_eq!(history.latest(), Some(&frames[4]));
+    }
+
+    #[test]
+    fn looks_back_over_recent_history() {
+        let frames = frames(5);
+        let history = PriceHistory::from_frames(Resolution::Minute(10), frames.clone());
+
+        assert_eq!(history.lookback(2), &frames[3..]);
+        assert_eq!(history.lookback(0), &[]);
+        assert_eq!(history.lookback(10), &frames[..]);
+    }
+
+    #[test]
+    fn slices_history_by_time_range() {
+        let frames = frames(5);
+        let history = PriceHistory::from_frames(Resolution::Minute(10), frames.clone());
+
+        let from = Utc.ymd(2021, 1, 1).and_hms(10, 10, 0);
+        let to = Utc.ymd(2021, 1, 1).and_hms(10, 30, 0);
+
+        assert_eq!(history.range(from..to), &frames[1..3]);
+        assert_eq!(history.range(from..=to), &frames[1..4]);
+        assert_eq!(history.range(from..), &frames[1..]);
+        assert_eq!(history.range(..to), &frames[..3]);
+        assert_eq!(history.range(to..from), &[]);
+    }
+
+    #[test]
+    fn bounded_history_keeps_most_recent_frames() {
+        let mut history = PriceHistory::bounded(Resolution::Minute(10), 3);
+        let frames = frames(20);
+
+        for (idx, frame) in frames.iter().enumerate() {
+            history.push(*frame);
+
+            let start = (idx + 1).saturating_sub(3);
+            assert_eq!(history.frames(), &frames[start..=idx]);
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.latest(), Some(&frames[19]));
+    }
+
+    #[test]
+    fn aligns_periods_to_resolution() {
+        let time = Utc.ymd(2021, 1, 7).and_hms(10, 37, 12); // Thursday
+
+        let cases = [
+            (Resolution::Second, Utc.ymd(2021, 1, 7).and_hms(10, 37, 12)),
+            (
+                Resolution::Minute(5),
+                Utc.ymd(2021, 1, 7).and_hms(10, 35, 0),
+            ),
+            (
+                Resolution::Minute(15),
+                Utc.ymd(2021, 1, 7).and_hms(10, 30, 0),
+            ),
+            (Resolution::Hour(1), Utc.ymd(2021, 1, 7).and_hms(10, 0, 0)),
+            (Resolution::Hour(4), Utc.ymd(2021, 1, 7).and_hms(8, 0, 0)),
+            (Resolution::Day, Utc.ymd(2021, 1, 7).and_hms(0, 0, 0)),
+            (Resolution::Week, Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)),
+            (Resolution::Month, Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)),
+        ];
+
+        for (resolution, expected) in cases.iter() {
+            assert_eq!(resolution.period_start(time), *expected, "{:?}", resolution);
+        }
+    }
+
+    #[test]
+    fn resamples_minutes_into_hours() {
+        // 5 minute frames from 10:00 to 12:00
+        let frames: Vec<_> = (1..=24)
+            .map(|i| {
+                ohlc(
+                    Utc.ymd(2021, 1, 1).and_hms(10, 0, 0) + Duration::minutes(5 * i),
+                    Decimal::from(100 + i),
+                    Decimal::from(200 + i),
+                    Decimal::from(50 + i),
+                    Decimal::from(101 + i),
+                    dec!(1),
+                )
+            })
+            .collect();
+        let history = PriceHistory::from_frames(Resolution::Minute(5), frames);
+
+        let expected = [
+            ohlc(
+                Utc.ymd(2021, 1, 1).and_hms(11, 0, 0),
+                dec!(101),
+                dec!(212),
+                dec!(51),
+                dec!(113),
+                dec!(1),
+            ),
+            ohlc(
+                Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+                dec!(113),
+                dec!(224),
+                dec!(63),
+                dec!(125),
+                dec!(1),
+            ),
+        ];
+        let actual = history.resample(Resolution::Hour(1)).unwrap();
+
+        assert_eq!(actual.resolution, Resolution::Hour(1));
+        assert_eq!(actual.frames(), &expected[..]);
+    }
+
+    #[test]
+    fn resamples_days_into_weeks() {
+        // Trading days closing at 20:00, Monday 4th to Friday 15th of January
+        let frames: Vec<_> = (4..=15)
+            .map(|d| Utc.ymd(2021, 1, d).and_hms(20, 0, 0))
+            .filter(|t| t.weekday().num_days_from_monday() < 5)
+            .map(|t| {
+                let p = Decimal::from(t.day());
+                ohlc(t, p, p + dec!(10), p - dec!(10), p + dec!(1), dec!(1))
+            })
+            .collect();
+        let history = PriceHistory::from_frames(Resolution::Day, frames);
+
+        let expected = [
+            ohlc(
+                Utc.ymd(2021, 1, 11).and_hms(0, 0, 0),
+                dec!(4),
+                dec!(18),
+                dec!(-6),
+                dec!(9),
+                dec!(1),
+            ),
+            ohlc(
+                Utc.ymd(2021, 1, 18).and_hms(0, 0, 0),
+                dec!(11),
+                dec!(25),
+                dec!(1),
+                dec!(16),
+                dec!(1),
+            ),
+        ];
+        let actual = history.resample(Resolution::Week).unwrap();
+
+        assert_eq!(actual.frames(), &expected[..]);
+    }
+
+    #[test]
+    fn aggregates_bid_and_ask_separately() {
+        let time = Utc.ymd(2021, 1, 1).and_hms(10, 0, 0);
+        let mut resampler = Resampler::new(Resolution::Hour(1));
+
+        // narrow spread at the extremes, wide spread in between
+        resampler.push(ohlc(
+            time + Duration::minutes(20),
+            dec!(100),
+            dec!(110),
+            dec!(90),
+            dec!(100),
+            dec!(1),
+        ));
+        resampler.push(ohlc(
+            time + Duration::minutes(40),
+            dec!(100),
+            dec!(109),
+            dec!(91),
+            dec!(100),
+            dec!(4),
+        ));
+
+        let actual = resampler.current().unwrap();
+
+        assert_eq!(
+            actual.high,
+            Price {
+                bid: dec!(109.5),
+                ask: dec!(111)
+            }
+        );
+        assert_eq!(
+            actual.low,
+            Price {
+                bid: dec!(89),
+                ask: dec!(90.5)
+            }
+        );
+    }
+
+    #[test]
+    fn completes_a_frame_at_the_end_of_the_period() {
+        let time = Utc.ymd(2021, 1, 1).and_hms(10, 0, 0);
+        let mut resampler = Resampler::new(Resolution::Hour(1));
+
+        let first = ohlc(
+            time + Duration::minutes(30),
+            dec!(100),
+            dec!(110),
+            dec!(90),
+            dec!(105),
+            dec!(1),
+        );
+        let last = ohlc(
+            time + Duration::minutes(60),
+            dec!(105),
+            dec!(120),
+            dec!(95),
+            dec!(115),
+            dec!(1),
+        );
+        let next = ohlc(
+            time + Duration::minutes(90),
+            dec!(115),
+            dec!(116),
+            dec!(114),
+            dec!(115),
+            dec!(1),
+        );
+
+        assert_eq!(resampler.push(first), vec![]);
+        assert_eq!(
+            resampler.push(last),
+            vec![ohlc(
+                time + Duration::hours(1),
+                dec!(100),
+                dec!(120),
+                dec!(90),
+                dec!(115),
+                dec!(1)
+            )]
+        );
+        assert_eq!(resampler.push(next), vec![]);
+        assert_eq!(
+            resampler.flush(),
+            Some(ohlc(
+                time + Duration::hours(2),
+                dec!(115),
+                dec!(116),
+                dec!(114),
+                dec!(115),
+                dec!(1)
+            ))
+        );
+        assert_eq!(resampler.current(), None);
+    }
+
+    #[test]
+    fn completes_an_unfinished_frame_after_a_gap() {
+        let time = Utc.ymd(2021, 1, 1).and_hms(10, 0, 0);
+        let mut resampler = Resampler::new(Resolution::Hour(2));
+
+        let first = ohlc(
+            time + Duration::hours(1),
+            dec!(100),
+            dec!(110),
+            dec!(90),
+            dec!(105),
+            dec!(1),
+        );
+        let after_gap = ohlc(
+            time + Duration::hours(4),
+            dec!(105),
+            dec!(120),
+            dec!(95),
+            dec!(115),
+            dec!(1),
+        );
+
+        assert_eq!(resampler.push(first), vec![]);
+        assert_eq!(
+            resampler.push(after_gap),
+            vec![
+                Frame {
+                    close_time: time + Duration::hours(2),
+                    ..first
+                },
+                after_gap
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_to_resample_to_finer_resolution() {
+        let history = PriceHistory::new(Resolution::Hour(1));
+
+        assert_eq!(
+            history.resample(Resolution::Minute(5)).unwrap_err(),
+            ResampleError::ResolutionTooFine
+        );
+    }
+
     #[test]
     fn makes_price_from_mid_market_and_spread() {
         let expected = Price {
@@ -271,4 +934,41 @@ mod test {
 
         assert_eq!(actual, expected)
     }
+
+    // Fixtures
+
+    fn ohlc(
+        close_time: DateTime<Utc>,
+        open: Decimal,
+        high: Decimal,
+        low: Decimal,
+        close: Decimal,
+        spread: Decimal,
+    ) -> Frame {
+        Frame {
+            open: Price::new_mid(open, spread),
+            high: Price::new_mid(high, spread),
+            low: Price::new_mid(low, spread),
+            close: Price::new_mid(close, spread),
+            close_time,
+        }
+    }
+
+    // Frames with a rising price every 10 minutes, starting at 10:00
+    fn frames(count: usize) -> Vec<Frame> {
+        (0..count)
+            .map(|i| {
+                let price = Price::new_mid(Decimal::from(100 + i), dec!(1));
+
+                Frame {
+                    open: price,
+                    close: price,
+                    high: price,
+                    low: price,
+                    close_time: Utc.ymd(2021, 1, 1).and_hms(10, 0, 0)
+                        + Duration::minutes(10 * i as i64),
+                }
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::core::account::{Account, AccountError};
+use crate::core::broker::OpenPosition;
+use crate::core::price::{CurrencyAmount, Points};
+use crate::core::strategy::{RiskStrategy, TradingStrategy};
+use crate::core::trade::{Amendment, Entry, Exit, Order, Position, StopType};
+
+// Difference between the account and what the broker reports
+#[derive(Debug, PartialEq, Clone)]
+pub enum Discrepancy {
+    // A live trade of the account isn't open at the broker, e.g. the broker stopped it out
+    MissingPosition(Position),
+    // The broker holds a position the account doesn't know about, e.g. a manual trade
+    UnexpectedPosition(OpenPosition),
+    StopMismatch {
+        position_id: String,
+        account: Points,
+        broker: Option<Points>,
+    },
+    SizeMismatch {
+        position_id: String,
+        account: CurrencyAmount,
+        broker: CurrencyAmount,
+    },
+    BalanceDrift {
+        account: CurrencyAmount,
+        broker: CurrencyAmount,
+    },
+}
+
+// What to do about discrepancies
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Policy {
+    Adopt, // bring the account in line with the broker
+    Halt,  // stop trading until it's sorted out by hand
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReconcileError {
+    NoStop(String), // the broker's position has no stop-loss for the account to trade it with
+    Account(AccountError),
+}
+
+impl Error for ReconcileError {}
+
+impl Display for ReconcileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconcileError::NoStop(s) => write!(f, "Position {} has no stop-loss", s),
+            ReconcileError::Account(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<AccountError> for ReconcileError {
+    fn from(error: AccountError) -> Self {
+        ReconcileError::Account(error)
+    }
+}
+
+// Compare the account with the positions and balance reported by the broker
+//
+// Balances closer than the tolerance are considered the same, the account doesn't round
+// its balance the way the broker does.
+pub fn discrepancies<TS, RS>(
+    account: &Account<TS, RS>,
+    positions: &[OpenPosition],
+    balance: CurrencyAmount,
+    tolerance: Decimal,
+) -> Vec<Discrepancy>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+{
+    let mut found = vec![];
+    let live_trades = account.live_trades();
+
+    for lt in live_trades {
+        match positions
+            .iter()
+            .find(|p| p.position_id == lt.entry.position_id)
+        {
+            Some(position) => {
+                if position.stop != Some(lt.stop) {
+                    found.push(Discrepancy::StopMismatch {
+                        position_id: position.position_id.clone(),
+                        account: lt.stop,
+                        broker: position.stop,
+                    });
+                }
+                if position.size != lt.entry.size {
+                    found.push(Discrepancy::SizeMismatch {
+                        position_id: position.position_id.clone(),
+                        account: lt.entry.size,
+                        broker: position.size,
+                    });
+                }
+            }
+            None => found.push(Discrepancy::MissingPosition(lt.clone())),
+        }
+    }
+
+    for position in positions {
+        if account.live_trade(&position.position_id).is_none() {
+            found.push(Discrepancy::UnexpectedPosition(position.clone()));
+        }
+    }
+
+    if (account.balance.amount - balance.amount).abs() > tolerance {
+        found.push(Discrepancy::BalanceDrift {
+            account: account.balance,
+            broker: balance,
+        });
+    }
+
+    found
+}
+
+// Bring the account in line with the broker
+//
+// The broker doesn't say how a missing position was closed, it's taken to have been
+// stopped out at its stop-loss. The balance drift takes care of the difference.
+pub fn adopt<TS, RS>(
+    account: &mut Account<TS, RS>,
+    discrepancy: &Discrepancy,
+    time: DateTime<Utc>,
+) -> Result<(), ReconcileError>
+where
+    TS: TradingStrategy,
+    RS: RiskStrategy,
+{
+    match discrepancy {
+        Discrepancy::MissingPosition(position) => account.log_order(Order::Stop(Exit {
+            position_id: position.entry.position_id.clone(),
+            price: position.stop,
+            time,
+        }))?,
+        Discrepancy::UnexpectedPosition(position) => {
+            let stop = position
+                .stop
+                .ok_or_else(|| ReconcileError::NoStop(position.position_id.clone()))?;
+
+            account.log_order(Order::Open(Entry {
+                position_id: position.position_id.clone(),
+                direction: position.direction,
+                price: position.level,
+                stop,
+                stop_type: StopType::Normal,
+                size: position.size,
+                time: position.time,
+            }))?
+        }
+        Discrepancy::StopMismatch {
+            position_id,
+            broker,
+            ..
+        } => {
+            let stop = broker.ok_or_else(|| ReconcileError::NoStop(position_id.clone()))?;
+
+            account.log_order(Order::Amend(Amendment {
+                position_id: position_id.clone(),
+                stop,
+                time,
+            }))?
+        }
+        Discrepancy::SizeMismatch {
+            position_id,
+            broker,
+            ..
+        } => account.resize_live_trade(position_id, *broker)?,
+        Discrepancy::BalanceDrift { broker, .. } => account.balance = *broker,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use crate::core::market::Market;
+    use crate::core::price::Resolution;
+    use crate::core::strategy::{Context, RiskStrategyError, Trend};
+    use crate::core::trade::Direction;
+
+    #[test]
+    fn finds_no_discrepancies_when_in_line() {
+        let account = account_with(entry("1"));
+
+        let positions = vec![open_position("1", Some(dec!(90)))];
+
+        assert_eq!(
+            discrepancies(&account, &positions, gbp(dec!(1000.004)), dec!(0.01)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn finds_a_position_closed_by_the_broker() {
+        let account = account_with(entry("1"));
+
+        let actual = discrepancies(&account, &[], gbp(dec!(980)), dec!(0.01));
+
+        assert_eq!(
+            actual,
+            vec![
+                Discrepancy::MissingPosition(Position::new(entry("1"))),
+                Discrepancy::BalanceDrift {
+                    account: gbp(dec!(1000)),
+                    broker: gbp(dec!(980))
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_unexpected_positions() {
+        let account = account_with(entry("1"));
+
+        let positions = vec![open_position("1", Some(dec!(90))), open_position("2", None)];
+
+        assert_eq!(
+            discrepancies(&account, &positions, gbp(dec!(1000)), dec!(0.01)),
+            vec![Discrepancy::UnexpectedPosition(open_position("2", None))]
+        );
+    }
+
+    #[test]
+    fn finds_stop_and_size_mismatches() {
+        let account = account_with(entry("1"));
+
+        let positions = vec![OpenPosition {
+            size: gbp(dec!(3)),
+            ..open_position("1", Some(dec!(95)))
+        }];
+
+        assert_eq!(
+            discrepancies(&account, &positions, gbp(dec!(1000)), dec!(0.01)),
+            vec![
+                Discrepancy::StopMismatch {
+                    position_id: "1".to_string(),
+                    account: dec!(90),
+                    broker: Some(dec!(95)),
+                },
+                Discrepancy::SizeMismatch {
+                    position_id: "1".to_string(),
+                    account: gbp(dec!(2)),
+                    broker: gbp(dec!(3)),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn adopts_the_broker_state() {
+        let mut account = account_with(entry("1"));
+        let positions = vec![OpenPosition {
+            size: gbp(dec!(3)),
+            ..open_position("2", Some(dec!(95)))
+        }];
+
+        for discrepancy in discrepancies(&account, &positions, gbp(dec!(980)), dec!(0.01)) {
+            adopt(&mut account, &discrepancy, date()).unwrap();
+        }
+
+        assert_eq!(account.balance, gbp(dec!(980)));
+        assert_eq!(account.closed_trades()[0].exit_price, Some(dec!(90)));
+        assert_eq!(
+            account.live_trade("2").map(|lt| lt.entry.clone()),
+            Some(Entry {
+                position_id: "2".to_string(),
+                stop: dec!(95),
+                size: gbp(dec!(3)),
+                ..entry("2")
+            })
+        );
+        assert_eq!(
+            discrepancies(&account, &positions, gbp(dec!(980)), dec!(0.01)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn adopts_stop_and_size_changes() {
+        let mut account = account_with(entry("1"));
+        let positions = vec![OpenPosition {
+            size: gbp(dec!(3)),
+            ..open_position("1", Some(dec!(95)))
+        }];
+
+        for discrepancy in discrepancies(&account, &positions, gbp(dec!(1000)), dec!(0.01)) {
+            adopt(&mut account, &discrepancy, date()).unwrap();
+        }
+
+        let live_trade = account.live_trade("1").unwrap();
+        assert_eq!(live_trade.stop, dec!(95));
+        assert_eq!(live_trade.entry.size, gbp(dec!(3)));
+    }
+
+    #[test]
+    fn cannot_adopt_a_position_without_a_stop() {
+        let mut account = account_with(entry("1"));
+
+        assert_eq!(
+            adopt(
+                &mut account,
+                &Discrepancy::StopMismatch {
+                    position_id: "1".to_string(),
+                    account: dec!(90),
+                    broker: None
+                },
+                date()
+            ),
+            Err(ReconcileError::NoStop("1".to_string()))
+        );
+        assert_eq!(
+            adopt(
+                &mut account,
+                &Discrepancy::UnexpectedPosition(open_position("2", None)),
+                date()
+            ),
+            Err(ReconcileError::NoStop("2".to_string()))
+        );
+        assert_eq!(
+            adopt(
+                &mut account,
+                &Discrepancy::UnexpectedPosition(open_position("1", Some(dec!(90)))),
+                date()
+            ),
+            Err(ReconcileError::Account(AccountError::DuplicateEntry(
+                "1".to_string()
+            )))
+        );
+    }
+
+    // Fixtures
+
+    struct Neutral {}
+
+    impl TradingStrategy for Neutral {
+        fn trend(&self, _context: &Context) -> Trend {
+            Trend::Neutral
+        }
+    }
+
+    struct NoRisk {}
+
+    impl RiskStrategy for NoRisk {
+        fn stop(
+            &self,
+            _direction: Direction,
+            _context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            Err(RiskStrategyError::NotEnoughHistory)
+        }
+    }
+
+    fn account_with(entry: Entry) -> Account<Neutral, NoRisk> {
+        let mut account = Account::new(
+            Market {
+                code: "UKX".to_string(),
+                margin_factor: dec!(0.05),
+                min_deal_size: gbp(dec!(0.5)),
+                min_stop_distance: dec!(5),
+                guaranteed_stop: None,
+                funding: None,
+            },
+            Neutral {},
+            NoRisk {},
+            dec!(0.01),
+            gbp(dec!(1000)),
+            Resolution::Day,
+        );
+        account.log_order(Order::Open(entry)).unwrap();
+
+        account
+    }
+
+    fn entry(id: &str) -> Entry {
+        Entry {
+            position_id: id.to_string(),
+            direction: Direction::Buy,
+            price: dec!(100),
+            stop: dec!(90),
+            stop_type: StopType::Normal,
+            size: gbp(dec!(2)),
+            time: date(),
+        }
+    }
+
+    fn open_position(id: &str, stop: Option<Points>) -> OpenPosition {
+        OpenPosition {
+            position_id: id.to_string(),
+            direction: Direction::Buy,
+            size: gbp(dec!(2)),
+            level: dec!(100),
+            stop,
+            time: date(),
+        }
+    }
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, Currency::GBP)
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)
+    }
+}
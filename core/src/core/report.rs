@@ -0,0 +1,468 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal_macros::dec;
+
+use crate::core::account::Equity;
+use crate::core::price::CurrencyAmount;
+use crate::core::trade::{Trade, TradeOutcome, TradeStatus};
+
+const SECONDS_PER_YEAR: i64 = 31_557_600; // 365.25 days
+
+// Performance summary of a backtest
+//
+// Trade statistics only consider closed trades, an open trade still counts towards the closing balance.
+// Ratios which can't be calculated (e.g. profit factor without any losing trade) are None.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Report {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub opening_balance: CurrencyAmount,
+    pub closing_balance: CurrencyAmount,
+    // Returns
+    pub total_return: Decimal,
+    pub cagr: Option<Decimal>,
+    // Drawdown
+    pub max_drawdown: CurrencyAmount,
+    pub max_drawdown_pct: Decimal,
+    pub max_drawdown_duration: Duration,
+    // Trades
+    pub trades: usize,
+    pub win_rate: Option<Decimal>,
+    pub average_r: Option<Decimal>,
+    pub expectancy: Option<CurrencyAmount>,
+    pub profit_factor: Option<Decimal>,
+    pub longest_losing_streak: usize,
+    pub exposure: Decimal, // fraction of the time spent in the market
+    // Risk adjusted returns, annualised
+    pub sharpe: Option<Decimal>,
+    pub sortino: Option<Decimal>,
+}
+
+impl Report {
+    pub fn new(opening_balance: CurrencyAmount, trades: &[Trade], equity: &[Equity]) -> Self {
+        let start = equity
+            .first()
+            .map(|e| e.time)
+            .or_else(|| trades.first().map(|t| t.entry_time))
+            .unwrap_or_else(Utc::now);
+        let end = equity.last().map_or(start, |e| e.time);
+        let years = Decimal::from((end - start).num_seconds()) / Decimal::from(SECONDS_PER_YEAR);
+
+        // Trades are in the currency of the balance, as the trade logs give them
+        let profit: Decimal = trades.iter().map(|t| t.profit.amount).sum();
+        let closing_balance =
+            CurrencyAmount::new(opening_balance.amount + profit, opening_balance.currency);
+        let total_return = ((closing_balance.amount - opening_balance.amount)
+            / opening_balance.amount)
+            .round_dp(6);
+        let cagr = if years > dec!(0) && closing_balance.amount > dec!(0) {
+            let growth = closing_balance.amount / opening_balance.amount;
+
+            Some((growth.powd(dec!(1) / years) - dec!(1)).round_dp(6))
+        } else {
+            None
+        };
+
+        let (max_drawdown, max_drawdown_pct, max_drawdown_duration) = drawdown(equity);
+
+        let closed: Vec<&Trade> = trades
+            .iter()
+            .filter(|t| t.status == TradeStatus::Closed)
+            .collect();
+        let count = Decimal::from(closed.len());
+
+        let wins = closed
+            .iter()
+            .filter(|t| t.outcome == TradeOutcome::Profit)
+            .count();
+        let win_rate = if closed.is_empty() {
+            None
+        } else {
+            Some((Decimal::from(wins) / count).round_dp(4))
+        };
+        let average_r = if closed.is_empty() {
+            None
+        } else {
+            let total: Decimal = closed.iter().map(|t| t.risk_reward).sum();
+
+            Some((total / count).round_dp(4))
+        };
+        let expectancy = if closed.is_empty() {
+            None
+        } else {
+            let total: Decimal = closed.iter().map(|t| t.profit.amount).sum();
+
+            Some(CurrencyAmount::new(total, opening_balance.currency) / count)
+        };
+
+        let gross_profit: Decimal = closed
+            .iter()
+            .map(|t| t.profit.amount)
+            .filter(|p| *p > dec!(0))
+            .sum();
+        let gross_loss: Decimal = closed
+            .iter()
+            .map(|t| t.profit.amount)
+            .filter(|p| *p < dec!(0))
+            .sum();
+        let profit_factor = if gross_loss < dec!(0) {
+            Some((gross_profit / -gross_loss).round_dp(4))
+        } else {
+            None
+        };
+
+        let longest_losing_streak = closed
+            .iter()
+            .scan(0, |streak, t| {
+                *streak = match t.outcome {
+                    TradeOutcome::Loss => *streak + 1,
+                    TradeOutcome::Profit => 0,
+                };
+
+                Some(*streak)
+            })
+            .max()
+            .unwrap_or(0);
+
+        // Positions held at the same time only count once
+        let mut periods: Vec<(DateTime<Utc>, DateTime<Utc>)> = trades
+            .iter()
+            .map(|t| (t.entry_time.max(start), t.exit_time.unwrap_or(end).min(end)))
+            .filter(|(entry, exit)| exit > entry)
+            .collect();
+        periods.sort();
+
+        let mut in_market = Duration::zero();
+        let mut covered = start;
+        for (entry, exit) in periods {
+            let from = entry.max(covered);
+            if exit > from {
+                in_market = in_market + (exit - from);
+                covered = exit;
+            }
+        }
+        let exposure = if end > start {
+            (Decimal::from(in_market.num_seconds()) / Decimal::from((end - start).num_seconds()))
+                .round_dp(4)
+        } else {
+            dec!(0)
+        };
+
+        let (sharpe, sortino) = risk_adjusted_returns(equity, years);
+
+        Self {
+            start,
+            end,
+            opening_balance,
+            closing_balance,
+            total_return,
+            cagr,
+            max_drawdown: CurrencyAmount::new(max_drawdown, opening_balance.currency),
+            max_drawdown_pct,
+            max_drawdown_duration,
+            trades: closed.len(),
+            win_rate,
+            average_r,
+            expectancy,
+            profit_factor,
+            longest_losing_streak,
+            exposure,
+            sharpe,
+            sortino,
+        }
+    }
+}
+
+impl Report {
+    // Length of the reported period in years
+    pub fn years(&self) -> Decimal {
+        Decimal::from((self.end - self.start).num_seconds()) / Decimal::from(SECONDS_PER_YEAR)
+    }
+
+    // Total return scaled to a year, without compounding
+    pub fn annualised_return(&self) -> Option<Decimal> {
+        let years = self.years();
+
+        if years > dec!(0) {
+            Some((self.total_return / years).round_dp(6))
+        } else {
+            None
+        }
+    }
+}
+
+// Largest fall from a peak (amount and fraction of the peak) and the longest time spent below a peak
+fn drawdown(equity: &[Equity]) -> (Decimal, Decimal, Duration) {
+    let mut max_amount = dec!(0);
+    let mut max_pct = dec!(0);
+    let mut max_duration = Duration::zero();
+
+    let (mut peak_time, mut peak) = match equity.first() {
+        Some(first) => (first.time, first.value().amount),
+        None => return (max_amount, max_pct, max_duration),
+    };
+
+    let mut under_water = false;
+
+    for point in equity {
+        let value = point.value().amount;
+
+        if value >= peak {
+            if under_water {
+                max_duration = max_duration.max(point.time - peak_time);
+            }
+            peak = value;
+            peak_time = point.time;
+            under_water = false;
+
+            continue;
+        }
+
+        under_water = true;
+        let amount = peak - value;
+        max_amount = max_amount.max(amount);
+        if peak > dec!(0) {
+            max_pct = max_pct.max((amount / peak).round_dp(6));
+        }
+    }
+
+    // Still under water at the end
+    if let (true, Some(last)) = (under_water, equity.last()) {
+        max_duration = max_duration.max(last.time - peak_time);
+    }
+
+    (max_amount, max_pct, max_duration)
+}
+
+// Sharpe and Sortino ratios of the returns between equity points, with zero risk-free rate
+fn risk_adjusted_returns(equity: &[Equity], years: Decimal) -> (Option<Decimal>, Option<Decimal>) {
+    let returns: Vec<Decimal> = equity
+        .windows(2)
+        .map(|w| (w[0].value().amount, w[1].value().amount))
+        .filter(|(previous, _)| *previous != dec!(0))
+        .map(|(previous, current)| current / previous - dec!(1))
+        .collect();
+
+    if returns.len() < 2 || years <= dec!(0) {
+        return (None, None);
+    }
+
+    let n = Decimal::from(returns.len());
+    let periods_per_year = n / years;
+    let annualise = match periods_per_year.sqrt() {
+        Some(factor) => factor,
+        None => return (None, None),
+    };
+
+    let mean = returns.iter().sum::<Decimal>() / n;
+    let variance = returns
+        .iter()
+        .map(|r| (r - mean) * (r - mean))
+        .sum::<Decimal>()
+        / (n - dec!(1));
+    let downside = returns
+        .iter()
+        .map(|r| r.min(&dec!(0)) * r.min(&dec!(0)))
+        .sum::<Decimal>()
+        / n;
+
+    let ratio = |deviation: Option<Decimal>| match deviation {
+        Some(d) if d > dec!(0) => Some((mean / d * annualise).round_dp(4)),
+        _ => None,
+    };
+
+    (ratio(variance.sqrt()), ratio(downside.sqrt()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+    use iso_currency::Currency::GBP;
+
+    use crate::core::trade::Direction;
+
+    #[test]
+    fn reports_returns() {
+        let equity = vec![point(0, dec!(1000)), point(365, dec!(1210))];
+        let trades = vec![trade(0, 100, dec!(110)), trade(100, 365, dec!(100))];
+
+        let report = Report::new(gbp(dec!(1000)), &trades, &equity);
+
+        assert_eq!(report.closing_balance, gbp(dec!(1210)));
+        assert_eq!(report.total_return, dec!(0.21));
+        assert_eq!(report.cagr.map(|c| c.round_dp(2)), Some(dec!(0.21)));
+    }
+
+    #[test]
+    fn annualises_returns() {
+        let equity = vec![point(0, dec!(1000)), point(730, dec!(1000))];
+        let trades = vec![trade(0, 100, dec!(200))];
+
+        let report = Report::new(gbp(dec!(1000)), &trades, &equity);
+
+        assert_eq!(report.years().round_dp(2), dec!(2.00));
+        assert_eq!(
+            report.annualised_return().map(|r| r.round_dp(2)),
+            Some(dec!(0.10))
+        );
+    }
+
+    #[test]
+    fn reports_drawdown() {
+        let equity = vec![
+            point(0, dec!(1000)),
+            point(1, dec!(1200)),
+            point(2, dec!(900)),
+            point(3, dec!(1100)),
+            point(10, dec!(1300)),
+            point(11, dec!(1250)),
+        ];
+
+        let report = Report::new(gbp(dec!(1000)), &[], &equity);
+
+        assert_eq!(report.max_drawdown, gbp(dec!(300)));
+        assert_eq!(report.max_drawdown_pct, dec!(0.25));
+        assert_eq!(report.max_drawdown_duration, Duration::days(9));
+    }
+
+    #[test]
+    fn includes_unrealised_losses_in_drawdown() {
+        let open_loss = Equity {
+            open_pnl: gbp(dec!(-150)),
+            ..point(1, dec!(1000))
+        };
+        let equity = vec![point(0, dec!(1000)), open_loss, point(2, dec!(1000))];
+
+        let report = Report::new(gbp(dec!(1000)), &[], &equity);
+
+        assert_eq!(report.max_drawdown, gbp(dec!(150)));
+        assert_eq!(report.max_drawdown_pct, dec!(0.15));
+        assert_eq!(report.max_drawdown_duration, Duration::days(2));
+    }
+
+    #[test]
+    fn reports_trade_statistics() {
+        let trades = vec![
+            trade(0, 1, dec!(20)),
+            trade(1, 2, dec!(-10)),
+            trade(2, 3, dec!(-10)),
+            trade(3, 4, dec!(30)),
+            trade(4, 5, dec!(-10)),
+        ];
+        let equity = vec![point(0, dec!(1000)), point(10, dec!(1020))];
+
+        let report = Report::new(gbp(dec!(1000)), &trades, &equity);
+
+        assert_eq!(report.trades, 5);
+        assert_eq!(report.win_rate, Some(dec!(0.4)));
+        assert_eq!(report.average_r, Some(dec!(0.4)));
+        assert_eq!(report.expectancy, Some(gbp(dec!(4))));
+        assert_eq!(report.profit_factor, Some(dec!(1.6667)));
+        assert_eq!(report.longest_losing_streak, 2);
+        assert_eq!(report.exposure, dec!(0.5));
+    }
+
+    #[test]
+    fn counts_open_trade_towards_balance_and_exposure() {
+        let mut open = trade(5, 10, dec!(50));
+        open.status = TradeStatus::Open;
+        open.exit_time = None;
+        let equity = vec![point(0, dec!(1000)), point(10, dec!(1000))];
+
+        let report = Report::new(gbp(dec!(1000)), &[open], &equity);
+
+        assert_eq!(report.closing_balance, gbp(dec!(1050)));
+        assert_eq!(report.trades, 0);
+        assert_eq!(report.win_rate, None);
+        assert_eq!(report.profit_factor, None);
+        assert_eq!(report.exposure, dec!(0.5));
+    }
+
+    #[test]
+    fn counts_overlapping_trades_towards_exposure_once() {
+        let trades = vec![
+            trade(0, 4, dec!(10)),
+            trade(2, 6, dec!(10)),
+            trade(3, 5, dec!(10)),
+        ];
+        let equity = vec![point(0, dec!(1000)), point(10, dec!(1030))];
+
+        let report = Report::new(gbp(dec!(1000)), &trades, &equity);
+
+        assert_eq!(report.exposure, dec!(0.6));
+    }
+
+    #[test]
+    fn reports_risk_adjusted_returns() {
+        let steady = vec![
+            point(0, dec!(1000)),
+            point(1, dec!(1010)),
+            point(2, dec!(1020.1)),
+            point(3, dec!(1030.301)),
+        ];
+        let volatile = vec![
+            point(0, dec!(1000)),
+            point(1, dec!(1100)),
+            point(2, dec!(990)),
+            point(3, dec!(1089)),
+        ];
+
+        let steady_report = Report::new(gbp(dec!(1000)), &[], &steady);
+        let volatile_report = Report::new(gbp(dec!(1000)), &[], &volatile);
+
+        // Constant returns have no deviation
+        assert_eq!(steady_report.sharpe, None);
+        assert_eq!(steady_report.sortino, None);
+
+        assert!(volatile_report.sharpe.unwrap() > dec!(0));
+        assert!(volatile_report.sortino.unwrap() > volatile_report.sharpe.unwrap());
+    }
+
+    // Fixtures
+
+    fn gbp(amount: Decimal) -> CurrencyAmount {
+        CurrencyAmount::new(amount, GBP)
+    }
+
+    fn day(n: i64) -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 1).and_hms(0, 0, 0) + Duration::days(n)
+    }
+
+    fn point(n: i64, balance: Decimal) -> Equity {
+        Equity {
+            time: day(n),
+            balance: gbp(balance),
+            open_pnl: gbp(dec!(0)),
+            margin_used: gbp(dec!(0)),
+            free_margin: gbp(balance),
+        }
+    }
+
+    // Closed long trade risking £10
+    fn trade(entry: i64, exit: i64, profit: Decimal) -> Trade {
+        Trade {
+            id: entry.to_string(),
+            status: TradeStatus::Closed,
+            direction: Direction::Buy,
+            entry_time: day(entry),
+            entry_price: dec!(100),
+            exit_time: Some(day(exit)),
+            exit_price: Some(dec!(100) + profit),
+            stop: dec!(90),
+            stop_history: vec![],
+            size: gbp(dec!(1)),
+            risk: gbp(dec!(10)),
+            outcome: if profit > dec!(0) {
+                TradeOutcome::Profit
+            } else {
+                TradeOutcome::Loss
+            },
+            price_diff: profit,
+            profit: gbp(profit),
+            funding: gbp(dec!(0)),
+            risk_reward: profit / dec!(10),
+        }
+    }
+}
@@ -2,8 +2,56 @@ use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt::Display;
 
-use super::price::{CurrencyAmount, Points, PriceHistory};
-use super::trade::{Direction, Entry};
+use super::price::{CurrencyAmount, DerivedHistory, Frame, Points, PriceHistory, Resolution};
+use super::trade::{Direction, Entry, Position, StopType};
+
+// Context is the market data strategies make decisions on: the price history at
+// the account's resolution and histories at any other resolutions the strategies asked for,
+// derived from it.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub history: &'a PriceHistory,
+    derived: &'a [DerivedHistory],
+}
+
+impl<'a> Context<'a> {
+    pub fn new(history: &'a PriceHistory) -> Self {
+        Self {
+            history,
+            derived: &[],
+        }
+    }
+
+    pub fn with_derived(history: &'a PriceHistory, derived: &'a [DerivedHistory]) -> Self {
+        Self { history, derived }
+    }
+
+    // Price history at the given resolution, if it's available
+    pub fn history(&self, resolution: Resolution) -> Option<&'a PriceHistory> {
+        if resolution == self.history.resolution {
+            return Some(self.history);
+        }
+
+        self.derived
+            .iter()
+            .find(|d| d.history.resolution == resolution)
+            .map(|d| &d.history)
+    }
+
+    // Frames at the given resolution completed by the latest price update, oldest first
+    //
+    // A gap in the prices can complete several frames of a lower resolution at once.
+    pub fn completed_frames(&self, resolution: Resolution) -> &'a [Frame] {
+        if resolution == self.history.resolution {
+            return self.history.latest().map_or(&[], std::slice::from_ref);
+        }
+
+        self.derived
+            .iter()
+            .find(|d| d.history.resolution == resolution)
+            .map_or(&[], |d| d.completed())
+    }
+}
 
 // Tading Strategy estimates the trned of the marekt
 
@@ -27,35 +75,82 @@ impl TryFrom<Trend> for Direction {
 }
 
 pub trait TradingStrategy {
-    fn trend(&self, history: &PriceHistory) -> Trend;
+    // Called once for every new frame added to the history, before the trend is requested.
+    // Strategies keeping indicator state update it here.
+    fn update(&mut self, _context: &Context) {}
+
+    fn trend(&self, context: &Context) -> Trend;
+
+    // Number of most recent frames the strategy needs to see at each resolution,
+    // None if it needs the full history
+    fn history_length(&self) -> Option<usize> {
+        None
+    }
+
+    // Number of frames at the account's resolution the strategy needs to see to rebuild its
+    // indicator state from scratch, e.g. when restoring an account, None if it needs all of them
+    fn warm_up_length(&self, _resolution: Resolution) -> Option<usize> {
+        self.history_length()
+    }
+
+    // Resolutions other than the account's resolution the strategy needs histories for
+    fn resolutions(&self) -> Vec<Resolution> {
+        vec![]
+    }
 }
 
 // RiskStrategy decides stop-loss placement and trade size
 
 pub trait RiskStrategy {
-    fn stop(
-        &self,
-        direction: Direction,
-        history: &PriceHistory,
-    ) -> Result<Points, RiskStrategyError>;
+    // Called once for every new frame added to the history, before a stop is requested.
+    // Strategies keeping indicator state update it here.
+    fn update(&mut self, _context: &Context) {}
+
+    fn stop(&self, direction: Direction, context: &Context) -> Result<Points, RiskStrategyError>;
+
+    // New stop-loss level for an open position, called on every frame the position stays open.
+    // Only stops moving in favour of the position are applied, None leaves the stop where it is.
+    fn trailing_stop(&self, _position: &Position, _context: &Context) -> Option<Points> {
+        None
+    }
+
+    // Number of most recent frames the strategy needs to see at each resolution,
+    // None if it needs the full history
+    fn history_length(&self) -> Option<usize> {
+        None
+    }
+
+    // Number of frames at the account's resolution the strategy needs to see to rebuild its
+    // indicator state from scratch, e.g. when restoring an account, None if it needs all of them
+    fn warm_up_length(&self, _resolution: Resolution) -> Option<usize> {
+        self.history_length()
+    }
+
+    // Resolutions other than the account's resolution the strategy needs histories for
+    fn resolutions(&self) -> Vec<Resolution> {
+        vec![]
+    }
 
     fn entry(
         &self,
         direction: Direction,
-        history: &PriceHistory,
+        context: &Context,
         risk: CurrencyAmount,
     ) -> Result<Entry, RiskStrategyError> {
-        let stop = self.stop(direction, history)?;
+        let stop = self.stop(direction, context)?;
 
         // Assuming immediate execution,
         // this may lead to a slight size error in real life due to slippage
-        let latest_close = history.history[0].close;
+        let latest = context
+            .history
+            .latest()
+            .ok_or(RiskStrategyError::NotEnoughHistory)?;
         let price = match direction {
-            Direction::Buy => latest_close.ask,
-            Direction::Sell => latest_close.bid,
+            Direction::Buy => latest.close.ask,
+            Direction::Sell => latest.close.bid,
         };
 
-        let time = history.history[0].close_time;
+        let time = latest.close_time;
 
         // Size of the trade (per point) is our total acceptable risk
         // divided by the distance to stop-loss level
@@ -69,6 +164,7 @@ pub trait RiskStrategy {
             direction,
             price,
             stop,
+            stop_type: StopType::Normal,
             size,
             time,
         })
@@ -98,9 +194,95 @@ mod test {
     use rust_decimal_macros::dec;
 
     use super::*;
-    use crate::core::price::{CurrencyAmount, Frame, Price, PriceHistory, Resolution};
+    use crate::core::price::{
+        CurrencyAmount, DerivedHistory, Frame, Price, PriceHistory, Resolution,
+    };
     use crate::core::trade::Entry;
 
+    // Context
+
+    #[test]
+    fn gives_history_at_available_resolutions() {
+        let history = PriceHistory::new(Resolution::Minute(10));
+        let derived = vec![DerivedHistory::new(PriceHistory::new(Resolution::Hour(1)))];
+        let context = Context::with_derived(&history, &derived);
+
+        assert_eq!(
+            context
+                .history(Resolution::Minute(10))
+                .map(|h| h.resolution),
+            Some(Resolution::Minute(10))
+        );
+        assert_eq!(
+            context.history(Resolution::Hour(1)).map(|h| h.resolution),
+            Some(Resolution::Hour(1))
+        );
+        assert!(context.history(Resolution::Day).is_none());
+    }
+
+    #[test]
+    fn gives_frames_completed_by_latest_update() {
+        let mut history = PriceHistory::new(Resolution::Minute(30));
+        let mut derived = vec![DerivedHistory::new(PriceHistory::new(Resolution::Hour(1)))];
+        let start = Utc.ymd(2021, 1, 1).and_hms(10, 0, 0);
+
+        let mut completed = vec![];
+        for i in 1..=4 {
+            let frame = Frame {
+                open: Price::new_mid(dec!(100), dec!(2)),
+                close: Price::new_mid(dec!(100), dec!(2)),
+                high: Price::new_mid(dec!(100), dec!(2)),
+                low: Price::new_mid(dec!(100), dec!(2)),
+                close_time: start + chrono::Duration::minutes(30 * i),
+            };
+            history.push(frame);
+            derived[0].push(frame);
+
+            let context = Context::with_derived(&history, &derived);
+
+            assert_eq!(context.completed_frames(Resolution::Minute(30)), &[frame]);
+            completed.push(
+                context
+                    .completed_frames(Resolution::Hour(1))
+                    .iter()
+                    .map(|f| f.close_time.hour())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(completed, vec![vec![], vec![11], vec![], vec![12]]);
+    }
+
+    #[test]
+    fn gives_every_frame_completed_after_a_gap() {
+        let mut history = PriceHistory::new(Resolution::Hour(1));
+        let mut derived = vec![DerivedHistory::new(PriceHistory::new(Resolution::Day))];
+        let start = Utc.ymd(2021, 1, 4).and_hms(0, 0, 0);
+
+        // Monday morning, then nothing until the last hour of Wednesday
+        for hours in &[1, 2, 3 * 24] {
+            let frame = Frame {
+                open: Price::new_mid(dec!(100), dec!(2)),
+                close: Price::new_mid(dec!(100), dec!(2)),
+                high: Price::new_mid(dec!(100), dec!(2)),
+                low: Price::new_mid(dec!(100), dec!(2)),
+                close_time: start + chrono::Duration::hours(*hours),
+            };
+            history.push(frame);
+            derived[0].push(frame);
+        }
+
+        let context = Context::with_derived(&history, &derived);
+        let days: Vec<u32> = context
+            .completed_frames(Resolution::Day)
+            .iter()
+            .map(|f| f.close_time.day())
+            .collect();
+
+        // Monday and Wednesday, there were no prices on Tuesday
+        assert_eq!(days, vec![5, 7]);
+    }
+
     // RiskStrategy
 
     #[test]
@@ -109,23 +291,23 @@ mod test {
         let rs_buy = ConstStop { stop: dec!(600.0) };
         let rs_sell = ConstStop { stop: dec!(800.0) };
 
-        let history = PriceHistory {
-            resolution: Resolution::Minute(10),
-            history: vec![Frame {
+        let history = PriceHistory::from_frames(
+            Resolution::Minute(10),
+            vec![Frame {
                 open: Price::new_mid(dec!(100), dec!(2)),
                 close: Price::new_mid(dec!(700), dec!(2)), // only close matters
                 high: Price::new_mid(dec!(200), dec!(2)),
                 low: Price::new_mid(dec!(300), dec!(2)),
                 close_time: Utc.ymd(2021, 1, 1).and_hms(12, 30, 0),
-            }]
-            .into(),
-        };
+            }],
+        );
 
         let expected_buy = Ok(Entry {
             position_id: String::new(),
             direction: Direction::Buy,
             price: dec!(701.0),
             stop: dec!(600.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.1), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(12, 30, 0),
         });
@@ -134,17 +316,32 @@ mod test {
             direction: Direction::Sell,
             price: dec!(699.0),
             stop: dec!(800.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.1), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(12, 30, 0),
         });
 
-        assert_eq!(rs_buy.entry(Direction::Buy, &history, risk), expected_buy);
+        let context = Context::new(&history);
+
+        assert_eq!(rs_buy.entry(Direction::Buy, &context, risk), expected_buy);
         assert_eq!(
-            rs_sell.entry(Direction::Sell, &history, risk),
+            rs_sell.entry(Direction::Sell, &context, risk),
             expected_sell
         );
     }
 
+    #[test]
+    fn rejects_entry_without_history() {
+        let risk = CurrencyAmount::new(dec!(10.1), Currency::GBP);
+        let rs = ConstStop { stop: dec!(600.0) };
+        let history = PriceHistory::new(Resolution::Minute(10));
+
+        assert_eq!(
+            rs.entry(Direction::Buy, &Context::new(&history), risk),
+            Err(RiskStrategyError::NotEnoughHistory)
+        );
+    }
+
     // Fixtures
 
     struct ConstStop {
@@ -155,7 +352,7 @@ mod test {
         fn stop(
             &self,
             _direction: Direction,
-            _history: &PriceHistory,
+            _context: &Context,
         ) -> Result<Points, RiskStrategyError> {
             Ok(self.stop)
         }
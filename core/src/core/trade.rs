@@ -1,12 +1,16 @@
 use std::fmt::Display;
 
 use chrono::{DateTime, Utc};
+use iso_currency::Currency;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use super::price::{CurrencyAmount, Points, Price};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Direction {
     Buy,
     Sell,
@@ -21,12 +25,27 @@ impl Display for Direction {
     }
 }
 
+// How the stop-loss of a position is filled
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StopType {
+    #[default]
+    Normal, // at the market once the stop is hit, it can slip
+    // Exactly at the stop, for a premium in points per unit of size charged when it's hit
+    Guaranteed {
+        premium: Points,
+    },
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Entry {
     pub position_id: String,
     pub direction: Direction,
     pub price: Points,
     pub stop: Points,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stop_type: StopType,
     pub size: CurrencyAmount,
     pub time: DateTime<Utc>,
 }
@@ -43,23 +62,99 @@ impl Entry {
             time,
         }
     }
+
+    // Move the stop-loss of the position opened by this entry
+    pub fn amend(&self, stop: Points, time: DateTime<Utc>) -> Amendment {
+        Amendment {
+            position_id: self.position_id.clone(),
+            stop,
+            time,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Exit {
     pub position_id: String,
     pub price: Points,
     pub time: DateTime<Utc>,
 }
 
+// Change of a stop-loss level of an open position
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Amendment {
+    pub position_id: String,
+    pub stop: Points,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Order {
     Open(Entry),
     Close(Exit),
     Stop(Exit),
+    Amend(Amendment),
+    Liquidate(Exit), // closed by the broker when the margin ran out
+}
+
+// An open position - the entry and any changes of its stop-loss since
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Position {
+    pub entry: Entry,
+    pub stop: Points, // current stop-loss level
+    pub stop_history: Vec<Amendment>,
+    pub funding: CurrencyAmount, // paid overnight so far, in the currency of the market
+}
+
+impl Position {
+    pub fn new(entry: Entry) -> Self {
+        Self {
+            stop: entry.stop,
+            funding: CurrencyAmount::new(dec!(0), entry.size.currency),
+            entry,
+            stop_history: vec![],
+        }
+    }
+
+    pub fn amend(&mut self, amendment: Amendment) {
+        self.stop = amendment.stop;
+        self.stop_history.push(amendment);
+    }
+
+    // Exit at the current stop-loss level
+    pub fn stopped(&self, time: DateTime<Utc>) -> Exit {
+        Exit {
+            position_id: self.entry.position_id.clone(),
+            price: self.stop,
+            time,
+        }
+    }
+
+    // What the position still stands to lose, nothing once the stop is past the entry
+    pub fn open_risk(&self) -> CurrencyAmount {
+        let distance = match self.entry.direction {
+            Direction::Buy => self.entry.price - self.stop,
+            Direction::Sell => self.stop - self.entry.price,
+        };
+
+        self.entry.size * distance.max(Decimal::ZERO)
+    }
+
+    // Whether the stop would be moved in favour of the position (reducing the risk)
+    pub fn is_tighter(&self, stop: Points) -> bool {
+        match self.entry.direction {
+            Direction::Buy => stop > self.stop,
+            Direction::Sell => stop < self.stop,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TradeStatus {
     Open,
     Closed,
@@ -75,6 +170,7 @@ impl Display for TradeStatus {
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TradeOutcome {
     Profit,
     Loss,
@@ -91,6 +187,7 @@ impl Display for TradeOutcome {
 
 // A row in a trade log
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Trade {
     pub id: String,
     pub status: TradeStatus,
@@ -102,17 +199,52 @@ pub struct Trade {
     pub exit_time: Option<DateTime<Utc>>,
     pub exit_price: Option<Points>,
     // Risk
-    pub stop: Points,
+    pub stop: Points, // initial stop-loss, the risk is based on
+    pub stop_history: Vec<Amendment>,
     pub size: CurrencyAmount,
     pub risk: CurrencyAmount,
     // Outcome
     pub outcome: TradeOutcome,
     pub price_diff: Points,
-    pub profit: CurrencyAmount,
+    pub profit: CurrencyAmount,  // after the funding
+    pub funding: CurrencyAmount, // paid overnight, negative when received
     pub risk_reward: Decimal,
 }
 
 impl Trade {
+    // Stop-loss level at the end of the trade
+    pub fn last_stop(&self) -> Points {
+        self.stop_history.last().map_or(self.stop, |a| a.stop)
+    }
+
+    // Trade with its risk and profit in another currency, the size stays in the market's
+    pub fn in_currency(self, currency: Currency, rate: Decimal) -> Self {
+        Trade {
+            risk: self.risk.convert(currency, rate),
+            profit: self.profit.convert(currency, rate),
+            funding: self.funding.convert(currency, rate),
+            ..self
+        }
+    }
+
+    // Trade of a position which paid funding while it was open
+    pub fn with_funding(self, funding: CurrencyAmount) -> Self {
+        let profit = CurrencyAmount::new(self.profit.amount - funding.amount, funding.currency);
+        let outcome = if profit.amount > dec!(0) {
+            TradeOutcome::Profit
+        } else {
+            TradeOutcome::Loss
+        };
+
+        Trade {
+            outcome,
+            profit,
+            funding,
+            risk_reward: (profit / self.risk).unwrap(), // both in the currency of the market
+            ..self
+        }
+    }
+
     pub fn open(entry: &Entry, latest_price: Price) -> Self {
         let price_diff = match entry.direction {
             Direction::Buy => latest_price.bid - entry.price,
@@ -138,20 +270,40 @@ impl Trade {
             exit_time: None,
             exit_price: None,
             stop: entry.stop,
+            stop_history: vec![],
             size: entry.size,
             risk,
             outcome,
             price_diff,
             profit,
+            funding: CurrencyAmount::new(dec!(0), profit.currency),
             risk_reward: (profit / risk).unwrap(), // both numbers are derived from o.size
         }
     }
 
+    // Trade closed by an exit at the market
     pub fn closed(entry: &Entry, exit: &Exit) -> Self {
+        Self::exited(entry, exit, dec!(0))
+    }
+
+    // Trade stopped out, less the premium of a guaranteed stop
+    //
+    // The premium is only charged when a guaranteed stop is hit, not when the position is
+    // closed any other way.
+    pub fn stopped(entry: &Entry, exit: &Exit) -> Self {
+        let premium = match entry.stop_type {
+            StopType::Normal => dec!(0),
+            StopType::Guaranteed { premium } => premium,
+        };
+
+        Self::exited(entry, exit, premium)
+    }
+
+    fn exited(entry: &Entry, exit: &Exit, premium: Points) -> Self {
         let price_diff = exit.price - entry.price;
         let profit = match entry.direction {
-            Direction::Buy => entry.size * (exit.price - entry.price),
-            Direction::Sell => entry.size * (entry.price - exit.price),
+            Direction::Buy => entry.size * (exit.price - entry.price - premium),
+            Direction::Sell => entry.size * (entry.price - exit.price - premium),
         };
         let outcome = if profit.amount > dec!(0) {
             TradeOutcome::Profit
@@ -169,11 +321,13 @@ impl Trade {
             exit_time: Some(exit.time),
             exit_price: Some(exit.price),
             stop: entry.stop,
+            stop_history: vec![],
             size: entry.size,
             risk,
             outcome,
             price_diff,
             profit,
+            funding: CurrencyAmount::new(dec!(0), profit.currency),
             risk_reward: (profit / risk).unwrap(), // both numbers are derived from o.size
         }
     }
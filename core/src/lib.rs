@@ -1,10 +1,22 @@
+// Indicator names follow the usual trading acronyms (EMA, MACD, ...)
+#![allow(clippy::upper_case_acronyms)]
+
 mod core;
+pub mod optimise;
 pub mod strategies;
 
 pub use crate::core::account;
+pub use crate::core::broker;
+pub use crate::core::fill;
+pub use crate::core::fx;
 pub use crate::core::market;
+pub use crate::core::maths;
 pub use crate::core::price;
+pub use crate::core::reconcile;
+pub use crate::core::report;
 pub use crate::core::strategy;
 pub use crate::core::trade;
 
 pub use crate::core::backtest;
+pub use crate::core::live;
+pub use crate::core::portfolio;
@@ -0,0 +1,224 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use super::{
+    random_coordinates, step, Evaluator, Objective, Optimisation, Optimiser, ParameterSpace,
+    Simulation,
+};
+use crate::core::price::Frame;
+
+// Attempts at finding valid parameters to start from
+const START_ATTEMPTS: usize = 100;
+
+// Simulated annealing - walks the parameter space one step at a time
+//
+// A step to better parameters is always taken, a step to worse ones is taken with a probability
+// which falls as the score gets worse and as the temperature cools down. This lets the search
+// escape local optima early on and settle later. Runs are repeatable for the same seed.
+pub struct Annealing {
+    pub iterations: usize,
+    pub temperature: f64, // initial, in units of the objective score
+    pub cooling: f64,     // temperature multiplier after each iteration
+    pub seed: u64,
+}
+
+impl Annealing {
+    pub fn new(iterations: usize, seed: u64) -> Self {
+        Self {
+            iterations,
+            temperature: 0.1,
+            cooling: 0.9,
+            seed,
+        }
+    }
+
+    // Cooling as a finite multiplier, whatever it was set to
+    fn cooling_factor(&self) -> f64 {
+        if !self.cooling.is_finite() {
+            return 0.0;
+        }
+
+        self.cooling
+    }
+}
+
+impl Optimiser for Annealing {
+    fn optimise(
+        &self,
+        simulation: &Simulation,
+        space: &ParameterSpace,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> Optimisation {
+        let mut evaluator = Evaluator::new(simulation, space, prices, objective);
+        let mut convergence = vec![];
+
+        let dimensions = space.dimensions();
+        if space.size() == 0 {
+            return evaluator.finish(convergence);
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let mut current = random_coordinates(&mut rng, &dimensions);
+        for _ in 0..START_ATTEMPTS {
+            if space.parameters(&current).is_valid() {
+                break;
+            }
+            current = random_coordinates(&mut rng, &dimensions);
+        }
+        let mut current_score = evaluator.score(&current);
+
+        let mut temperature = self.temperature;
+        let cooling = self.cooling_factor();
+
+        for _ in 0..self.iterations {
+            let mut candidate = current;
+            let d = rng.gen_range(0..dimensions.len());
+            step(&mut rng, &mut candidate, &dimensions, d);
+
+            let score = evaluator.score(&candidate);
+            let accept = match (score, current_score) {
+                (Some(new), Some(old)) if new >= old => true,
+                (Some(new), Some(old)) => rng.gen_bool(acceptance(new - old, temperature)),
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+
+            if accept {
+                current = candidate;
+                current_score = score;
+            }
+
+            temperature *= cooling;
+            convergence.push(evaluator.best);
+        }
+
+        evaluator.finish(convergence)
+    }
+}
+
+// Probability of taking a step making the score worse by a difference
+//
+// Without a positive, finite temperature worse steps are never taken.
+fn acceptance(difference: Decimal, temperature: f64) -> f64 {
+    if !(temperature > 0.0 && temperature.is_finite()) {
+        return 0.0;
+    }
+
+    let difference = difference.to_f64().unwrap_or(f64::NEG_INFINITY);
+
+    (difference / temperature).exp().clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::optimise::fixtures::{prices, simulation, wide_space};
+    use crate::optimise::grid_search;
+
+    #[test]
+    fn is_repeatable_with_a_seed() {
+        let prices = prices(120);
+        let optimiser = Annealing::new(20, 42);
+
+        let first = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+        let second = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn records_best_score_of_every_iteration() {
+        let prices = prices(120);
+        let optimiser = Annealing::new(30, 7);
+
+        let actual = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        assert_eq!(actual.convergence.len(), 30);
+        assert!(actual.convergence.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(
+            actual.convergence.last().cloned().flatten(),
+            actual.best().and_then(|e| e.score)
+        );
+    }
+
+    #[test]
+    fn finds_good_parameters() {
+        let prices = prices(120);
+        let optimiser = Annealing::new(70, 1);
+
+        let actual = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+        let expected = grid_search(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+            1,
+        );
+
+        // Better than nearly all of the parameter space, with a fraction of the backtests
+        let best = actual.best().and_then(|e| e.score);
+        let better = expected.iter().filter(|e| e.score > best).count();
+
+        assert!(actual.evaluations.len() < expected.len() / 2);
+        assert!(better < expected.len() / 20, "{} better", better);
+    }
+
+    #[test]
+    fn accepts_worse_steps_less_as_it_cools() {
+        assert_eq!(acceptance(dec!(0), 1.0), 1.0);
+        assert!(acceptance(dec!(-0.1), 1.0) > acceptance(dec!(-0.5), 1.0));
+        assert!(acceptance(dec!(-0.1), 1.0) > acceptance(dec!(-0.1), 0.1));
+        assert_eq!(acceptance(dec!(-0.1), 0.0), 0.0);
+        assert_eq!(acceptance(dec!(-0.1), f64::NAN), 0.0);
+        assert_eq!(acceptance(dec!(-0.1), f64::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn ignores_temperatures_and_cooling_that_are_not_finite() {
+        let prices = prices(120);
+
+        for (temperature, cooling) in &[(f64::NAN, 0.9), (0.1, f64::NAN), (0.1, f64::INFINITY)] {
+            let optimiser = Annealing {
+                temperature: *temperature,
+                cooling: *cooling,
+                ..Annealing::new(30, 42)
+            };
+
+            let actual = optimiser.optimise(
+                &simulation(),
+                &wide_space(),
+                &prices,
+                Objective::TotalReturn,
+            );
+
+            assert_eq!(actual.convergence.len(), 30);
+        }
+    }
+}
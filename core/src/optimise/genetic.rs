@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+
+use super::{
+    random_coordinates, step, Coordinates, Evaluator, Objective, Optimisation, Optimiser,
+    ParameterSpace, Simulation, DIMENSIONS,
+};
+use crate::core::price::Frame;
+
+const TOURNAMENT_SIZE: usize = 3;
+
+// Genetic algorithm - evolves a population of parameter sets over a number of generations
+//
+// Each generation keeps the best few (elite) and breeds the rest from parents picked by
+// tournament selection, mixing their parameters (uniform crossover) and randomly changing some
+// of them (mutation). Runs are repeatable for the same seed.
+pub struct Genetic {
+    pub population: usize,
+    pub generations: usize,
+    pub elite: usize,
+    pub mutation_rate: f64, // probability of each parameter mutating, clamped to 0-1
+    pub seed: u64,
+}
+
+impl Genetic {
+    pub fn new(population: usize, generations: usize, seed: u64) -> Self {
+        Self {
+            population,
+            generations,
+            elite: 2,
+            mutation_rate: 0.1,
+            seed,
+        }
+    }
+
+    // Mutation rate as a valid probability, whatever it was set to
+    fn mutation_probability(&self) -> f64 {
+        if self.mutation_rate.is_nan() {
+            return 0.0;
+        }
+
+        self.mutation_rate.clamp(0.0, 1.0)
+    }
+}
+
+impl Optimiser for Genetic {
+    fn optimise(
+        &self,
+        simulation: &Simulation,
+        space: &ParameterSpace,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> Optimisation {
+        let mut evaluator = Evaluator::new(simulation, space, prices, objective);
+        let mut convergence = vec![];
+
+        let dimensions = space.dimensions();
+        if space.size() == 0 || self.population == 0 {
+            return evaluator.finish(convergence);
+        }
+
+        let mutation_rate = self.mutation_probability();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut population: Vec<Coordinates> = (0..self.population)
+            .map(|_| random_coordinates(&mut rng, &dimensions))
+            .collect();
+
+        for generation in 0..self.generations {
+            let mut scored: Vec<(Coordinates, Option<Decimal>)> = population
+                .iter()
+                .map(|c| (*c, evaluator.score(c)))
+                .collect();
+            scored.sort_by_key(|(_, score)| Reverse(*score));
+
+            convergence.push(evaluator.best);
+            if generation + 1 == self.generations {
+                break;
+            }
+
+            let mut next: Vec<Coordinates> =
+                scored.iter().take(self.elite).map(|(c, _)| *c).collect();
+
+            while next.len() < self.population {
+                let mother = tournament(&mut rng, &scored);
+                let father = tournament(&mut rng, &scored);
+
+                let mut child = mother;
+                for d in 0..DIMENSIONS {
+                    if rng.gen_bool(0.5) {
+                        child[d] = father[d];
+                    }
+
+                    if rng.gen_bool(mutation_rate) {
+                        step(&mut rng, &mut child, &dimensions, d);
+                    }
+                }
+
+                next.push(child);
+            }
+
+            population = next;
+        }
+
+        evaluator.finish(convergence)
+    }
+}
+
+// Best of a few random members of the population, which is sorted best first
+fn tournament<R: Rng>(rng: &mut R, scored: &[(Coordinates, Option<Decimal>)]) -> Coordinates {
+    let winner = (0..TOURNAMENT_SIZE)
+        .map(|_| rng.gen_range(0..scored.len()))
+        .min()
+        .unwrap_or(0);
+
+    scored[winner].0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::optimise::fixtures::{prices, simulation, wide_space};
+    use crate::optimise::grid_search;
+
+    #[test]
+    fn is_repeatable_with_a_seed() {
+        let prices = prices(120);
+        let optimiser = Genetic::new(8, 5, 42);
+
+        let first = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+        let second = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn records_best_score_of_every_generation() {
+        let prices = prices(120);
+        let optimiser = Genetic::new(8, 6, 7);
+
+        let actual = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        assert_eq!(actual.convergence.len(), 6);
+        assert!(actual.convergence.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(
+            actual.convergence.last().cloned().flatten(),
+            actual.best().and_then(|e| e.score)
+        );
+        assert!(actual.evaluations.len() <= 8 * 6);
+    }
+
+    #[test]
+    fn clamps_the_mutation_rate() {
+        let prices = prices(120);
+
+        for rate in &[-0.5, 1.5, f64::NAN] {
+            let optimiser = Genetic {
+                mutation_rate: *rate,
+                ..Genetic::new(4, 3, 42)
+            };
+
+            let actual = optimiser.optimise(
+                &simulation(),
+                &wide_space(),
+                &prices,
+                Objective::TotalReturn,
+            );
+
+            assert_eq!(actual.convergence.len(), 3);
+        }
+    }
+
+    #[test]
+    fn finds_the_best_parameters() {
+        let prices = prices(120);
+        let optimiser = Genetic::new(12, 10, 1);
+
+        let actual = optimiser.optimise(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+        let expected = grid_search(
+            &simulation(),
+            &wide_space(),
+            &prices,
+            Objective::TotalReturn,
+            1,
+        );
+
+        assert!(actual.evaluations.len() < expected.len());
+        assert_eq!(
+            actual.best().and_then(|e| e.score),
+            expected.first().and_then(|e| e.score)
+        );
+    }
+}
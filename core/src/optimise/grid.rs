@@ -0,0 +1,132 @@
+use std::thread;
+
+use super::{rank, Evaluation, Objective, Optimisation, Optimiser, ParameterSpace, Simulation};
+use crate::core::price::Frame;
+
+// Backtest every combination of the parameter space, spread across a number of threads.
+// Gives evaluations ranked by the objective, best first.
+pub fn grid_search(
+    simulation: &Simulation,
+    space: &ParameterSpace,
+    prices: &[Frame],
+    objective: Objective,
+    threads: usize,
+) -> Vec<Evaluation> {
+    let combinations = space.combinations();
+    if combinations.is_empty() {
+        return vec![];
+    }
+
+    let chunk_size = combinations.len().div_ceil(threads.max(1));
+
+    let mut evaluations: Vec<Evaluation> = thread::scope(|scope| {
+        let workers: Vec<_> = combinations
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|parameters| simulation.evaluate(parameters, prices, objective))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Backtest thread panicked"))
+            .collect()
+    });
+
+    rank(&mut evaluations);
+
+    evaluations
+}
+
+// Tries every combination of the parameter values
+pub struct GridSearch {
+    pub threads: usize,
+}
+
+impl Optimiser for GridSearch {
+    fn optimise(
+        &self,
+        simulation: &Simulation,
+        space: &ParameterSpace,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> Optimisation {
+        let evaluations = grid_search(simulation, space, prices, objective, self.threads);
+
+        // There's no order to the search, the best is known once everything has been tried
+        let convergence = vec![evaluations.first().and_then(|e| e.score)];
+
+        Optimisation {
+            evaluations,
+            convergence,
+        }
+    }
+}
+
+// Number of threads to use by default, one per core
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rust_decimal::Decimal;
+
+    use crate::optimise::fixtures::{prices, simulation, space};
+
+    #[test]
+    fn evaluates_every_combination() {
+        let prices = prices(120);
+        let space = space();
+
+        let actual = grid_search(&simulation(), &space, &prices, Objective::TotalReturn, 3);
+
+        assert_eq!(actual.len(), space.combinations().len());
+        for parameters in space.combinations() {
+            assert!(actual.iter().any(|e| e.parameters == parameters));
+        }
+    }
+
+    #[test]
+    fn ranks_by_objective() {
+        let prices = prices(120);
+
+        let actual = grid_search(&simulation(), &space(), &prices, Objective::TotalReturn, 2);
+        assert!(actual.iter().any(|e| e.report.trades > 0));
+
+        let scores: Vec<Option<Decimal>> = actual.iter().map(|e| e.score).collect();
+
+        let mut expected = scores.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+
+        assert_eq!(scores, expected);
+        assert_eq!(actual[0].score, Some(actual[0].report.total_return));
+    }
+
+    #[test]
+    fn gives_same_results_regardless_of_threads() {
+        let prices = prices(120);
+
+        let single = grid_search(&simulation(), &space(), &prices, Objective::Sharpe, 1);
+        let multi = grid_search(&simulation(), &space(), &prices, Objective::Sharpe, 4);
+
+        let scores = |evaluations: &[Evaluation]| {
+            evaluations
+                .iter()
+                .map(|e| (e.parameters, e.score))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(scores(&single).len(), scores(&multi).len());
+        for score in scores(&single) {
+            assert!(scores(&multi).contains(&score));
+        }
+    }
+}
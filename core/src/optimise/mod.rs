@@ -0,0 +1,534 @@
+mod annealing;
+mod genetic;
+mod grid;
+mod monte_carlo;
+mod walk_forward;
+
+pub use annealing::*;
+pub use genetic::*;
+pub use grid::*;
+pub use monte_carlo::*;
+pub use walk_forward::*;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use rand::Rng;
+use rust_decimal::Decimal;
+
+use crate::core::account::Account;
+use crate::core::backtest::Backtest;
+use crate::core::market::Market;
+use crate::core::price::{CurrencyAmount, Frame, Price, Resolution};
+use crate::core::report::Report;
+use crate::strategies::{Donchian, MACD};
+
+// Parameters of the MACD trading strategy with a Donchian channel stop
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Parameters {
+    pub short: usize,
+    pub long: usize,
+    pub signal: usize,
+    pub entry_lim: Decimal,
+    pub exit_lim: Decimal,
+    pub channel_length: usize,
+    pub risk_per_trade: Decimal,
+}
+
+impl Parameters {
+    // Short EMA needs to be shorter than the long one to make sense
+    pub fn is_valid(&self) -> bool {
+        self.short < self.long
+            && self.signal > 0
+            && self.channel_length > 0
+            && self.risk_per_trade > Decimal::ZERO
+    }
+}
+
+// Position in a parameter space - index of the value of each parameter
+pub type Coordinates = [usize; DIMENSIONS];
+
+pub const DIMENSIONS: usize = 7;
+
+// Values to try for each of the parameters
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParameterSpace {
+    pub short: Vec<usize>,
+    pub long: Vec<usize>,
+    pub signal: Vec<usize>,
+    pub entry_lim: Vec<Decimal>,
+    pub exit_lim: Vec<Decimal>,
+    pub channel_length: Vec<usize>,
+    pub risk_per_trade: Vec<Decimal>,
+}
+
+impl ParameterSpace {
+    // Number of values of each of the parameters
+    pub fn dimensions(&self) -> Coordinates {
+        [
+            self.short.len(),
+            self.long.len(),
+            self.signal.len(),
+            self.entry_lim.len(),
+            self.exit_lim.len(),
+            self.channel_length.len(),
+            self.risk_per_trade.len(),
+        ]
+    }
+
+    pub fn size(&self) -> usize {
+        self.dimensions().iter().product()
+    }
+
+    pub fn parameters(&self, coordinates: &Coordinates) -> Parameters {
+        Parameters {
+            short: self.short[coordinates[0]],
+            long: self.long[coordinates[1]],
+            signal: self.signal[coordinates[2]],
+            entry_lim: self.entry_lim[coordinates[3]],
+            exit_lim: self.exit_lim[coordinates[4]],
+            channel_length: self.channel_length[coordinates[5]],
+            risk_per_trade: self.risk_per_trade[coordinates[6]],
+        }
+    }
+
+    // Every valid combination of the parameter values
+    pub fn combinations(&self) -> Vec<Parameters> {
+        let dimensions = self.dimensions();
+        if self.size() == 0 {
+            return vec![];
+        }
+
+        let mut combinations = vec![];
+        let mut coordinates = [0; DIMENSIONS];
+
+        // Count through the coordinates like an odometer, last parameter changing fastest
+        loop {
+            let parameters = self.parameters(&coordinates);
+            if parameters.is_valid() {
+                combinations.push(parameters);
+            }
+
+            let mut d = DIMENSIONS;
+            loop {
+                if d == 0 {
+                    return combinations;
+                }
+                d -= 1;
+
+                coordinates[d] += 1;
+                if coordinates[d] < dimensions[d] {
+                    break;
+                }
+                coordinates[d] = 0;
+            }
+        }
+    }
+}
+
+// Inclusive range of values with a step, e.g. 10, 15, 20 for steps(10, 20, 5)
+pub fn steps(start: usize, end: usize, step: usize) -> Vec<usize> {
+    (start..=end).step_by(step.max(1)).collect()
+}
+
+// Performance metric to optimise for, higher score is better
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Objective {
+    TotalReturn,
+    CAGR,
+    Sharpe,
+    Sortino,
+    ProfitFactor,
+    Expectancy,
+    MaxDrawdown, // smallest drawdown scores highest
+}
+
+impl Objective {
+    pub fn score(&self, report: &Report) -> Option<Decimal> {
+        match self {
+            Objective::TotalReturn => Some(report.total_return),
+            Objective::CAGR => report.cagr,
+            Objective::Sharpe => report.sharpe,
+            Objective::Sortino => report.sortino,
+            Objective::ProfitFactor => report.profit_factor,
+            Objective::Expectancy => report.expectancy.map(|e| e.amount),
+            Objective::MaxDrawdown => Some(-report.max_drawdown_pct),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ObjectiveError(pub String);
+
+impl Error for ObjectiveError {}
+
+impl Display for ObjectiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown objective {}", self.0)
+    }
+}
+
+impl FromStr for Objective {
+    type Err = ObjectiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "return" => Ok(Objective::TotalReturn),
+            "cagr" => Ok(Objective::CAGR),
+            "sharpe" => Ok(Objective::Sharpe),
+            "sortino" => Ok(Objective::Sortino),
+            "profit-factor" => Ok(Objective::ProfitFactor),
+            "expectancy" => Ok(Objective::Expectancy),
+            "drawdown" => Ok(Objective::MaxDrawdown),
+            _ => Err(ObjectiveError(s.to_string())),
+        }
+    }
+}
+
+impl Display for Objective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Objective::TotalReturn => write!(f, "return"),
+            Objective::CAGR => write!(f, "cagr"),
+            Objective::Sharpe => write!(f, "sharpe"),
+            Objective::Sortino => write!(f, "sortino"),
+            Objective::ProfitFactor => write!(f, "profit-factor"),
+            Objective::Expectancy => write!(f, "expectancy"),
+            Objective::MaxDrawdown => write!(f, "drawdown"),
+        }
+    }
+}
+
+// Backtest result of one set of parameters
+#[derive(Debug, PartialEq, Clone)]
+pub struct Evaluation {
+    pub parameters: Parameters,
+    pub report: Report,
+    pub score: Option<Decimal>, // None when the objective can't be calculated
+}
+
+// Everything about a backtest, other than the strategy parameters
+#[derive(Clone)]
+pub struct Simulation {
+    pub market: Market,
+    pub opening_balance: CurrencyAmount,
+    pub resolution: Resolution,
+}
+
+impl Simulation {
+    pub fn run(&self, parameters: &Parameters, prices: &[Frame]) -> Report {
+        let backtest = self.backtest(parameters, &[], prices);
+
+        let latest_price = prices
+            .last()
+            .map_or(Price::new_mid(Decimal::ZERO, Decimal::ZERO), |f| f.close);
+
+        backtest.report(latest_price)
+    }
+
+    // Run a backtest on prices, after warming the strategies up on preceding ones
+    pub fn backtest(
+        &self,
+        parameters: &Parameters,
+        warm_up: &[Frame],
+        prices: &[Frame],
+    ) -> Backtest<MACD, Donchian> {
+        let ts = MACD::new(
+            parameters.short,
+            parameters.long,
+            parameters.signal,
+            parameters.entry_lim,
+            parameters.exit_lim,
+        );
+        let rs = Donchian::new(parameters.channel_length);
+
+        let account = Account::new(
+            self.market.clone(),
+            ts,
+            rs,
+            parameters.risk_per_trade,
+            self.opening_balance,
+            self.resolution,
+        );
+
+        let mut backtest = Backtest::new(account);
+        backtest.warm_up(warm_up);
+        backtest.run(prices);
+
+        backtest
+    }
+
+    pub fn evaluate(
+        &self,
+        parameters: &Parameters,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> Evaluation {
+        let report = self.run(parameters, prices);
+
+        Evaluation {
+            parameters: *parameters,
+            score: objective.score(&report),
+            report,
+        }
+    }
+}
+
+// Strategy for searching a parameter space for the best performing parameters
+pub trait Optimiser {
+    fn optimise(
+        &self,
+        simulation: &Simulation,
+        space: &ParameterSpace,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> Optimisation;
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Optimisation {
+    pub evaluations: Vec<Evaluation>, // every parameter set tried, best first
+    pub convergence: Vec<Option<Decimal>>, // best score so far after each iteration
+}
+
+impl Optimisation {
+    pub fn best(&self) -> Option<&Evaluation> {
+        self.evaluations.first()
+    }
+}
+
+// Backtests points of a parameter space, remembering the results so each is only run once
+struct Evaluator<'a> {
+    simulation: &'a Simulation,
+    space: &'a ParameterSpace,
+    prices: &'a [Frame],
+    objective: Objective,
+    evaluations: Vec<Evaluation>,
+    visited: HashMap<Coordinates, Option<Decimal>>,
+    best: Option<Decimal>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(
+        simulation: &'a Simulation,
+        space: &'a ParameterSpace,
+        prices: &'a [Frame],
+        objective: Objective,
+    ) -> Self {
+        Self {
+            simulation,
+            space,
+            prices,
+            objective,
+            evaluations: vec![],
+            visited: HashMap::new(),
+            best: None,
+        }
+    }
+
+    // Score of the parameters at the coordinates, invalid parameters have no score
+    fn score(&mut self, coordinates: &Coordinates) -> Option<Decimal> {
+        if let Some(score) = self.visited.get(coordinates) {
+            return *score;
+        }
+
+        let parameters = self.space.parameters(coordinates);
+        let score = if parameters.is_valid() {
+            let evaluation = self
+                .simulation
+                .evaluate(&parameters, self.prices, self.objective);
+            let score = evaluation.score;
+            self.evaluations.push(evaluation);
+
+            score
+        } else {
+            None
+        };
+
+        self.best = self.best.max(score);
+        self.visited.insert(*coordinates, score);
+
+        score
+    }
+
+    fn finish(self, convergence: Vec<Option<Decimal>>) -> Optimisation {
+        let mut evaluations = self.evaluations;
+        rank(&mut evaluations);
+
+        Optimisation {
+            evaluations,
+            convergence,
+        }
+    }
+}
+
+// Uniformly random point of a parameter space
+fn random_coordinates<R: Rng>(rng: &mut R, dimensions: &Coordinates) -> Coordinates {
+    let mut coordinates = [0; DIMENSIONS];
+    for (c, size) in coordinates.iter_mut().zip(dimensions) {
+        *c = rng.gen_range(0..*size);
+    }
+
+    coordinates
+}
+
+// Move a single parameter to one of the neighbouring values
+fn step<R: Rng>(rng: &mut R, coordinates: &mut Coordinates, dimensions: &Coordinates, d: usize) {
+    let size = dimensions[d];
+    if size < 2 {
+        return;
+    }
+
+    let c = &mut coordinates[d];
+    *c = if *c == 0 {
+        1
+    } else if *c == size - 1 || rng.gen_bool(0.5) {
+        *c - 1
+    } else {
+        *c + 1
+    };
+}
+
+// Best evaluations first, the ones without a score last
+pub fn rank(evaluations: &mut [Evaluation]) {
+    evaluations.sort_by_key(|e| std::cmp::Reverse(e.score));
+}
+
+// Fixtures shared by the tests of the optimisers
+#[cfg(test)]
+mod fixtures {
+    use chrono::{Duration, TimeZone, Utc};
+    use iso_currency::Currency;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::market::Market;
+    use crate::core::price::{CurrencyAmount, Price, Resolution};
+
+    pub fn simulation() -> Simulation {
+        Simulation {
+            market: Market {
+                code: "UKX".to_string(),
+                margin_factor: dec!(0.05),
+                min_deal_size: CurrencyAmount::new(dec!(0.1), Currency::GBP),
+                min_stop_distance: dec!(1),
+                guaranteed_stop: None,
+                funding: None,
+            },
+            opening_balance: CurrencyAmount::new(dec!(10000), Currency::GBP),
+            resolution: Resolution::Day,
+        }
+    }
+
+    // Small enough to search through every combination
+    pub fn space() -> ParameterSpace {
+        ParameterSpace {
+            short: vec![3, 5],
+            long: vec![10],
+            signal: vec![3],
+            entry_lim: vec![dec!(1)],
+            exit_lim: vec![dec!(1)],
+            channel_length: vec![3, 5, 7],
+            risk_per_trade: vec![dec!(0.01)],
+        }
+    }
+
+    // Big enough for a search to pay off over trying every combination
+    pub fn wide_space() -> ParameterSpace {
+        ParameterSpace {
+            short: vec![2, 3, 4, 5],
+            long: vec![8, 10, 12],
+            signal: vec![3],
+            entry_lim: vec![dec!(1)],
+            exit_lim: vec![dec!(1)],
+            channel_length: vec![3, 4, 5, 6, 7, 8],
+            risk_per_trade: vec![dec!(0.01), dec!(0.02)],
+        }
+    }
+
+    // Price swinging up and down in waves, one frame a day
+    pub fn prices(days: usize) -> Vec<Frame> {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let wave = [0, 10, 20, 30, 40, 50, 40, 30, 20, 10];
+
+        (0..days)
+            .map(|i| {
+                let level = Decimal::from(1000 + wave[(i / 3) % wave.len()] * 3 + i as i64);
+
+                Frame {
+                    open: Price::new_mid(level, dec!(1)),
+                    close: Price::new_mid(level + dec!(2), dec!(1)),
+                    low: Price::new_mid(level - dec!(5), dec!(1)),
+                    high: Price::new_mid(level + dec!(5), dec!(1)),
+                    close_time: start + Duration::days(i as i64 + 1),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn generates_valid_combinations() {
+        let space = ParameterSpace {
+            short: vec![10, 20],
+            long: vec![20, 30],
+            signal: vec![5],
+            entry_lim: vec![dec!(40)],
+            exit_lim: vec![dec!(20), dec!(40)],
+            channel_length: vec![15],
+            risk_per_trade: vec![dec!(0.01)],
+        };
+
+        let actual: Vec<(usize, usize, Decimal)> = space
+            .combinations()
+            .iter()
+            .map(|p| (p.short, p.long, p.exit_lim))
+            .collect();
+        let expected = vec![
+            (10, 20, dec!(20)),
+            (10, 20, dec!(40)),
+            (10, 30, dec!(20)),
+            (10, 30, dec!(40)),
+            (20, 30, dec!(20)),
+            (20, 30, dec!(40)),
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn steps_through_a_range() {
+        assert_eq!(steps(10, 20, 5), vec![10, 15, 20]);
+        assert_eq!(steps(10, 22, 5), vec![10, 15, 20]);
+        assert_eq!(steps(10, 10, 5), vec![10]);
+    }
+
+    #[test]
+    fn parses_objectives() {
+        for objective in &[
+            Objective::TotalReturn,
+            Objective::CAGR,
+            Objective::Sharpe,
+            Objective::Sortino,
+            Objective::ProfitFactor,
+            Objective::Expectancy,
+            Objective::MaxDrawdown,
+        ] {
+            assert_eq!(objective.to_string().parse(), Ok(*objective));
+        }
+
+        assert_eq!(
+            "luck".parse::<Objective>(),
+            Err(ObjectiveError("luck".to_string()))
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::core::price::{CurrencyAmount, Points};
+use crate::core::trade::{Trade, TradeStatus};
+
+// How the trade sequence of each run is drawn from the backtested trades
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Sampling {
+    Shuffle,   // the same trades in a random order
+    Bootstrap, // as many trades, drawn at random with replacement
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SamplingError(pub String);
+
+impl Error for SamplingError {}
+
+impl Display for SamplingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown sampling {}", self.0)
+    }
+}
+
+impl FromStr for Sampling {
+    type Err = SamplingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shuffle" => Ok(Sampling::Shuffle),
+            "bootstrap" => Ok(Sampling::Bootstrap),
+            _ => Err(SamplingError(s.to_string())),
+        }
+    }
+}
+
+impl Display for Sampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sampling::Shuffle => write!(f, "shuffle"),
+            Sampling::Bootstrap => write!(f, "bootstrap"),
+        }
+    }
+}
+
+// Monte Carlo simulation of a backtest's closed trades
+//
+// Each trade is replayed by its R-multiple (profit as a multiple of the risk), so the trades can
+// be resized for any risk per trade, compounding the balance as they go. Every run draws its own
+// trade sequence, optionally making each trade worse by an extra spread and a random slippage.
+// Runs are repeatable for the same seed, and the same seed draws the same sequences for every
+// risk per trade, so the risk levels can be compared like for like.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MonteCarlo {
+    pub runs: usize,
+    pub sampling: Sampling,
+    pub spread: Points,   // extra cost of every trade
+    pub slippage: Points, // maximum random slippage, on both entry and exit
+    pub ruin: Decimal,    // fraction of the opening balance lost, considered a ruin
+    pub seed: u64,
+}
+
+// Summary of the values from all the runs
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Distribution {
+    pub mean: Decimal,
+    pub min: Decimal,
+    pub p5: Decimal,
+    pub p25: Decimal,
+    pub median: Decimal,
+    pub p75: Decimal,
+    pub p95: Decimal,
+    pub max: Decimal,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MonteCarloResult {
+    pub risk_per_trade: Decimal,
+    pub runs: usize,
+    pub final_balance: Distribution,
+    pub max_drawdown: Distribution, // fraction of the peak balance
+    pub risk_of_ruin: Decimal,      // fraction of the runs ending in a ruin
+}
+
+impl MonteCarlo {
+    pub fn new(runs: usize, seed: u64) -> Self {
+        Self {
+            runs,
+            sampling: Sampling::Shuffle,
+            spread: dec!(0),
+            slippage: dec!(0),
+            ruin: dec!(0.5),
+            seed,
+        }
+    }
+
+    // None without any runs
+    pub fn run(
+        &self,
+        trades: &[Trade],
+        opening_balance: CurrencyAmount,
+        risk_per_trade: Decimal,
+    ) -> Option<MonteCarloResult> {
+        // R-multiple and stop distance of every closed trade
+        let outcomes: Vec<(Decimal, Points)> = trades
+            .iter()
+            .filter(|t| t.status == TradeStatus::Closed)
+            .map(|t| (t.risk_reward, (t.entry_price - t.stop).abs()))
+            .collect();
+
+        let ruin_level = opening_balance.amount * (dec!(1) - self.ruin);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let mut balances = Vec::with_capacity(self.runs);
+        let mut drawdowns = Vec::with_capacity(self.runs);
+        let mut ruins = 0;
+
+        for _ in 0..self.runs {
+            let sequence = self.sequence(&mut rng, &outcomes);
+
+            let mut balance = opening_balance.amount;
+            let mut peak = balance;
+            let mut max_drawdown = dec!(0);
+            let mut ruined = false;
+
+            for (r, distance) in sequence {
+                let r = r - self.cost(&mut rng, distance);
+                balance = (balance + balance * risk_per_trade * r).round_dp(2);
+
+                peak = peak.max(balance);
+                if peak > dec!(0) {
+                    max_drawdown = max_drawdown.max((peak - balance) / peak);
+                }
+
+                // Nothing left to trade with
+                if balance <= ruin_level {
+                    ruined = true;
+                    break;
+                }
+            }
+
+            balances.push(balance);
+            drawdowns.push(max_drawdown.round_dp(6));
+            if ruined {
+                ruins += 1;
+            }
+        }
+
+        Some(MonteCarloResult {
+            risk_per_trade,
+            runs: self.runs,
+            final_balance: Distribution::new(balances)?,
+            max_drawdown: Distribution::new(drawdowns)?,
+            risk_of_ruin: (Decimal::from(ruins) / Decimal::from(self.runs)).round_dp(4),
+        })
+    }
+
+    fn sequence<R: Rng>(
+        &self,
+        rng: &mut R,
+        outcomes: &[(Decimal, Points)],
+    ) -> Vec<(Decimal, Points)> {
+        match self.sampling {
+            Sampling::Shuffle => {
+                let mut sequence = outcomes.to_vec();
+                sequence.shuffle(rng);
+
+                sequence
+            }
+            Sampling::Bootstrap => (0..outcomes.len())
+                .map(|_| outcomes[rng.gen_range(0..outcomes.len())])
+                .collect(),
+        }
+    }
+
+    // Spread and slippage in R, i.e. as a fraction of the stop distance
+    fn cost<R: Rng>(&self, rng: &mut R, distance: Points) -> Decimal {
+        if distance <= dec!(0) {
+            return dec!(0);
+        }
+
+        let slippage = if self.slippage > dec!(0) {
+            let entry = Decimal::new(rng.gen_range(0..=1000), 3);
+            let exit = Decimal::new(rng.gen_range(0..=1000), 3);
+
+            self.slippage * (entry + exit)
+        } else {
+            dec!(0)
+        };
+
+        (self.spread + slippage) / distance
+    }
+}
+
+impl Distribution {
+    // None without any values
+    pub fn new(mut values: Vec<Decimal>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort();
+        let total: Decimal = values.iter().sum();
+
+        Some(Self {
+            mean: (total / Decimal::from(values.len())).round_dp(6),
+            min: values[0],
+            p5: percentile(&values, dec!(0.05)),
+            p25: percentile(&values, dec!(0.25)),
+            median: percentile(&values, dec!(0.5)),
+            p75: percentile(&values, dec!(0.75)),
+            p95: percentile(&values, dec!(0.95)),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+// Nearest value to a percentile of sorted values
+fn percentile(sorted: &[Decimal], p: Decimal) -> Decimal {
+    let index = (Decimal::from(sorted.len() - 1) * p).round();
+
+    sorted[index.to_usize().unwrap_or(0)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use iso_currency::Currency;
+
+    use crate::core::trade::{Direction, TradeOutcome};
+
+    #[test]
+    fn is_repeatable_with_a_seed() {
+        let monte_carlo = MonteCarlo {
+            sampling: Sampling::Bootstrap,
+            slippage: dec!(2),
+            ..MonteCarlo::new(100, 42)
+        };
+
+        let first = monte_carlo.run(&trades(), balance(), dec!(0.02));
+        let second = monte_carlo.run(&trades(), balance(), dec!(0.02));
+
+        assert_eq!(first, second);
+        assert_ne!(
+            first,
+            MonteCarlo {
+                seed: 7,
+                ..monte_carlo
+            }
+            .run(&trades(), balance(), dec!(0.02))
+        );
+    }
+
+    #[test]
+    fn shuffling_only_changes_the_path() {
+        let actual = MonteCarlo::new(200, 1)
+            .run(&trades(), balance(), dec!(0.1))
+            .unwrap();
+
+        // Compounding doesn't depend on the order: 10000 * 1.2 * 0.9 * 1.1 * 0.9^4
+        assert_eq!(actual.final_balance.min, dec!(8660.52));
+        assert_eq!(actual.final_balance.max, dec!(8660.52));
+        assert!(actual.max_drawdown.min < actual.max_drawdown.max);
+        assert_eq!(actual.max_drawdown.max, dec!(0.3439)); // all four losses in a row
+    }
+
+    #[test]
+    fn bootstraps_different_outcomes() {
+        let monte_carlo = MonteCarlo {
+            sampling: Sampling::Bootstrap,
+            ..MonteCarlo::new(200, 1)
+        };
+
+        let actual = monte_carlo.run(&trades(), balance(), dec!(0.1)).unwrap();
+
+        assert!(actual.final_balance.min < dec!(8660.52));
+        assert!(actual.final_balance.max > dec!(8660.52));
+        assert!(actual.final_balance.p5 <= actual.final_balance.median);
+        assert!(actual.final_balance.median <= actual.final_balance.p95);
+    }
+
+    #[test]
+    fn makes_trades_worse_by_spread_and_slippage() {
+        let clean = MonteCarlo::new(50, 1)
+            .run(&trades(), balance(), dec!(0.1))
+            .unwrap();
+        let spread = MonteCarlo {
+            spread: dec!(5),
+            ..MonteCarlo::new(50, 1)
+        }
+        .run(&trades(), balance(), dec!(0.1))
+        .unwrap();
+        let slippage = MonteCarlo {
+            spread: dec!(5),
+            slippage: dec!(5),
+            ..MonteCarlo::new(50, 1)
+        }
+        .run(&trades(), balance(), dec!(0.1))
+        .unwrap();
+
+        // Spread of 5 points costs 0.1R with a stop 50 points away: 10000 * 1.19 * 1.09 * 0.89^4
+        assert_eq!(spread.final_balance.max.round(), dec!(8138));
+        assert!(slippage.final_balance.max < spread.final_balance.max);
+        assert!(slippage.final_balance.max < clean.final_balance.min);
+    }
+
+    #[test]
+    fn calculates_risk_of_ruin() {
+        let monte_carlo = MonteCarlo {
+            sampling: Sampling::Bootstrap,
+            ..MonteCarlo::new(500, 3)
+        };
+
+        let low = monte_carlo.run(&trades(), balance(), dec!(0.01)).unwrap();
+        let high = monte_carlo.run(&trades(), balance(), dec!(0.25)).unwrap();
+
+        assert_eq!(low.risk_of_ruin, dec!(0));
+        assert!(high.risk_of_ruin > dec!(0.1));
+        assert!(high.max_drawdown.median > low.max_drawdown.median);
+    }
+
+    #[test]
+    fn only_replays_closed_trades() {
+        let mut trades = trades();
+        trades.push(Trade {
+            status: TradeStatus::Open,
+            ..trade(dec!(-10))
+        });
+
+        let actual = MonteCarlo::new(10, 1)
+            .run(&trades, balance(), dec!(0.1))
+            .unwrap();
+
+        assert_eq!(actual.final_balance.max, dec!(8660.52));
+    }
+
+    #[test]
+    fn needs_at_least_a_run() {
+        assert_eq!(
+            MonteCarlo::new(0, 1).run(&trades(), balance(), dec!(0.1)),
+            None
+        );
+    }
+
+    #[test]
+    fn picks_percentiles() {
+        let actual = Distribution::new((1..=101).map(Decimal::from).collect()).unwrap();
+
+        assert_eq!(
+            actual,
+            Distribution {
+                mean: dec!(51),
+                min: dec!(1),
+                p5: dec!(6),
+                p25: dec!(26),
+                median: dec!(51),
+                p75: dec!(76),
+                p95: dec!(96),
+                max: dec!(101),
+            }
+        );
+        assert_eq!(Distribution::new(vec![]), None);
+    }
+
+    // Fixtures
+
+    fn balance() -> CurrencyAmount {
+        CurrencyAmount::new(dec!(10000), Currency::GBP)
+    }
+
+    // Trades risking 50 points each
+    fn trades() -> Vec<Trade> {
+        [dec!(2), dec!(-1), dec!(-1), dec!(1), dec!(-1), dec!(-1)]
+            .iter()
+            .map(|r| trade(*r))
+            .collect()
+    }
+
+    fn trade(r: Decimal) -> Trade {
+        let time = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let size = CurrencyAmount::new(dec!(1), Currency::GBP);
+        let price_diff = r * dec!(50);
+
+        Trade {
+            id: "1".to_string(),
+            status: TradeStatus::Closed,
+            direction: Direction::Buy,
+            entry_time: time,
+            entry_price: dec!(1000),
+            exit_time: Some(time + Duration::days(1)),
+            exit_price: Some(dec!(1000) + price_diff),
+            stop: dec!(950),
+            stop_history: vec![],
+            size,
+            risk: size * dec!(50),
+            outcome: if r > dec!(0) {
+                TradeOutcome::Profit
+            } else {
+                TradeOutcome::Loss
+            },
+            price_diff,
+            profit: size * price_diff,
+            funding: size * dec!(0),
+            risk_reward: r,
+        }
+    }
+}
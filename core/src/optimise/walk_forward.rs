@@ -0,0 +1,298 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use super::{Evaluation, Objective, Optimiser, ParameterSpace, Simulation};
+use crate::core::account::Equity;
+use crate::core::price::Frame;
+use crate::core::report::Report;
+use crate::core::trade::{Trade, TradeStatus};
+
+// Sizes of the rolling windows, in price frames
+//
+// Each window optimises on `in_sample` frames and tests the winner on the `out_of_sample` frames
+// that follow. The next window moves forward by `out_of_sample` frames, so the out-of-sample
+// periods follow each other without overlap.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WalkForward {
+    pub in_sample: usize,
+    pub out_of_sample: usize,
+}
+
+// Outcome of one walk-forward window
+#[derive(Debug, PartialEq, Clone)]
+pub struct WalkForwardWindow {
+    pub in_sample: Evaluation, // best parameters on the in-sample period
+    pub out_of_sample: Report, // the same parameters on the out-of-sample period
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WalkForwardResult {
+    pub windows: Vec<WalkForwardWindow>,
+    pub report: Report, // all the out-of-sample periods combined
+    pub trades: Vec<Trade>,
+    pub equity: Vec<Equity>,
+    // Average annualised out-of-sample return as a fraction of the average in-sample one.
+    // Values well below 1 suggest the parameters are fitted to the in-sample noise.
+    pub efficiency: Option<Decimal>,
+}
+
+impl WalkForward {
+    // Start indices of the in-sample periods for a price history of a given length
+    pub fn window_starts(&self, length: usize) -> Vec<usize> {
+        let size = self.in_sample + self.out_of_sample;
+        if self.out_of_sample == 0 || size > length {
+            return vec![];
+        }
+
+        (0..=length - size).step_by(self.out_of_sample).collect()
+    }
+
+    pub fn run(
+        &self,
+        simulation: &Simulation,
+        optimiser: &dyn Optimiser,
+        space: &ParameterSpace,
+        prices: &[Frame],
+        objective: Objective,
+    ) -> WalkForwardResult {
+        let mut windows = vec![];
+        let mut trades = vec![];
+        let mut equity = vec![];
+
+        // Each out-of-sample period continues with the balance the previous one finished with
+        let mut out_of_sample_simulation = simulation.clone();
+
+        for start in self.window_starts(prices.len()) {
+            let split = start + self.in_sample;
+            let in_sample = &prices[start..split];
+            let out_of_sample = &prices[split..split + self.out_of_sample];
+
+            let optimisation = optimiser.optimise(simulation, space, in_sample, objective);
+            let best = match optimisation.best() {
+                Some(best) => best.clone(),
+                None => continue,
+            };
+
+            let backtest =
+                out_of_sample_simulation.backtest(&best.parameters, in_sample, out_of_sample);
+            let latest = out_of_sample[out_of_sample.len() - 1];
+
+            let window_trades: Vec<Trade> = backtest
+                .account
+                .trade_log(latest.close)
+                .into_iter()
+                .map(|t| close_open_trade(t, &latest))
+                .collect();
+            let report = Report::new(
+                out_of_sample_simulation.opening_balance,
+                &window_trades,
+                &backtest.equity,
+            );
+
+            out_of_sample_simulation.opening_balance = report.closing_balance;
+            trades.extend(window_trades);
+            equity.extend(backtest.equity);
+            windows.push(WalkForwardWindow {
+                in_sample: best,
+                out_of_sample: report,
+            });
+        }
+
+        let report = Report::new(simulation.opening_balance, &trades, &equity);
+        let efficiency = efficiency(&windows);
+
+        WalkForwardResult {
+            windows,
+            report,
+            trades,
+            equity,
+            efficiency,
+        }
+    }
+}
+
+// Positions still open at the end of an out-of-sample period are closed at the latest price,
+// the next period starts from scratch
+fn close_open_trade(trade: Trade, latest: &Frame) -> Trade {
+    if trade.status == TradeStatus::Closed {
+        return trade;
+    }
+
+    Trade {
+        status: TradeStatus::Closed,
+        exit_time: Some(latest.close_time),
+        exit_price: Some(trade.entry_price + trade.price_diff),
+        ..trade
+    }
+}
+
+fn efficiency(windows: &[WalkForwardWindow]) -> Option<Decimal> {
+    let returns: Vec<(Decimal, Decimal)> = windows
+        .iter()
+        .filter_map(|w| {
+            Some((
+                w.in_sample.report.annualised_return()?,
+                w.out_of_sample.annualised_return()?,
+            ))
+        })
+        .collect();
+
+    let in_sample: Decimal = returns.iter().map(|(is, _)| is).sum();
+    let out_of_sample: Decimal = returns.iter().map(|(_, oos)| oos).sum();
+
+    if in_sample > dec!(0) {
+        Some((out_of_sample / in_sample).round_dp(4))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use crate::core::price::CurrencyAmount;
+    use crate::optimise::fixtures::{prices, simulation, space};
+    use crate::optimise::{grid_search, GridSearch};
+
+    #[test]
+    fn rolls_windows_forward_by_out_of_sample_length() {
+        let walk_forward = WalkForward {
+            in_sample: 100,
+            out_of_sample: 25,
+        };
+
+        assert_eq!(walk_forward.window_starts(200), vec![0, 25, 50, 75]);
+        assert_eq!(walk_forward.window_starts(124), Vec::<usize>::new());
+        assert_eq!(walk_forward.window_starts(125), vec![0]);
+    }
+
+    #[test]
+    fn tests_best_in_sample_parameters_out_of_sample() {
+        let prices = prices(140);
+        let walk_forward = WalkForward {
+            in_sample: 60,
+            out_of_sample: 20,
+        };
+        let simulation = simulation();
+        let space = space();
+
+        let result = walk_forward.run(
+            &simulation,
+            &GridSearch { threads: 2 },
+            &space,
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        assert_eq!(result.windows.len(), 4);
+        for (window, start) in result.windows.iter().zip(walk_forward.window_starts(140)) {
+            let split = start + 60;
+            let in_sample = &prices[start..split];
+            let best = &grid_search(&simulation, &space, in_sample, Objective::TotalReturn, 1)[0];
+
+            assert_eq!(window.in_sample.parameters, best.parameters);
+            assert_eq!(window.out_of_sample.start, prices[split].close_time);
+            assert_eq!(window.out_of_sample.end, prices[split + 19].close_time);
+        }
+    }
+
+    #[test]
+    fn combines_out_of_sample_periods() {
+        let prices = prices(140);
+        let walk_forward = WalkForward {
+            in_sample: 60,
+            out_of_sample: 20,
+        };
+
+        let result = walk_forward.run(
+            &simulation(),
+            &GridSearch { threads: 2 },
+            &space(),
+            &prices,
+            Objective::TotalReturn,
+        );
+
+        // Balance carries over between the windows
+        for pair in result.windows.windows(2) {
+            assert_eq!(
+                pair[1].out_of_sample.opening_balance,
+                pair[0].out_of_sample.closing_balance
+            );
+        }
+
+        let last = result.windows.last().unwrap();
+        assert_eq!(
+            result.report.closing_balance,
+            last.out_of_sample.closing_balance
+        );
+        assert_eq!(result.report.start, prices[60].close_time);
+        assert_eq!(result.report.end, prices[139].close_time);
+        assert_eq!(result.equity.len(), 80);
+        assert!(result
+            .trades
+            .iter()
+            .all(|t| t.status == TradeStatus::Closed));
+        assert!(result
+            .trades
+            .iter()
+            .all(|t| t.entry_time >= prices[60].close_time));
+    }
+
+    #[test]
+    fn calculates_efficiency_ratio() {
+        let window = |is: Decimal, oos: Decimal| {
+            let report = |total_return| Report {
+                total_return,
+                ..report()
+            };
+
+            WalkForwardWindow {
+                in_sample: Evaluation {
+                    parameters: space().combinations()[0],
+                    report: report(is),
+                    score: None,
+                },
+                out_of_sample: report(oos),
+            }
+        };
+
+        assert_eq!(
+            efficiency(&[window(dec!(0.2), dec!(0.1)), window(dec!(0.2), dec!(0.2))]),
+            Some(dec!(0.75))
+        );
+        assert_eq!(efficiency(&[window(dec!(-0.2), dec!(0.1))]), None);
+    }
+
+    // Fixtures
+
+    // A year long report, so the annualised return equals the total return
+    fn report() -> Report {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+
+        Report::new(
+            CurrencyAmount::new(dec!(1000), Currency::GBP),
+            &[],
+            &[
+                equity(start, dec!(1000)),
+                equity(start + Duration::seconds(31_557_600), dec!(1000)),
+            ],
+        )
+    }
+
+    fn equity(time: DateTime<Utc>, balance: Decimal) -> Equity {
+        let balance = CurrencyAmount::new(balance, Currency::GBP);
+
+        Equity {
+            time,
+            balance,
+            open_pnl: CurrencyAmount::new(dec!(0), Currency::GBP),
+            margin_used: CurrencyAmount::new(dec!(0), Currency::GBP),
+            free_margin: balance,
+        }
+    }
+}
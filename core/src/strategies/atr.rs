@@ -0,0 +1,328 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::core::maths::{Indicator, ATR, EMA};
+use crate::core::price::{Points, Resolution};
+use crate::core::strategy::{Context, RiskStrategy, RiskStrategyError};
+use crate::core::trade::{Direction, Position};
+use crate::price::Frame;
+
+const ATR_ERROR: Decimal = dec!(0.1);
+
+// Places stop-loss a multiple of the Average True Range away from the entry price
+//
+// A trailing stop follows the price as a chandelier exit - a multiple of ATR below the latest high
+// for long positions and above the latest low for short positions.
+pub struct AtrStop {
+    pub length: usize,
+    pub multiple: Decimal,
+    pub trailing: bool,
+    // Indicator state, kept between price updates
+    atr: ATR,
+    samples: usize,
+}
+
+impl AtrStop {
+    pub fn new(length: usize, multiple: Decimal) -> Self {
+        Self {
+            length,
+            multiple,
+            trailing: false,
+            atr: ATR::new(length),
+            samples: 0,
+        }
+    }
+
+    // ATR for every frame of the history, calculated from scratch
+    pub fn atr(&self, history: &[Frame]) -> Vec<Decimal> {
+        let mut atr = ATR::new(self.length);
+
+        history
+            .iter()
+            .map(|frame| {
+                atr.push(*frame);
+                atr.value().expect("Couldn't calculate ATR")
+            })
+            .collect()
+    }
+}
+
+impl RiskStrategy for AtrStop {
+    fn update(&mut self, context: &Context) {
+        if let Some(frame) = context.history.latest() {
+            self.atr.push(*frame);
+            self.samples += 1;
+        }
+    }
+
+    fn stop(&self, direction: Direction, context: &Context) -> Result<Points, RiskStrategyError> {
+        let (latest, atr) = match (context.history.latest(), self.atr.value()) {
+            (Some(latest), Some(atr)) if self.samples >= self.length => (latest, atr),
+            _ => return Err(RiskStrategyError::NotEnoughHistory),
+        };
+
+        // Measured from the price we'd enter at
+        let stop = match direction {
+            Direction::Buy => latest.close.ask - atr * self.multiple,
+            Direction::Sell => latest.close.bid + atr * self.multiple,
+        };
+
+        Ok(stop)
+    }
+
+    fn trailing_stop(&self, position: &Position, context: &Context) -> Option<Points> {
+        if !self.trailing || self.samples < self.length {
+            return None;
+        }
+
+        let latest = context.history.latest()?;
+        let atr = self.atr.value()?;
+
+        // Only moving in favour of the position is applied, so this follows the extreme since entry
+        let stop = match position.entry.direction {
+            Direction::Buy => latest.high.bid - atr * self.multiple,
+            Direction::Sell => latest.low.ask + atr * self.multiple,
+        };
+
+        Some(stop)
+    }
+
+    fn history_length(&self) -> Option<usize> {
+        // all the history needed is in the indicator state
+        Some(1)
+    }
+
+    fn warm_up_length(&self, _resolution: Resolution) -> Option<usize> {
+        // Wilder's smoothing over n frames weighs them like an EMA over 2n - 1
+        Some(EMA::samples_needed(2 * self.length.max(1) - 1, ATR_ERROR).max(self.length))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use chrono::prelude::*;
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::price::{CurrencyAmount, Frame, Price, PriceHistory, Resolution};
+    use crate::core::trade::{Entry, StopType};
+
+    // RiskStrategy
+
+    #[test]
+    fn calculates_basic_atr() {
+        let history = ranging_history(
+            dec!(1000),
+            dec!(20),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+            Resolution::Minute(10),
+            10,
+        );
+
+        let strategy = AtrStop::new(3, dec!(2));
+
+        let expected = vec![dec!(22); 10];
+        let actual = strategy.atr(history.frames());
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn rejects_entry_without_enough_history() {
+        let balance = CurrencyAmount::new(dec!(1020), Currency::GBP);
+        let mut rs = AtrStop::new(4, dec!(2));
+        let history = ranging_history(
+            dec!(1000),
+            dec!(20),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+            Resolution::Minute(10),
+            3,
+        );
+        feed(&mut rs, &history);
+
+        assert_eq!(
+            rs.entry(Direction::Buy, &Context::new(&history), balance),
+            Err(RiskStrategyError::NotEnoughHistory)
+        );
+    }
+
+    #[test]
+    fn sets_stop_based_on_recent_volatility() {
+        let risk = CurrencyAmount::new(dec!(10), Currency::GBP);
+        let mut tight_rs = AtrStop::new(2, dec!(1));
+        let mut wide_rs = AtrStop::new(2, dec!(3));
+
+        let older = ranging_history(
+            dec!(1000),
+            dec!(100),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+            Resolution::Minute(10),
+            5,
+        );
+        let recent = ranging_history(
+            dec!(1000),
+            dec!(20),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 50, 0),
+            Resolution::Minute(10),
+            5,
+        );
+
+        let history = PriceHistory::from_frames(
+            Resolution::Minute(10),
+            [older.frames(), recent.frames()].concat(),
+        );
+        feed(&mut tight_rs, &history);
+        feed(&mut wide_rs, &history);
+        let context = Context::new(&history);
+
+        // ATR converges from 102 towards 22 after the volatility drops: 62, 42, 32, 27, 24.5
+        let atr = dec!(24.5);
+
+        let tight_expected_buy = Ok(Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(1001.0),
+            stop: dec!(1001.0) - atr,
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(0.408163), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
+        });
+        let tight_expected_sell = Ok(Entry {
+            position_id: String::new(),
+            direction: Direction::Sell,
+            price: dec!(999.0),
+            stop: dec!(999.0) + atr,
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(0.408163), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
+        });
+
+        let wide_expected_buy = Ok(Entry {
+            position_id: String::new(),
+            direction: Direction::Buy,
+            price: dec!(1001.0),
+            stop: dec!(1001.0) - atr * dec!(3),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(0.136054), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
+        });
+        let wide_expected_sell = Ok(Entry {
+            position_id: String::new(),
+            direction: Direction::Sell,
+            price: dec!(999.0),
+            stop: dec!(999.0) + atr * dec!(3),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(0.136054), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
+        });
+
+        assert_eq!(
+            tight_rs.entry(Direction::Buy, &context, risk),
+            tight_expected_buy
+        );
+        assert_eq!(
+            tight_rs.entry(Direction::Sell, &context, risk),
+            tight_expected_sell
+        );
+
+        assert_eq!(
+            wide_rs.entry(Direction::Buy, &context, risk),
+            wide_expected_buy
+        );
+        assert_eq!(
+            wide_rs.entry(Direction::Sell, &context, risk),
+            wide_expected_sell
+        );
+    }
+
+    #[test]
+    fn trails_stop_below_the_high() {
+        let mut rs = AtrStop::new(3, dec!(2));
+        let mut trailing_rs = AtrStop::new(3, dec!(2));
+        trailing_rs.trailing = true;
+
+        let history = ranging_history(
+            dec!(1000),
+            dec!(20),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+            Resolution::Minute(10),
+            5,
+        );
+        feed(&mut rs, &history);
+        feed(&mut trailing_rs, &history);
+        let context = Context::new(&history);
+
+        let long = Position::new(Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(900),
+            stop: dec!(850),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+        });
+        let short = Position::new(Entry {
+            direction: Direction::Sell,
+            price: dec!(1100),
+            stop: dec!(1150),
+            ..long.entry.clone()
+        });
+
+        // ATR is 22, high bid is 1009, low ask is 991
+        assert_eq!(rs.trailing_stop(&long, &context), None);
+        assert_eq!(trailing_rs.trailing_stop(&long, &context), Some(dec!(965)));
+        assert_eq!(
+            trailing_rs.trailing_stop(&short, &context),
+            Some(dec!(1035))
+        );
+    }
+
+    // Fixtures
+
+    // Replay the history into the strategy one frame at a time
+    fn feed(strategy: &mut AtrStop, history: &PriceHistory) {
+        let mut replay = PriceHistory::new(history.resolution);
+
+        for frame in history {
+            replay.push(*frame);
+            strategy.update(&Context::new(&replay));
+        }
+    }
+
+    // History that ranges around a price level, closing at the level every time
+    fn ranging_history(
+        level: Decimal,
+        range: Decimal,
+        spread: Decimal,
+        start_time: DateTime<Utc>,
+        resolution: Resolution,
+        length: usize,
+    ) -> PriceHistory {
+        let frame = Frame {
+            open: Price::new_mid(level, spread),
+            close: Price::new_mid(level, spread),
+            high: Price::new_mid(level + range / dec!(2), spread),
+            low: Price::new_mid(level - range / dec!(2), spread),
+            close_time: start_time,
+        };
+        let timeline = iter::successors(Some(start_time + resolution), |t| Some(*t + resolution));
+
+        let history: Vec<Frame> = timeline
+            .map(|time| Frame {
+                close_time: time,
+                ..frame
+            })
+            .take(length)
+            .collect();
+
+        PriceHistory::from_frames(resolution, history)
+    }
+}
@@ -0,0 +1,187 @@
+use rust_decimal::Decimal;
+
+use crate::core::price::{Points, Resolution};
+use crate::core::strategy::{Context, RiskStrategy, RiskStrategyError};
+use crate::core::trade::{Direction, Position};
+
+// Moves the stop-loss to the entry price once the position has gained a multiple of its initial
+// risk (R), otherwise follows the wrapped risk strategy
+pub struct Breakeven<RS: RiskStrategy> {
+    pub strategy: RS,
+    pub after: Decimal, // R multiple of gain after which the stop moves to breakeven
+}
+
+impl<RS: RiskStrategy> Breakeven<RS> {
+    pub fn new(strategy: RS, after: Decimal) -> Self {
+        Self { strategy, after }
+    }
+}
+
+impl<RS: RiskStrategy> RiskStrategy for Breakeven<RS> {
+    fn update(&mut self, context: &Context) {
+        self.strategy.update(context)
+    }
+
+    fn stop(&self, direction: Direction, context: &Context) -> Result<Points, RiskStrategyError> {
+        self.strategy.stop(direction, context)
+    }
+
+    fn trailing_stop(&self, position: &Position, context: &Context) -> Option<Points> {
+        let trailing = self.strategy.trailing_stop(position, context);
+        let latest = match context.history.latest() {
+            Some(latest) => latest,
+            None => return trailing,
+        };
+
+        let entry = &position.entry;
+        let risk = (entry.price - entry.stop).abs();
+        let gain = match entry.direction {
+            Direction::Buy => latest.close.bid - entry.price,
+            Direction::Sell => entry.price - latest.close.ask,
+        };
+
+        if gain < risk * self.after {
+            return trailing;
+        }
+
+        // Keep the wrapped trailing stop if it is already past breakeven
+        let stop = match (entry.direction, trailing) {
+            (Direction::Buy, Some(stop)) => stop.max(entry.price),
+            (Direction::Sell, Some(stop)) => stop.min(entry.price),
+            (_, None) => entry.price,
+        };
+
+        Some(stop)
+    }
+
+    fn history_length(&self) -> Option<usize> {
+        self.strategy.history_length()
+    }
+
+    fn warm_up_length(&self, resolution: Resolution) -> Option<usize> {
+        self.strategy.warm_up_length(resolution)
+    }
+
+    fn resolutions(&self) -> Vec<Resolution> {
+        self.strategy.resolutions()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeZone, Utc};
+    use iso_currency::Currency;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::price::{CurrencyAmount, Frame, Price, PriceHistory};
+    use crate::core::trade::{Entry, StopType};
+
+    #[test]
+    fn moves_stop_to_entry_after_gaining_the_risk() {
+        let rs = Breakeven::new(ConstStop {}, dec!(1));
+
+        let long = position(Direction::Buy, dec!(100), dec!(90));
+        let short = position(Direction::Sell, dec!(100), dec!(110));
+
+        let below = history(dec!(105));
+        let above = history(dec!(111));
+        let far_below = history(dec!(89));
+
+        assert_eq!(rs.trailing_stop(&long, &Context::new(&below)), None);
+        assert_eq!(
+            rs.trailing_stop(&long, &Context::new(&above)),
+            Some(dec!(100))
+        );
+
+        assert_eq!(rs.trailing_stop(&short, &Context::new(&below)), None);
+        assert_eq!(
+            rs.trailing_stop(&short, &Context::new(&far_below)),
+            Some(dec!(100))
+        );
+    }
+
+    #[test]
+    fn keeps_a_tighter_trailing_stop() {
+        let rs = Breakeven::new(Trailing { stop: dec!(104) }, dec!(1));
+        let long = position(Direction::Buy, dec!(100), dec!(90));
+
+        assert_eq!(
+            rs.trailing_stop(&long, &Context::new(&history(dec!(111)))),
+            Some(dec!(104))
+        );
+
+        let rs = Breakeven::new(Trailing { stop: dec!(95) }, dec!(1));
+
+        assert_eq!(
+            rs.trailing_stop(&long, &Context::new(&history(dec!(111)))),
+            Some(dec!(100))
+        );
+        assert_eq!(
+            rs.trailing_stop(&long, &Context::new(&history(dec!(105)))),
+            Some(dec!(95))
+        );
+    }
+
+    // Fixtures
+
+    struct ConstStop {}
+
+    impl RiskStrategy for ConstStop {
+        fn stop(
+            &self,
+            _direction: Direction,
+            _context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            Ok(dec!(90))
+        }
+    }
+
+    // Proposes the same trailing stop on every update
+    struct Trailing {
+        stop: Points,
+    }
+
+    impl RiskStrategy for Trailing {
+        fn stop(
+            &self,
+            _direction: Direction,
+            _context: &Context,
+        ) -> Result<Points, RiskStrategyError> {
+            Ok(self.stop)
+        }
+
+        fn trailing_stop(&self, _position: &Position, _context: &Context) -> Option<Points> {
+            Some(self.stop)
+        }
+    }
+
+    fn position(direction: Direction, price: Points, stop: Points) -> Position {
+        Position::new(Entry {
+            position_id: "1".to_string(),
+            direction,
+            price,
+            stop,
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), Currency::GBP),
+            time: date(),
+        })
+    }
+
+    fn history(close: Points) -> PriceHistory {
+        let price = Price::new_mid(close, dec!(2));
+        let frame = Frame {
+            open: price,
+            close: price,
+            low: price,
+            high: price,
+            close_time: date(),
+        };
+
+        PriceHistory::from_frames(Resolution::Minute(10), vec![frame])
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.ymd(2021, 1, 1).and_hms(12, 0, 0)
+    }
+}
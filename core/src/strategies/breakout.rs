@@ -0,0 +1,210 @@
+use rust_decimal::Decimal;
+
+use crate::core::maths::{DonchianChannel, Indicator};
+use crate::core::price::Resolution;
+use crate::core::strategy::{Context, TradingStrategy, Trend};
+use crate::strategies::MACD;
+
+// Channel breakout in the direction of a higher timeframe trend
+//
+// The MACD on the filter resolution (e.g. daily) decides which way we're allowed to trade,
+// a break of the Donchian channel on the account's resolution (e.g. hourly) triggers the entry.
+// Positions are held until the filter trend ends.
+pub struct Breakout {
+    pub filter_resolution: Resolution,
+    pub channel_length: usize,
+    // Indicator state, kept between price updates
+    filter: MACD,
+    channel: DonchianChannel,
+    samples: usize,
+    trend: Trend,
+}
+
+impl Breakout {
+    pub fn new(filter: MACD, filter_resolution: Resolution, channel_length: usize) -> Self {
+        Self {
+            filter_resolution,
+            channel_length,
+            filter,
+            channel: DonchianChannel::new(channel_length),
+            samples: 0,
+            trend: Trend::Neutral,
+        }
+    }
+
+    // Direction the price broke out of the channel of the previous frames, if it did
+    fn breakout(&self, close: Decimal) -> Trend {
+        match self.channel.value() {
+            Some((_, high)) if self.samples >= self.channel_length && close > high => {
+                Trend::Bullish
+            }
+            Some((low, _)) if self.samples >= self.channel_length && close < low => Trend::Bearish,
+            _ => Trend::Neutral,
+        }
+    }
+}
+
+impl TradingStrategy for Breakout {
+    fn update(&mut self, context: &Context) {
+        for frame in context.completed_frames(self.filter_resolution) {
+            self.filter.push(*frame);
+        }
+
+        let frame = match context.history.latest() {
+            Some(frame) => *frame,
+            None => return,
+        };
+
+        let filter = self.filter.current_trend();
+        let breakout = self.breakout(frame.close.mid_price());
+
+        self.trend = match filter {
+            Trend::Neutral => Trend::Neutral,
+            _ if self.trend == filter || breakout == filter => filter,
+            _ => Trend::Neutral,
+        };
+
+        // Channel only includes the frames before the current one
+        self.channel.push(frame);
+        self.samples += 1;
+    }
+
+    fn trend(&self, _context: &Context) -> Trend {
+        self.trend
+    }
+
+    fn history_length(&self) -> Option<usize> {
+        // all the history needed is in the indicator state
+        Some(1)
+    }
+
+    fn warm_up_length(&self, resolution: Resolution) -> Option<usize> {
+        // The filter warms up on frames of its own resolution, with a partial one to begin with
+        let per_filter_frame = self.filter_resolution.nominal_duration().num_seconds()
+            / resolution.nominal_duration().num_seconds();
+        let filter = (self.filter.warm_up_length(self.filter_resolution)? + 1)
+            * per_filter_frame.max(1) as usize;
+
+        Some(filter.max(self.channel_length))
+    }
+
+    fn resolutions(&self) -> Vec<Resolution> {
+        vec![self.filter_resolution]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::prelude::*;
+    use chrono::Duration;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::price::{DerivedHistory, Frame, Price, PriceHistory};
+
+    #[test]
+    fn enters_on_breakout_in_direction_of_filter() {
+        let mut strategy = Breakout::new(daily_macd(dec!(0)), Resolution::Day, 3);
+
+        // Price rising every hour for 6 days
+        let prices: Vec<_> = (0..6 * 24).map(|i| dec!(1000) + Decimal::from(i)).collect();
+        let trends = run(&mut strategy, &prices);
+
+        // Daily trend is not established in the first days
+        assert!(trends[..3 * 24].iter().all(|t| *t == Trend::Neutral));
+        assert_eq!(trends.last(), Some(&Trend::Bullish));
+    }
+
+    #[test]
+    fn stays_out_without_filter_trend() {
+        let mut strategy = Breakout::new(daily_macd(dec!(1000)), Resolution::Day, 3);
+
+        let prices: Vec<_> = (0..6 * 24).map(|i| dec!(1000) + Decimal::from(i)).collect();
+        let trends = run(&mut strategy, &prices);
+
+        assert!(trends.iter().all(|t| *t == Trend::Neutral));
+    }
+
+    #[test]
+    fn holds_position_until_filter_trend_ends() {
+        let mut strategy = Breakout::new(daily_macd(dec!(0)), Resolution::Day, 3);
+
+        // 5 days rising, flat for a day, rising for a day, then falling for 5 days
+        let prices: Vec<_> = (0..5 * 24)
+            .map(|i| dec!(1000) + Decimal::from(i))
+            .chain((0..24).map(|_| dec!(1120)))
+            .chain((0..24).map(|i| dec!(1120) + Decimal::from(i)))
+            .chain((0..5 * 24).map(|i| dec!(1144) - Decimal::from(i * 3)))
+            .collect();
+        let trends = run(&mut strategy, &prices);
+
+        // in a position before the flat day
+        assert_eq!(trends[5 * 24 - 1], Trend::Bullish);
+        // held through the flat day
+        assert!(trends[5 * 24..6 * 24].iter().all(|t| *t == Trend::Bullish));
+        // filter trend turned as the price fell
+        assert_ne!(trends.last(), Some(&Trend::Bullish));
+    }
+
+    #[test]
+    fn filters_on_every_day_completed_after_a_gap() {
+        let mut strategy = Breakout::new(daily_macd(dec!(0)), Resolution::Day, 3);
+        let start = Utc.ymd(2021, 1, 4).and_hms(0, 0, 0);
+        let mut history = PriceHistory::new(Resolution::Hour(1));
+        let mut derived = vec![DerivedHistory::new(PriceHistory::new(Resolution::Day))];
+
+        // Every hour on Monday and Tuesday except the last, then the last hour of Wednesday
+        for hour in (1..2 * 24).chain(3 * 24..=3 * 24) {
+            let price = Price::new_mid(dec!(1000) + Decimal::from(hour), dec!(1));
+            let frame = Frame {
+                open: price,
+                close: price,
+                high: price,
+                low: price,
+                close_time: start + Duration::hours(hour),
+            };
+
+            history.push(frame);
+            derived[0].push(frame);
+            strategy.update(&Context::with_derived(&history, &derived));
+        }
+
+        // The last price completed both Tuesday and Wednesday
+        assert_eq!(strategy.filter.samples(), 3);
+    }
+
+    // Fixtures
+
+    fn daily_macd(limit: Decimal) -> MACD {
+        MACD::new(1, 2, 1, limit, limit)
+    }
+
+    // Feed hourly prices into the strategy, return the trend after each one
+    fn run(strategy: &mut Breakout, prices: &[Decimal]) -> Vec<Trend> {
+        let start = Utc.ymd(2021, 1, 4).and_hms(0, 0, 0);
+        let mut history = PriceHistory::new(Resolution::Hour(1));
+        let mut derived = vec![DerivedHistory::new(PriceHistory::new(Resolution::Day))];
+
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| {
+                let price = Price::new_mid(*price, dec!(1));
+                let frame = Frame {
+                    open: price,
+                    close: price,
+                    high: price,
+                    low: price,
+                    close_time: start + Duration::hours(i as i64 + 1),
+                };
+
+                history.push(frame);
+                derived[0].push(frame);
+
+                let context = Context::with_derived(&history, &derived);
+                strategy.update(&context);
+                strategy.trend(&context)
+            })
+            .collect()
+    }
+}
@@ -1,67 +1,58 @@
-use std::cmp::{max, min};
-
 use rust_decimal::Decimal;
 
-use crate::core::price::{Points, PriceHistory};
-use crate::core::strategy::{RiskStrategy, RiskStrategyError};
-use crate::core::trade::Direction;
+use crate::core::maths::{DonchianChannel, Indicator};
+use crate::core::price::{Points, Resolution};
+use crate::core::strategy::{Context, RiskStrategy, RiskStrategyError};
+use crate::core::trade::{Direction, Position};
 use crate::price::Frame;
 
 pub struct Donchian {
     pub channel_length: usize,
+    pub trailing: bool, // move the stop with the channel while the position is open
+    // Indicator state, kept between price updates
+    channel: DonchianChannel,
+    samples: usize,
 }
 
 impl Donchian {
+    pub fn new(channel_length: usize) -> Self {
+        Self {
+            channel_length,
+            trailing: false,
+            channel: DonchianChannel::new(channel_length),
+            samples: 0,
+        }
+    }
+
+    // Channel limits for every frame of the history, calculated from scratch
     pub fn channel(&self, history: &[Frame]) -> Vec<(Decimal, Decimal)> {
-        // minimum and maximum are order independent, so we use this as a primitive ring-buffer
-        let mut buffer = vec![(Decimal::MAX, Decimal::MIN); self.channel_length];
+        let mut channel = DonchianChannel::new(self.channel_length);
 
         history
             .iter()
-            .enumerate()
-            .map(|(idx, frame)| {
-                let buf_idx = idx % self.channel_length;
-
-                // The lower end of the channel is a bid price - we are selling to exit a long position that didn't go our way
-                // The higher end of the channel is an ask price - we are buying to exit a short position that didn't go our way
-                buffer[buf_idx] = (frame.low.bid, frame.high.ask);
-
-                (
-                    buffer
-                        .iter()
-                        .map(|c| c.0)
-                        .min()
-                        .expect("Couldn't calculate minimum"),
-                    buffer
-                        .iter()
-                        .map(|c| c.1)
-                        .max()
-                        .expect("Couldn't calculate maximum"),
-                )
+            .map(|frame| {
+                channel.push(*frame);
+                channel.value().expect("Couldn't calculate channel")
             })
             .collect()
     }
 }
 
 impl RiskStrategy for Donchian {
-    fn stop(
-        &self,
-        direction: Direction,
-        history: &PriceHistory,
-    ) -> Result<Points, RiskStrategyError> {
-        if history.history.len() < self.channel_length {
-            return Err(RiskStrategyError::NotEnoughHistory);
+    fn update(&mut self, context: &Context) {
+        if let Some(frame) = context.history.latest() {
+            self.channel.push(*frame);
+            self.samples += 1;
         }
+    }
 
+    fn stop(&self, direction: Direction, _context: &Context) -> Result<Points, RiskStrategyError> {
         // The lower end of the channel is a bid price - we are selling to exit a long position that didn't go our way
         // The higher end of the channel is an ask price - we are buying to exit a short position that didn't go our way
-        let channel_limits = (&history.history)
-            .into_iter()
-            .take(self.channel_length)
-            .fold(
-                (history.history[0].low.bid, history.history[0].high.ask),
-                |limits, frame| (min(limits.0, frame.low.bid), max(limits.1, frame.high.ask)),
-            );
+        let channel_limits = match self.channel.value() {
+            Some(limits) if self.samples >= self.channel_length => limits,
+            _ => return Err(RiskStrategyError::NotEnoughHistory),
+        };
 
         let stop = match direction {
             Direction::Buy => channel_limits.0,
@@ -70,6 +61,23 @@ impl RiskStrategy for Donchian {
 
         Ok(stop)
     }
+
+    fn trailing_stop(&self, position: &Position, context: &Context) -> Option<Points> {
+        if !self.trailing {
+            return None;
+        }
+
+        self.stop(position.entry.direction, context).ok()
+    }
+
+    fn history_length(&self) -> Option<usize> {
+        // all the history needed is in the indicator state
+        Some(1)
+    }
+
+    fn warm_up_length(&self, _resolution: Resolution) -> Option<usize> {
+        Some(self.channel_length)
+    }
 }
 
 #[cfg(test)]
@@ -83,7 +91,7 @@ mod test {
 
     use super::*;
     use crate::core::price::{CurrencyAmount, Frame, Price, PriceHistory, Resolution};
-    use crate::core::trade::Entry;
+    use crate::core::trade::{Entry, StopType};
 
     // RiskStrategy
 
@@ -98,12 +106,10 @@ mod test {
             10,
         );
 
-        let strategy = Donchian { channel_length: 1 };
-
-        let frames: Vec<Frame> = history.history.into();
+        let strategy = Donchian::new(1);
 
         let expected = vec![(dec!(599), dec!(1001)); 10];
-        let actual = strategy.channel(&frames);
+        let actual = strategy.channel(history.frames());
 
         assert_eq!(actual, expected);
     }
@@ -111,7 +117,7 @@ mod test {
     #[test]
     fn rejects_entry_without_enough_history() {
         let balance = CurrencyAmount::new(dec!(1020), Currency::GBP);
-        let rs = Donchian { channel_length: 4 };
+        let mut rs = Donchian::new(4);
         let history = oscilating_history(
             dec!(600),
             dec!(1000),
@@ -120,9 +126,10 @@ mod test {
             Resolution::Minute(10),
             3,
         );
+        feed(&mut rs, &history);
 
         assert_eq!(
-            rs.entry(Direction::Buy, &history, balance),
+            rs.entry(Direction::Buy, &Context::new(&history), balance),
             Err(RiskStrategyError::NotEnoughHistory)
         );
     }
@@ -130,40 +137,41 @@ mod test {
     #[test]
     fn sets_stop_based_on_recent_history() {
         let risk = CurrencyAmount::new(dec!(10), Currency::GBP);
-        let short_rs = Donchian { channel_length: 2 };
+        let mut short_rs = Donchian::new(2);
 
-        let long_rs = Donchian { channel_length: 8 };
+        let mut long_rs = Donchian::new(8);
 
-        let mut h = oscilating_history(
-            dec!(600),
-            dec!(1000),
-            dec!(2),
-            Utc.ymd(2021, 1, 1).and_hms(12, 50, 0),
-            Resolution::Minute(10),
-            5,
-        )
-        .history;
-        let mut h2 = oscilating_history(
+        let older = oscilating_history(
             dec!(200),
             dec!(2000),
             dec!(2),
             Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
             Resolution::Minute(10),
             5,
-        )
-        .history;
-        h.append(&mut h2);
+        );
+        let recent = oscilating_history(
+            dec!(600),
+            dec!(1000),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 50, 0),
+            Resolution::Minute(10),
+            5,
+        );
 
-        let history = PriceHistory {
-            resolution: Resolution::Minute(10),
-            history: h,
-        };
+        let history = PriceHistory::from_frames(
+            Resolution::Minute(10),
+            [older.frames(), recent.frames()].concat(),
+        );
+        feed(&mut short_rs, &history);
+        feed(&mut long_rs, &history);
+        let context = Context::new(&history);
 
         let short_expected_buy = Ok(Entry {
             position_id: String::new(),
             direction: Direction::Buy,
             price: dec!(701.0),
             stop: dec!(599.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.098039), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
         });
@@ -172,6 +180,7 @@ mod test {
             direction: Direction::Sell,
             price: dec!(699.0),
             stop: dec!(1001.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.033113), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
         });
@@ -181,6 +190,7 @@ mod test {
             direction: Direction::Buy,
             price: dec!(701.0),
             stop: dec!(199.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.019920), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
         });
@@ -189,31 +199,83 @@ mod test {
             direction: Direction::Sell,
             price: dec!(699.0),
             stop: dec!(2001.0),
+            stop_type: StopType::Normal,
             size: CurrencyAmount::new(dec!(0.007680), Currency::GBP),
             time: Utc.ymd(2021, 1, 1).and_hms(13, 40, 0),
         });
 
         assert_eq!(
-            short_rs.entry(Direction::Buy, &history, risk),
+            short_rs.entry(Direction::Buy, &context, risk),
             short_expected_buy
         );
         assert_eq!(
-            short_rs.entry(Direction::Sell, &history, risk),
+            short_rs.entry(Direction::Sell, &context, risk),
             short_expected_sell
         );
 
         assert_eq!(
-            long_rs.entry(Direction::Buy, &history, risk),
+            long_rs.entry(Direction::Buy, &context, risk),
             long_expected_buy
         );
         assert_eq!(
-            long_rs.entry(Direction::Sell, &history, risk),
+            long_rs.entry(Direction::Sell, &context, risk),
             long_expected_sell
         );
     }
 
+    #[test]
+    fn trails_stop_with_the_channel() {
+        let mut rs = Donchian::new(2);
+        let mut trailing_rs = Donchian::new(2);
+        trailing_rs.trailing = true;
+
+        let history = oscilating_history(
+            dec!(600),
+            dec!(1000),
+            dec!(2),
+            Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+            Resolution::Minute(10),
+            5,
+        );
+        feed(&mut rs, &history);
+        feed(&mut trailing_rs, &history);
+        let context = Context::new(&history);
+
+        let long = Position::new(Entry {
+            position_id: "1".to_string(),
+            direction: Direction::Buy,
+            price: dec!(700),
+            stop: dec!(500),
+            stop_type: StopType::Normal,
+            size: CurrencyAmount::new(dec!(1), Currency::GBP),
+            time: Utc.ymd(2021, 1, 1).and_hms(12, 0, 0),
+        });
+        let short = Position::new(Entry {
+            direction: Direction::Sell,
+            stop: dec!(1100),
+            ..long.entry.clone()
+        });
+
+        assert_eq!(rs.trailing_stop(&long, &context), None);
+        assert_eq!(trailing_rs.trailing_stop(&long, &context), Some(dec!(599)));
+        assert_eq!(
+            trailing_rs.trailing_stop(&short, &context),
+            Some(dec!(1001))
+        );
+    }
+
     // Fixtures
 
+    // Replay the history into the strategy one frame at a time
+    fn feed(strategy: &mut Donchian, history: &PriceHistory) {
+        let mut replay = PriceHistory::new(history.resolution);
+
+        for frame in history {
+            replay.push(*frame);
+            strategy.update(&Context::new(&replay));
+        }
+    }
+
     // History that jumps between two prices starting up
     fn oscilating_history(
         min_level: Decimal,
@@ -246,7 +308,7 @@ mod test {
         ];
         let timeline = iter::successors(Some(start_time + resolution), |t| Some(*t + resolution));
 
-        let mut history: Vec<Frame> = std::iter::repeat(cycle)
+        let history: Vec<Frame> = std::iter::repeat(cycle)
             .flatten()
             .zip(timeline)
             .map(|(frame, time)| Frame {
@@ -256,11 +318,6 @@ mod test {
             .take(length)
             .collect();
 
-        history.reverse();
-
-        PriceHistory {
-            resolution,
-            history: history.into(),
-        }
+        PriceHistory::from_frames(resolution, history)
     }
 }
@@ -1,10 +1,9 @@
-use rust_decimal::prelude::ToPrimitive;
-use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 
-use crate::core::maths::EMAIterator;
-use crate::core::price::PriceHistory;
-use crate::core::strategy::TradingStrategy;
+use crate::core::maths::{Indicator, MACDIndicator, MACDLines, EMA};
+use crate::core::price::Resolution;
+use crate::core::strategy::{Context, TradingStrategy};
 use crate::price::Frame;
 use crate::strategy::Trend;
 
@@ -18,15 +17,10 @@ pub struct MACD {
     pub signal: usize,
     pub entry_lim: Decimal, // enter above this value
     pub exit_lim: Decimal,  // exit below this value
-}
-
-#[derive(Debug)]
-struct Indicators {
-    short_ema: Decimal,
-    long_ema: Decimal,
-    macd: Decimal,
-    macd_signal: Decimal,
-    macd_trend: Decimal,
+    // Indicator state, kept between price updates
+    indicator: MACDIndicator,
+    samples: usize,
+    trend: Trend,
 }
 
 pub struct MACDValue {
@@ -39,60 +33,83 @@ pub struct MACDValue {
 }
 
 impl MACD {
-    pub fn macd(&self, history: &[Frame]) -> Vec<MACDValue> {
-        let points = history.into_iter().map(|it| it.close.mid_price());
-
-        let short_ema = points.clone().into_iter().ema(self.short);
-        let long_ema = points.clone().into_iter().ema(self.long);
-        let macd = short_ema
-            .clone()
-            .zip(long_ema.clone())
-            .map(|(s, l)| s - l)
-            .clone();
-        let macd_sig = macd.clone().ema(self.signal);
-        let macd_trend = macd.clone().zip(macd_sig.clone()).map(|(m, s)| m - s);
-
-        let all = short_ema
-            .zip(long_ema)
-            .zip(macd)
-            .zip(macd_sig)
-            .zip(macd_trend)
-            .map(|((((s, l), m), ms), mt)| Indicators {
-                short_ema: s,
-                long_ema: l,
-                macd: m,
-                macd_signal: ms,
-                macd_trend: mt,
-            });
+    pub fn new(
+        short: usize,
+        long: usize,
+        signal: usize,
+        entry_lim: Decimal,
+        exit_lim: Decimal,
+    ) -> Self {
+        Self {
+            short,
+            long,
+            signal,
+            entry_lim,
+            exit_lim,
+            indicator: MACDIndicator::new(short, long, signal),
+            samples: 0,
+            trend: Trend::Neutral,
+        }
+    }
 
+    // Indicator values for every frame of the history, calculated from scratch
+    pub fn macd(&self, history: &[Frame]) -> Vec<MACDValue> {
+        let mut indicator = MACDIndicator::new(self.short, self.long, self.signal);
         let mut output: Vec<MACDValue> = Vec::with_capacity(history.len());
 
-        for indicators in all {
+        for frame in history {
+            indicator.push(*frame);
+            let lines = indicator.value().expect("MACD should have a value");
+
             let trend = if let Some(last) = output.last() {
                 // Note we're not worried about having enough history in here,
                 // this is the raw indicators, the TradingStrategy implementation
                 // further down is used for actual decision making
-                Self::trend(last.trend, &indicators, self.entry_lim, self.exit_lim)
+                Self::trend(last.trend, &lines, self.entry_lim, self.exit_lim)
             } else {
                 Trend::Neutral
             };
 
-            let value = MACDValue {
-                short_ema: indicators.short_ema,
-                long_ema: indicators.long_ema,
-                macd: indicators.macd,
-                macd_signal: indicators.macd_signal,
-                macd_trend: indicators.macd_trend,
+            output.push(MACDValue {
+                short_ema: lines.short_ema,
+                long_ema: lines.long_ema,
+                macd: lines.macd,
+                macd_signal: lines.macd_signal,
+                macd_trend: lines.macd_trend,
                 trend,
-            };
-
-            output.push(value);
+            });
         }
 
         output
     }
 
-    fn trend(trend: Trend, iv: &Indicators, entry_lim: Decimal, exit_lim: Decimal) -> Trend {
+    // Update the indicators with the next frame
+    pub fn push(&mut self, frame: Frame) {
+        self.indicator.push(frame);
+        self.samples += 1;
+
+        if self.samples > 1 {
+            let lines = self.indicator.value().expect("MACD should have a value");
+            self.trend = Self::trend(self.trend, &lines, self.entry_lim, self.exit_lim);
+        }
+    }
+
+    // Frames pushed so far
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    // Trend based on the frames pushed so far
+    pub fn current_trend(&self) -> Trend {
+        if self.samples_taken() > self.samples {
+            // not enough history to make safe judgement
+            return Trend::Neutral;
+        }
+
+        self.trend
+    }
+
+    fn trend(trend: Trend, iv: &MACDLines, entry_lim: Decimal, exit_lim: Decimal) -> Trend {
         match trend {
             // TODO these rules need more work
             Trend::Bearish | Trend::Neutral if iv.macd > entry_lim => Trend::Bullish,
@@ -103,37 +120,36 @@ impl MACD {
         }
     }
 
+    // Samples of history needed before the trend can be trusted
+    fn samples_taken(&self) -> usize {
+        let length = *[self.short, self.long, self.signal].iter().max().unwrap();
+
+        Self::samples_needed(length, EMA_ERROR) + 1 // need at least 2 valid samples
+    }
+
     pub fn samples_needed(length: usize, error: Decimal) -> usize {
-        let alpha = dec!(2.0) / Decimal::from(length + 1);
-        (error.ln() / -alpha).round().to_isize().unwrap() as usize
+        EMA::samples_needed(length, error)
     }
 }
 
 impl TradingStrategy for MACD {
-    fn trend(&self, history: &PriceHistory) -> Trend {
-        let length = [self.short, self.long, self.signal]
-            .iter()
-            .max()
-            .unwrap()
-            .clone();
-        let take = Self::samples_needed(length, EMA_ERROR) + 1; // need at least 2 valid samples
-
-        if take > history.history.len() {
-            // not enough history to make safe judgement
-            return Trend::Neutral;
+    fn update(&mut self, context: &Context) {
+        if let Some(frame) = context.history.latest() {
+            self.push(*frame);
         }
+    }
 
-        let price: Vec<Frame> = history
-            .history
-            .iter()
-            .take(take + 1) // only need this much history for signal
-            .rev()
-            .cloned()
-            .collect();
+    fn trend(&self, _context: &Context) -> Trend {
+        self.current_trend()
+    }
 
-        let macd = self.macd(&price);
+    fn history_length(&self) -> Option<usize> {
+        // all the history needed is in the indicator state
+        Some(1)
+    }
 
-        macd.last().unwrap().trend
+    fn warm_up_length(&self, _resolution: Resolution) -> Option<usize> {
+        Some(self.samples_taken())
     }
 }
 
@@ -141,6 +157,10 @@ impl TradingStrategy for MACD {
 mod tests {
     use super::*;
 
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::core::price::{Price, PriceHistory, Resolution};
+
     #[test]
     fn calculates_samples_needed() {
         let actual = MACD::samples_needed(40, dec!(0.1));
@@ -148,4 +168,50 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn streaming_trend_matches_calculated_indicators() {
+        let mut strategy = MACD::new(3, 8, 3, dec!(2), dec!(1));
+        let mut history = PriceHistory::new(Resolution::Day);
+
+        let frames: Vec<_> = (0..60).map(|i| frame(i, wave(i))).collect();
+        let expected = strategy.macd(&frames);
+        let warm_up = strategy.samples_taken();
+
+        for (idx, frame) in frames.iter().enumerate() {
+            history.push(*frame);
+            strategy.update(&Context::new(&history));
+
+            let actual = strategy.trend(&Context::new(&history));
+
+            if idx + 1 < warm_up {
+                assert_eq!(actual, Trend::Neutral);
+            } else {
+                assert_eq!(actual, expected[idx].trend);
+            }
+        }
+
+        // the wave is large enough to generate both signals
+        assert!(expected.iter().any(|v| v.trend == Trend::Bullish));
+        assert!(expected.iter().any(|v| v.trend == Trend::Bearish));
+    }
+
+    // Fixtures
+
+    // A triangle wave between 100 and 200 with period of 20
+    fn wave(i: i64) -> Decimal {
+        Decimal::from(100 + (i % 20 - 10).abs() * 10)
+    }
+
+    fn frame(i: i64, price: Decimal) -> Frame {
+        let price = Price::new_mid(price, dec!(2));
+
+        Frame {
+            open: price,
+            close: price,
+            high: price,
+            low: price,
+            close_time: Utc.ymd(2021, 1, 1).and_hms(20, 0, 0) + Duration::days(i),
+        }
+    }
 }
@@ -1,5 +1,11 @@
+mod atr;
+mod breakeven;
+mod breakout;
 mod donchian;
 mod macd;
 
+pub use atr::*;
+pub use breakeven::*;
+pub use breakout::*;
 pub use donchian::*;
 pub use macd::*;
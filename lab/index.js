@@ -34,10 +34,11 @@ const lab = async () => {
   };
 
   const priceData = await getData();
-  const { indicators, trades } = run_test(priceData, opts);
+  const { indicators, trades, equity } = run_test(priceData, opts);
 
   const data = priceData.map((d, i) => ({ ...d, ...indicators[i] }));
   data.trades = trades;
+  data.equity = equity.map((e) => ({ ...e, date: new Date(e.date) }));
 
   console.log("Data for charts", data);
 
@@ -201,9 +202,59 @@ const lab = async () => {
       sel.enter().selectAll("path").attr("stroke", foreground);
     });
 
-  // Drawing function, to update the chart
+  // Equity and drawdown pane, sharing the time axis with the price chart
+
+  const equityLine = fc
+    .seriesSvgLine()
+    .mainValue((d) => d.equity)
+    .crossValue((d) => d.date)
+    .decorate((sel) => sel.enter().attr("stroke", foreground));
+
+  const balanceLine = fc
+    .seriesSvgLine()
+    .mainValue((d) => d.balance)
+    .crossValue((d) => d.date)
+    .decorate((sel) =>
+      sel.enter().attr("stroke", foreground).style("opacity", 0.4)
+    );
+
+  const drawdownArea = fc
+    .seriesSvgArea()
+    .mainValue((d) => d.equity)
+    .baseValue((d) => d.equity + d.drawdown)
+    .crossValue((d) => d.date)
+    .decorate((sel) => sel.enter().attr("fill", red).style("opacity", 0.3));
+
+  const equityExtent = fc
+    .extentLinear()
+    .pad([0.1, 0.1])
+    .accessors([(d) => d.equity, (d) => d.equity + d.drawdown, (d) => d.balance]);
+
+  const equityYScale = d3.scaleLinear().domain(equityExtent(data.equity));
+
+  const equityChart = fc
+    .chartCartesian(xScale, equityYScale)
+    .svgPlotArea(
+      fc
+        .seriesSvgMulti()
+        .series([gridlines, drawdownArea, balanceLine, equityLine])
+    )
+    .decorate((sel) => {
+      sel.enter().selectAll(".plot-area").call(zoom, xScale, null);
+    })
+    .xDecorate((sel) => {
+      sel.enter().selectAll("text").attr("fill", foreground);
+      sel.enter().selectAll("path").attr("stroke", foreground);
+    })
+    .yDecorate((sel) => {
+      sel.enter().selectAll("text").attr("fill", foreground);
+      sel.enter().selectAll("path").attr("stroke", foreground);
+    });
+
+  // Drawing function, to update the charts
   function render() {
     d3.select("#chart").datum(data).call(chart);
+    d3.select("#equity").datum(data.equity).call(equityChart);
   }
 
   // first render
@@ -63,15 +63,28 @@ struct Trade {
     open_date: DateTime<Utc>,
     open_price: Decimal,
     stop: Decimal,
+    last_stop: Decimal,
     close_date: Option<DateTime<Utc>>,
     close_price: Option<Decimal>,
     outcome: String,
 }
 
+#[derive(Serialize, Debug)]
+struct EquityRecord {
+    date: DateTime<Utc>,
+    balance: Decimal,
+    open_pnl: Decimal,
+    margin_used: Decimal,
+    free_margin: Decimal,
+    equity: Decimal,
+    drawdown: Decimal, // below the highest equity so far
+}
+
 #[derive(Serialize, Debug)]
 struct TestResult {
     indicators: Vec<StrategyRecord>,
     trades: Vec<Trade>,
+    equity: Vec<EquityRecord>,
 }
 
 #[wasm_bindgen]
@@ -102,22 +115,16 @@ pub fn run_test(prices: JsValue, parameters: JsValue) -> JsValue {
         .expect("Expected at least one price frame")
         .close;
 
-    let ts = MACD {
-        short: opts.short,
-        long: opts.long,
-        signal: opts.signal,
-        entry_lim: opts.entry,
-        exit_lim: opts.exit,
-    };
-    let rs = Donchian {
-        channel_length: opts.channel,
-    };
+    let ts = MACD::new(opts.short, opts.long, opts.signal, opts.entry, opts.exit);
+    let rs = Donchian::new(opts.channel);
 
     let market = Market {
         code: "GDAXI".to_string(),
         margin_factor: dec!(0.05),
         min_deal_size: CurrencyAmount::new(dec!(0.50), Currency::GBP),
         min_stop_distance: dec!(12),
+        guaranteed_stop: None,
+        funding: None,
     };
 
     let account = Account::new(
@@ -157,13 +164,38 @@ pub fn run_test(prices: JsValue, parameters: JsValue) -> JsValue {
             open_date: t.entry_time,
             open_price: t.entry_price,
             stop: t.stop,
+            last_stop: t.last_stop(),
             close_date: t.exit_time,
             close_price: t.exit_price,
             outcome: format!("{}", t.outcome),
         })
         .collect();
 
-    let result = TestResult { indicators, trades };
+    let mut peak = Decimal::MIN;
+    let equity = test
+        .equity
+        .iter()
+        .map(|e| {
+            let value = e.value().amount;
+            peak = peak.max(value);
+
+            EquityRecord {
+                date: e.time,
+                balance: e.balance.amount,
+                open_pnl: e.open_pnl.amount,
+                margin_used: e.margin_used.amount,
+                free_margin: e.free_margin.amount,
+                equity: value,
+                drawdown: peak - value,
+            }
+        })
+        .collect();
+
+    let result = TestResult {
+        indicators,
+        trades,
+        equity,
+    };
 
     JsValue::from_serde(&result).unwrap()
 }